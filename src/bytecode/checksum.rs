@@ -0,0 +1,176 @@
+//! Checksums for ensuring the integrity of Revision 1 bytecode files.
+//!
+//! A Revision 1 file ends with a checksum section, holding the CRC-32 of
+//! every byte in the file before that section. The checksum is verified
+//! before anything in the file is decoded, so that a file which has been
+//! truncated or corrupted is rejected, rather than decoded as a different
+//! program.
+
+use super::byteorder::{BigEndian, ByteOrder};
+
+use std::io::{self, Write};
+
+use super::{SECTION_CHECKSUM, PREAMBLE_LEN};
+
+/// The length of a checksum section, including its header.
+pub const CHECKSUM_SECTION_LEN: usize = 1 + 8 + 4;
+
+/// The prefix of every error returned when a file fails its integrity
+/// check, which distinguishes them from other decoding errors.
+#[cfg_attr(feature = "nightly", unstable(feature="module"))]
+pub const INTEGRITY_ERROR: &'static str = "Integrity check failed";
+
+/// Computes the CRC-32 (as used by zlib and PNG) of some bytes.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !update(!0, bytes)
+}
+
+/// Updates a CRC-32 which is being computed a piece at a time.
+fn update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Encodes a checksum section for the bytes of a file written so far.
+pub fn emit_checksum(bytes: &[u8]) -> Vec<u8> {
+    checksum_section(crc32(bytes))
+}
+
+/// Encodes a checksum section holding the given checksum.
+pub fn checksum_section(checksum: u32) -> Vec<u8> {
+    let mut result = vec![0; CHECKSUM_SECTION_LEN];
+    result[0] = SECTION_CHECKSUM;
+    BigEndian::write_u64(&mut result[1..], 4);
+    BigEndian::write_u32(&mut result[9..], checksum);
+    result
+}
+
+/// Computes the checksum of everything written through it, for files
+/// which are written as they are encoded.
+pub struct ChecksumWriter<'a, W: 'a> {
+    inner: &'a mut W,
+    crc: u32
+}
+
+impl<'a, W> ChecksumWriter<'a, W> where W: Write {
+
+    pub fn new(inner: &'a mut W) -> ChecksumWriter<'a, W> {
+        ChecksumWriter { inner: inner, crc: !0 }
+    }
+
+    /// Returns the checksum of everything written so far.
+    pub fn checksum(&self) -> u32 {
+        !self.crc
+    }
+
+    pub fn into_inner(self) -> &'a mut W {
+        self.inner
+    }
+}
+
+impl<'a, W> Write for ChecksumWriter<'a, W> where W: Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = try!(self.inner.write(buf));
+        self.crc = update(self.crc, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A section found by `sections()`: its type, and the offsets of its
+/// header, its payload and its end.
+pub struct Section {
+    pub section: u8,
+    pub start: usize,
+    pub payload: usize,
+    pub end: usize
+}
+
+/// Finds the sections of a complete Revision 1 file, using only the
+/// length of each section, so that nothing is decoded.
+pub fn sections(bytes: &[u8]) -> Result<Vec<Section>, String> {
+    if bytes.len() < PREAMBLE_LEN + 2 {
+        return Err(format!("{}: file is truncated", INTEGRITY_ERROR));
+    }
+    let count = BigEndian::read_u16(&bytes[PREAMBLE_LEN..]);
+    let mut offset = PREAMBLE_LEN + 2;
+    let mut result = Vec::new();
+    for _ in 0..count {
+        if bytes.len() < offset + 9 {
+            return Err(format!("{}: file is truncated", INTEGRITY_ERROR));
+        }
+        let len = BigEndian::read_u64(&bytes[offset + 1..]);
+        let end = (offset as u64 + 9).saturating_add(len);
+        if end > bytes.len() as u64 {
+            return Err(format!("{}: file is truncated", INTEGRITY_ERROR));
+        }
+        result.push(Section {
+            section: bytes[offset],
+            start: offset,
+            payload: offset + 9,
+            end: end as usize
+        });
+        offset = end as usize;
+    }
+    if offset < bytes.len() {
+        return Err(format!("{}: {} unexpected bytes after checksum",
+            INTEGRITY_ERROR, bytes.len() - offset));
+    }
+    Ok(result)
+}
+
+/// Checks the integrity of a complete Revision 1 file, before it is
+/// decoded.
+pub fn verify(bytes: &[u8]) -> Result<(), String> {
+    let sections = try!(sections(bytes));
+    match sections.iter().position(|it| it.section == SECTION_CHECKSUM) {
+        Some(i) if i == sections.len() - 1 && sections[i].end - sections[i].payload == 4 => {
+            let checksum = &sections[i];
+            let expected = BigEndian::read_u32(&bytes[checksum.payload..]);
+            let actual = crc32(&bytes[..checksum.start]);
+            if expected == actual {
+                Ok(())
+            } else {
+                Err(format!("{}: checksum is {:#010x}, but contents have checksum {:#010x}",
+                    INTEGRITY_ERROR, expected, actual))
+            }
+        },
+        Some(_) => Err(format!(
+            "{}: checksum must be the last section, and 4 bytes long", INTEGRITY_ERROR)),
+        None => Err(format!("{}: missing checksum", INTEGRITY_ERROR))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32,verify,emit_checksum};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414FA339);
+    }
+
+    #[test]
+    fn test_verify_empty_file() {
+        let mut bytes = vec![0x5e, 0xcd, 0x00, 0x01, 0x00, 0x01];
+        let checksum = emit_checksum(&bytes);
+        bytes.extend(checksum);
+        assert_eq!(verify(&bytes), Ok(()));
+        bytes[3] = 0x02;
+        assert!(verify(&bytes).unwrap_err().contains("contents have checksum"));
+    }
+}
@@ -6,7 +6,7 @@
 //! Seax Bytecode Format
 //! ====================
 //!
-//! Seax Bytecode Standard Revision 0, June 11th, 2015
+//! Seax Bytecode Standard Revision 1
 //!
 //! I: The preamble
 //! ---------------
@@ -17,18 +17,22 @@
 //!    spell out the abbreviation SECD in hexadecimal, identify the file as a Seax bytecode file.
 //! 2. A 16-bit unsigned integer that represents the version of the Seax bytecode format that the
 //!    file was encoded with. This number is used to determine how the remainder of the file should
-//!    be decoded. This document is Revision 0 of the Seax Bytecode format, so the version
-//!    should be 0x0000.
+//!    be decoded. This document is Revision 1 of the Seax Bytecode format, so the version
+//!    should be 0x0001.
 //!
-//! Future revisions of this standard will allow additional metadata, such as the author's
-//! cryptographic signature, checksums for ensuring the executable's integrity, and directives to
-//! the virtual machine, to be placed in the preamble as well.
+//! In a Revision 1 file, the preamble is followed by a table of sections (see below), which hold
+//! the program along with any metadata, such as its symbol table and the natives it imports.
+//!
+//! Revision 0 files, which have the version 0x0000, contain no section table: the preamble is
+//! followed directly by the cells of the program, which are read until the end of the file. A
+//! decoder for this revision accepts both Revision 0 and Revision 1 files, and rejects files
+//! encoded with any later revision, which it cannot be expected to understand.
 //!
 //! II: Instructions
 //! ----------------
 //!
 //! All Seax VM instructions are encoded using single byes. The Seax opcodes occupy the
-//! space 0x00 to 0x30, with the bytes 0x1D through 0x3F being reserved for future use.
+//! space 0x00 to 0xBF, with the bytes 0x5D through 0xBF being reserved for future use.
 //!
 //! The following table shows all of the currently available SVM opcodes.
 //!
@@ -66,9 +70,72 @@
 //!   0x1B  | CDR (a . b)   |
 //!   0x1C  | LDC           |
 //!   0x1D  | STOP          |
-//!   0x1E  | reserved      |
+//!   0x1E  | INTP a        | Pushes true if `a` is an integer atom, nil otherwise.
+//!   0x1F  | FLOATP a      | Pushes true if `a` is a float atom, nil otherwise.
+//!   0x20  | CHARP a       | Pushes true if `a` is a char atom, nil otherwise.
+//!   0x21  | PROCP a       | Pushes true if `a` is a closure, nil otherwise.
+//!   0x22  | PAIRP a       | Pushes true if `a` is a non-empty list, nil otherwise.
+//!   0x23  | TOINT a       | Converts the atom `a` to a signed integer.
+//!   0x24  | TOUINT a      | Converts the atom `a` to an unsigned integer.
+//!   0x25  | TOFLOAT a     | Converts the atom `a` to a float.
+//!   0x26  | TOCHAR a      | Converts the atom `a` to a char.
+//!   0x27  | SQRT a        | Pushes the square root of `a`.
+//!   0x28  | EXP a         | Pushes e raised to the power `a`.
+//!   0x29  | LOG a         | Pushes the natural logarithm of `a`.
+//!   0x2A  | SIN a         | Pushes the sine of `a` (in radians).
+//!   0x2B  | COS a         | Pushes the cosine of `a` (in radians).
+//!   0x2C  | TAN a         | Pushes the tangent of `a` (in radians).
+//!   0x2D  | ASIN a        | Pushes the arcsine of `a`.
+//!   0x2E  | ACOS a        | Pushes the arccosine of `a`.
+//!   0x2F  | ATAN a        | Pushes the arctangent of `a`.
+//!   0x30  | FLOOR a       | Pushes the largest integral float less than or equal to `a`.
+//!   0x31  | CEIL a        | Pushes the smallest integral float greater than or equal to `a`.
+//!   0x32  | ROUND a       | Pushes `a` rounded to the nearest integral float.
+//!   0x33  | NANP a        | Pushes true if `a` is NaN, nil otherwise.
+//!   0x34  | INFP a        | Pushes true if `a` is positive or negative infinity, nil otherwise.
+//!   0x35  | POW a b       | Pushes `a` raised to the power `b`.
+//!   0x36  | ATAN2 a b     | Pushes the four-quadrant arctangent of `a` (y) and `b` (x).
+//!   0x37  | VMAKE n a     | Pushes a vector of `n` copies of `a`.
+//!   0x38  | VGET v i      | Pushes the element of the vector `v` at index `i`.
+//!   0x39  | VSET v i a    | Pushes the vector `v` with the element at index `i` replaced by `a`.
+//!   0x3A  | VLEN v        | Pushes the length of the vector `v`.
+//!   0x3B  | VTOL v        | Pushes a list of the elements of the vector `v`.
+//!   0x3C  | LTOV l        | Pushes a vector of the elements of the list `l`.
+//!   0x3D  | BLEN b        | Pushes the length of the byte buffer `b`.
+//!   0x3E  | BGET b i      | Pushes the byte at index `i` of the byte buffer `b` as a uint.
+//!   0x3F  | BSLICE b i j  | Pushes the bytes of `b` from index `i` up to (not including) `j`.
+//!   0x40  | BCAT a b      | Pushes the concatenation of the byte buffers `a` and `b`.
+//!   0x41  | STOB s        | Pushes the UTF-8 encoding of the string (list of chars) `s`.
+//!   0x42  | BTOS b        | Pushes the string (list of chars) decoded from the UTF-8 bytes `b`.
+//!   0x43  | READB         | Reads a byte from the input stream and pushes it as a uint.
+//!   0x44  | WRITEB b      | Writes the byte buffer `b` to the output stream.
+//!   0x45  | MNEW          | Pushes an empty map.
+//!   0x46  | MPUT m k a    | Pushes the map `m` with the key `k` bound to `a`.
+//!   0x47  | MGET m k      | Pushes the value bound to the key `k` in the map `m`.
+//!   0x48  | MHAS m k      | Pushes true if the key `k` is bound in the map `m`, false otherwise.
+//!   0x49  | MDEL m k      | Pushes the map `m` with the key `k` removed.
+//!   0x4A  | MKEYS m       | Pushes a list of the keys of the map `m`, in order.
+//!   0x4B  | MLEN m        | Pushes the number of entries in the map `m`.
+//!   0x4C  | TRY h b       | Evaluates `b` with the exception handler `h` installed.
+//!   0x4D  | ENDTRY        | Removes the innermost exception handler and continues after its `TRY`.
+//!   0x4E  | THROW a       | Unwinds to the innermost exception handler, passing it `a`.
+//!   0x4F  | CALLN n l     | Calls the host native function `n` with the arguments in the list `l`.
+//!   0x50  | LDG g         | Pushes the value of the global `g`.
+//!   0x51  | STG g a       | Stores `a` in the global `g`, defining it if necessary.
+//!   0x52  | BOX a         | Pushes a new box containing `a`.
+//!   0x53  | UNBOX b       | Pushes the contents of the box `b`.
+//!   0x54  | SETBOX b a    | Replaces the contents of the box `b` with `a`.
+//!   0x55  | RETN n        | Returns the top `n` values of the stack, followed by their count.
+//!   0x56  | RECVN n       | Receives `n` values returned by `RETN`.
+//!   0x57  | SPAWN f v     | Starts a new task applying the closure `f` to the arguments `v`.
+//!   0x58  | YIELD         | Suspends the current task.
+//!   0x59  | WAIT t        | Waits for the task `t` to finish, and pushes its result.
+//!   0x5A  | CHAN          | Pushes a new, empty channel.
+//!   0x5B  | SEND ch a     | Sends `a` on the channel `ch`.
+//!   0x5C  | RECV ch       | Receives the next value sent on the channel `ch`.
+//!   0x5D  | reserved      |
 //!         |     ...       |
-//!   0x30  | reserved      |
+//!   0xBF  | reserved      |
 //!
 //! III: Constants
 //! --------------
@@ -103,7 +170,10 @@
 //!
 //!    Any constants that are not CONS cells are atom constants. Atom constants are identified by
 //!    bytes in the range between 0xC1 and 0xCF, inclusive. Currently, 0xC1, 0xC2, 0xC3, and 0xC4
-//!    identify extant atom types, while 0xC5 ... 0xCE are reserved for future use.
+//!    identify extant atom types, while 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xCB, 0xCC, 0xCD,
+//!    0xCE and 0xCF identify vector, byte buffer, map, native function, global, box, channel, box
+//!    reference, empty list, constant pool reference and channel reference constants (see
+//!    below).
 //!
 //!    Once an atom constant identifying byte is read, the bytes that follow it will be read as
 //!    that type of atom. The number of bytes read depends on the length of the atom type, which is
@@ -115,29 +185,195 @@
 //! + 0xC3: char atom (32-bit Unicode scalar value)
 //! + 0xC4: float atom (64-bit double-precision floating point number
 //!
-//!    If additional primitive data types are added to the Seax VM, the bytes 0xC5 to 0xCF will
-//!    be used to identify those types.
-//!
 //!    Note that the type tag identifying a constant may be extracted by byte-masking the
 //!    identifying byte with the number 0x0F.
 //!
+//! 3. Vector constants (0xC5)
+//!
+//!    0xC5 identifies the beginning of a vector constant. It is followed by a 64-bit unsigned
+//!    integer giving the number of elements in the vector, and then by that many cells. Each
+//!    element may be any constant (including another vector) or an instruction, and is encoded
+//!    exactly as it would be elsewhere.
+//!
+//! 4. Byte buffer constants (0xC6)
+//!
+//!    0xC6 identifies the beginning of a byte buffer constant. It is followed by a 64-bit
+//!    unsigned integer giving the length of the buffer in bytes, and then by the contents of
+//!    the buffer.
+//!
+//! 5. Map constants (0xC7)
+//!
+//!    0xC7 identifies the beginning of a map constant. It is followed by a 64-bit unsigned
+//!    integer giving the number of entries in the map, and then by that many pairs of cells.
+//!    The first cell of each pair is the key, which must be an atom or a string (a list of
+//!    chars), and the second is the value bound to it. Entries are encoded in key order.
+//!
+//! 6. Native function references (0xC8)
+//!
+//!    0xC8 identifies a reference to a native function bound by the host. It is followed by a
+//!    64-bit unsigned integer giving the length of the function's name in bytes, and then by
+//!    the name, encoded as UTF-8. Natives are referenced by name, rather than by the index at
+//!    which they were bound, so that programs may be loaded by any host which provides them.
+//!
+//! 7. Global references (0xC9)
+//!
+//!    0xC9 identifies a reference to a global definition, for use by the `LDG` and `STG`
+//!    instructions. Like a native function reference, it is followed by a 64-bit unsigned
+//!    integer giving the length of the global's name in bytes, and then by the name, encoded
+//!    as UTF-8.
+//!
+//! 8. Box constants (0xCA)
+//!
+//!    0xCA identifies a mutable box. It is followed by the cell the box contains, encoded as it
+//!    would be elsewhere. Boxes are numbered, so that each box is encoded once, and any further
+//!    copies of it, including any inside the box itself, are encoded as box references (see
+//!    below). A single cell encoded on its own numbers the boxes in it from zero, while the
+//!    sections of a file share one numbering.
+//!
+//! 9. Channel constants (0xCB)
+//!
+//!    0xCB identifies a channel. It is followed by a 64-bit unsigned integer giving the number of
+//!    values waiting to be received on the channel, and then by those values, in the order in
+//!    which they will be received. Channels are numbered like boxes, separately from them, with
+//!    further copies encoded as channel references. Each channel is decoded as a new channel,
+//!    which is not shared with the channel that was encoded.
+//!
+//! 10. Box references (0xCC)
+//!
+//!    0xCC identifies a reference to a box which has already been decoded. It is followed by a
+//!    64-bit unsigned integer giving the index of that box, counting from zero, in the order in
+//!    which the boxes (0xCA) in the file were decoded. The reference is decoded as a copy of that
+//!    box, sharing its contents.
+//!
+//! 11. Empty lists (0xCD)
+//!
+//!    0xCD identifies an empty list appearing as a cell, such as an empty environment or an
+//!    empty list of arguments. Since the NIL byte, 0x00, is also the opcode of the `NIL`
+//!    instruction, it only terminates lists of one or more cells. Revision 0 files only use this
+//!    byte in state snapshots, so that their programs may still be decoded by older decoders.
+//!
+//! 12. Constant pool references (0xCE)
+//!
+//!    0xCE identifies a reference to a constant in the file's constant pool (see below). It is
+//!    followed by a 64-bit unsigned integer giving the index of the constant in the pool, counting
+//!    from zero, and is decoded as a copy of that constant. Constant pool references may only
+//!    appear in Revision 1 files, after the constant pool section.
+//!
+//! 13. Channel references (0xCF)
+//!
+//!    0xCF identifies a reference to a channel which has already been decoded. It is followed by
+//!    a 64-bit unsigned integer giving the index of that channel, counting from zero, in the order
+//!    in which the channels (0xCB) in the file were decoded. The reference is decoded as a copy of
+//!    that channel, sharing its queue.
+//!
+//! IV: Sections
+//! ------------
+//!
+//! In a Revision 1 file, the preamble is followed by a 16-bit unsigned integer giving the number
+//! of sections in the file, and then by that many sections. Each section begins with a byte in the
+//! range between 0xD0 and 0xDF, inclusive, which identifies the section's type, followed by a
+//! 64-bit unsigned integer giving the length of the rest of the section in bytes. Each type of
+//! section other than custom sections may appear at most once.
+//!
+//! Sections share a single numbering of boxes: boxes are encoded once, with any further copies,
+//! in the same section or a later one, encoded as box references, so that boxes which are shared
+//! are still shared when they are decoded. Channels are numbered in the same way, separately from
+//! boxes. A section which refers to the constant pool must come
+//! after it. The following section types are defined:
+//!
+//! 1. State snapshots (0xD0)
+//!
+//!    0xD0 identifies a snapshot of the complete state of a running program, which may be used to
+//!    resume it later. It contains the four registers of the state -- the stack, the
+//!    environment, the control stack and the dump -- in that order, each encoded as a list. A
+//!    file containing a state snapshot contains no other sections, apart from its checksum.
+//!
+//! 2. Code (0xD1)
+//!
+//!    0xD1 identifies the program itself. It contains the cells of the program, which are read
+//!    until the end of the section.
+//!
+//! 3. Constant pool (0xD2)
+//!
+//!    0xD2 identifies the constant pool. It contains a 64-bit unsigned integer giving the number
+//!    of constants in the pool, followed by that many cells. When a program is encoded, any cell
+//!    in its code whose encoding is the same as that of a constant in the pool is encoded as a
+//!    reference to that constant. Constants which are, or hold, boxes or channels are never
+//!    referred to in this way, since a copy of one is not the same cell.
+//!
+//! 4. Symbol table (0xD3)
+//!
+//!    0xD3 identifies the program's symbol table, which holds the globals it defines. It contains
+//!    a 64-bit unsigned integer giving the number of symbols, followed by that many symbols, each
+//!    of which is a length-prefixed UTF-8 name, encoded as for global references, followed by the
+//!    cell bound to that name. Globals are defined before the program is run.
+//!
+//! 5. Debug information (0xD4)
+//!
+//!    0xD4 identifies debugging information, such as the name of the program's source file. It
+//!    is encoded in the same way as the symbol table, but is not used by the virtual machine.
+//!
+//! 6. Native imports (0xD5)
+//!
+//!    0xD5 identifies the native functions which the program calls. It contains a 64-bit unsigned
+//!    integer giving the number of imports, followed by that many length-prefixed UTF-8 names. A
+//!    program is not run unless the host has bound every native it imports.
+//!
+//! 7. Custom sections (0xDF)
+//!
+//!    0xDF identifies a custom section, for use by tools other than the virtual machine. It
+//!    contains a length-prefixed UTF-8 name identifying the section, followed by the section's
+//!    contents, which are read until the end of the section. Custom sections are ignored by the
+//!    virtual machine, but are preserved when a program is decoded and encoded again.
+//!
+//! 8. Checksum (0xD6)
+//!
+//!    0xD6 identifies the file's checksum, which must be the last section in every Revision 1
+//!    file. It contains a 32-bit unsigned integer, which is the CRC-32 (as used by zlib and PNG)
+//!    of every byte in the file before the checksum section, including the preamble and the
+//!    number of sections. A file is not decoded unless its checksum matches its contents.
+//!
+//! 9. Signature (0xD7)
+//!
+//!    0xD7 identifies the author's cryptographic signature. It contains the author's 32-byte
+//!    Ed25519 public key, followed by the 64-byte Ed25519 signature of every byte in the file
+//!    before the signature section, made with the author's private key. A signature must be the
+//!    last section before the checksum, so that no sections can be added to a file once it has
+//!    been signed. Signatures are optional, and are only checked by hosts which only run
+//!    programs signed by authors they trust.
+//!
+//!    The section types 0xD8 through 0xDE are reserved for future use.
+//!
 
 extern crate byteorder;
 
 use self::byteorder::{ByteOrder, BigEndian, ReadBytesExt, WriteBytesExt};
 
 use std::error::Error;
-use std::io::Read;
+use std::io::{self,Read,Write,Cursor,ErrorKind};
 use std::fmt;
 use std::char;
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::Arc;
 
 use super::slist::List;
 use super::slist::List::*;
-use super::{SVMCell,Atom,Inst};
+use super::{State,SVMCell,Atom,Inst,MapKey,SharedCell,Channel};
 use super::SVMCell::*;
 use super::Atom::*;
 use super::Inst::*;
 
+mod checksum;
+
+pub use self::checksum::INTEGRITY_ERROR;
+
+/// Signing bytecode files, and decoding only files signed by trusted
+/// authors.
+#[cfg(feature = "signing")]
+#[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+pub mod signing;
+
 #[cfg(test)]
 mod tests;
 
@@ -158,35 +394,368 @@ macro_rules! push_all {
 #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.3.0"))]
 pub const IDENT_BYTES: u16 = 0x5ECD;
 #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.3.0"))]
-pub const VERSION: u16     = 0x0000;
+pub const VERSION: u16     = 0x0001;
 
 /// block reserved for future opcodes
-const RESERVED_START: u8  = 0x1E;
-const RESERVED_LEN: u8    = 0x12;
+const RESERVED_START: u8  = 0x5D;
+const RESERVED_LEN: u8    = 0x62;
 /// block reserved for typetags
 const CONST_START: u8     = 0xC1;
 const CONST_LEN: u8       = 0x0E;
 /// important bytecodes
 const BYTE_CONS: u8       = 0xC0;
 const BYTE_NIL: u8        = 0x00;
+const BYTE_VECTOR: u8     = 0xC5;
+const BYTE_BYTES: u8      = 0xC6;
+const BYTE_MAP: u8        = 0xC7;
+const BYTE_NATIVE: u8     = 0xC8;
+const BYTE_GLOBAL: u8     = 0xC9;
+const BYTE_BOX: u8        = 0xCA;
+const BYTE_CHANNEL: u8    = 0xCB;
+const BYTE_BOX_REF: u8    = 0xCC;
+const BYTE_EMPTY: u8      = 0xCD;
+const BYTE_CONST_REF: u8  = 0xCE;
+const BYTE_CHANNEL_REF: u8 = 0xCF;
+/// section types
+const SECTION_STATE: u8     = 0xD0;
+const SECTION_CODE: u8      = 0xD1;
+const SECTION_CONSTANTS: u8 = 0xD2;
+const SECTION_SYMBOLS: u8   = 0xD3;
+const SECTION_DEBUG: u8     = 0xD4;
+const SECTION_IMPORTS: u8   = 0xD5;
+const SECTION_CHECKSUM: u8  = 0xD6;
+const SECTION_SIGNATURE: u8 = 0xD7;
+const SECTION_CUSTOM: u8    = 0xDF;
+/// the length of the preamble, in bytes
+const PREAMBLE_LEN: usize   = 4;
+
+/// A program, along with the metadata stored with it in a Revision 1
+/// bytecode file.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+pub struct Module {
+    /// The program itself.
+    #[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+    pub code: List<SVMCell>,
+    /// Constants which cells in the program may be encoded as references
+    /// to, rather than in full.
+    #[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+    pub constants: Vec<SVMCell>,
+    /// The globals defined by the program, in order.
+    #[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+    pub symbols: Vec<(String, SVMCell)>,
+    /// Debugging information, which is not used by the machine.
+    #[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+    pub debug: Vec<(String, SVMCell)>,
+    /// The names of the natives the program calls.
+    #[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+    pub imports: Vec<String>,
+    /// Custom sections, by name, which are ignored by the machine.
+    #[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+    pub custom: Vec<(String, Vec<u8>)>
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+impl Module {
+    /// Creates a module containing a program and no metadata.
+    #[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+    pub fn new(code: List<SVMCell>) -> Module {
+        Module {
+            code: code,
+            constants: Vec::new(),
+            symbols: Vec::new(),
+            debug: Vec::new(),
+            imports: Vec::new(),
+            custom: Vec::new()
+        }
+    }
+}
 
+/// Decodes a program from a Revision 0 or Revision 1 bytecode file,
+/// discarding any metadata stored with it.
+///
+/// If a cell in the program cannot be decoded, the error gives its offset
+/// in the file, the byte found there, and what was expected instead.
 #[cfg_attr(feature = "nightly", unstable(feature = "decode"))]
 pub fn decode_program<R>(source: &mut R) -> Result<List<SVMCell>, String>
     where R: Read
 {
-    let mut decoder = Decoder::new(source);
-    decoder
-        .check_ident_bytes()
-        .map(|_| decoder.check_version()
-                        .map_err(|why| warn!("{}", why) )
-            )
-        .map(|_| decoder.collect::<List<SVMCell>>() )
+    decode_module(source).map(|module| module.code)
+}
+
+/// Decodes a program and its metadata from a Revision 0 or Revision 1
+/// bytecode file.
+///
+/// A Revision 0 file is decoded as a module with no metadata. The whole
+/// of a Revision 1 file is read and its checksum verified before it is
+/// decoded; if it has been truncated or corrupted, an error beginning
+/// with `INTEGRITY_ERROR` is returned.
+#[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+pub fn decode_module<R>(source: &mut R) -> Result<Module, String>
+    where R: Read
+{
+    let bytes = try!(read_file(source));
+    let mut cursor = Cursor::new(&bytes[..]);
+    let mut decoder = Decoder::new(&mut cursor);
+    try!(decoder.check_ident_bytes());
+    try!(decoder.check_version());
+    if decoder.version == 0 {
+        return decoder.collect::<Result<List<SVMCell>, DecodeError>>()
+                      .map(Module::new)
+                      .map_err(String::from);
+    }
+    try!(checksum::verify(&bytes));
+    let mut module = Module::new(Nil);
+    let mut seen = Vec::new();
+    for _ in 0..try!(decoder.read_u16("the number of sections")) {
+        let (section, len) = try!(decoder.read_section_header());
+        if section != SECTION_CUSTOM && seen.contains(&section) {
+            return Err(format!("Duplicate section {:#04x}", section));
+        }
+        seen.push(section);
+        let start = decoder.num_read;
+        match section {
+            SECTION_CODE      => module.code = try!(decoder.decode_cells(len))
+                                                   .into_iter()
+                                                   .collect(),
+            SECTION_CONSTANTS => {
+                module.constants = try!(decoder.decode_vector());
+                decoder.constants = module.constants.clone();
+            },
+            SECTION_SYMBOLS   => module.symbols = try!(decoder.decode_named_cells()),
+            SECTION_DEBUG     => module.debug = try!(decoder.decode_named_cells()),
+            SECTION_IMPORTS   => {
+                for _ in 0..try!(decoder.read_u64("the number of imports")) {
+                    module.imports.push(try!(decoder.decode_name()));
+                }
+            },
+            SECTION_CUSTOM    => {
+                let name = try!(decoder.decode_name());
+                let rest = len.saturating_sub((decoder.num_read - start) as u64);
+                module.custom.push((name, try!(decoder.read_bytes(rest, "the contents of a custom section"))));
+            },
+            SECTION_CHECKSUM  |
+            SECTION_SIGNATURE => { try!(decoder.read_bytes(len, "the contents of a section")); },
+            SECTION_STATE     => return Err(String::from(
+                "Expected program, found state snapshot")),
+            other             => return Err(format!(
+                "Unsupported section {:#04x}", other))
+        }
+        try!(decoder.check_section_len(section, start, len));
+    }
+    Ok(module)
+}
+
+/// Encodes a module as a Revision 1 bytecode file.
+///
+/// Sections are only written for metadata the module has, so a module
+/// containing only a program is encoded as a file with a single code
+/// section.
+#[cfg_attr(feature = "nightly", unstable(feature = "module"))]
+pub fn encode_module(module: &Module) -> Vec<u8> {
+    emit_module(module, None)
+}
+
+/// Encodes a module, signing it with `sign` if it is given.
+fn emit_module(module: &Module, sign: Option<&Fn(&[u8]) -> Vec<u8>>) -> Vec<u8> {
+    let mut numbering = Numbering::default();
+    let keys = constant_keys(&module.constants);
+    let mut sections = Vec::new();
+    if !module.constants.is_empty() {
+        sections.push((SECTION_CONSTANTS, emit_payload(&mut numbering, None, |encoder| {
+            try!(encoder.write_u64(module.constants.len() as u64));
+            for cell in &module.constants {
+                try!(encoder.encode_cell(cell));
+            }
+            Ok(())
+        })));
+    }
+    if !module.imports.is_empty() {
+        sections.push((SECTION_IMPORTS, emit_payload(&mut numbering, None, |encoder| {
+            try!(encoder.write_u64(module.imports.len() as u64));
+            for name in &module.imports {
+                try!(encoder.write_name(name));
+            }
+            Ok(())
+        })));
+    }
+    if !module.symbols.is_empty() {
+        sections.push((SECTION_SYMBOLS, emit_payload(&mut numbering, Some(&keys),
+            |encoder| encoder.encode_named_cells(&module.symbols))));
+    }
+    sections.push((SECTION_CODE, emit_payload(&mut numbering, Some(&keys),
+        |encoder| encoder.encode_cells(&module.code))));
+    if !module.debug.is_empty() {
+        sections.push((SECTION_DEBUG, emit_payload(&mut numbering, Some(&keys),
+            |encoder| encoder.encode_named_cells(&module.debug))));
+    }
+    for &(ref name, ref contents) in &module.custom {
+        sections.push((SECTION_CUSTOM, emit_payload(&mut numbering, None, |encoder| {
+            try!(encoder.write_name(name));
+            encoder.write(contents)
+        })));
+    }
+    emit_sections(&sections, sign)
+}
+
+/// Encodes a program as a Revision 1 bytecode file, which may be decoded
+/// with `decode_program()`, returning the number of bytes written.
+///
+/// The program is written as it is encoded, without first being encoded
+/// in memory: it is encoded once to find the length of the code section,
+/// and again as it is written. The contents of any boxes and channels in
+/// it are taken once, in the first pass, so the program written is the one
+/// that was measured even if another thread changes them in the meantime.
+/// The result is the same as encoding a module containing only the program
+/// with `encode_module()`.
+#[cfg_attr(feature = "nightly", unstable(feature = "encode"))]
+pub fn encode_program<W>(program: &List<SVMCell>, sink: &mut W) -> Result<usize, String>
+    where W: Write
+{
+    let (len, snapshot) = {
+        let mut counter = io::sink();
+        let mut encoder = Encoder::new(&mut counter);
+        encoder.snapshot = Some(Snapshot::default());
+        try!(encoder.encode_cells(program));
+        (encoder.num_written(), encoder.snapshot)
+    };
+    let mut sink = checksum::ChecksumWriter::new(sink);
+    let written = {
+        let mut encoder = Encoder::new(&mut sink);
+        encoder.snapshot = snapshot;
+        try!(encoder.write_preamble());
+        try!(encoder.write_u16(2));
+        try!(encoder.write(&[SECTION_CODE]));
+        try!(encoder.write_u64(len as u64));
+        try!(encoder.encode_cells(program));
+        encoder.num_written()
+    };
+    let checksum = checksum::checksum_section(sink.checksum());
+    try!(sink.into_inner()
+             .write_all(&checksum)
+             .map_err(|why| String::from(why.description())));
+    Ok(written + checksum.len())
+}
+
+/// Encodes a snapshot of a state, which may be restored with `decode_state()`.
+///
+/// The snapshot is a complete bytecode file, consisting of the preamble
+/// followed by a single state snapshot section.
+#[cfg_attr(feature = "nightly", unstable(feature = "snapshot"))]
+pub fn encode_state(state: &State) -> Vec<u8> {
+    let payload = emit_payload(&mut Numbering::default(), None, |encoder| {
+        for register in &[&state.stack, &state.env, &state.control, &state.dump] {
+            try!(encoder.encode_list(register));
+        }
+        Ok(())
+    });
+    emit_sections(&[(SECTION_STATE, payload)], None)
+}
+
+/// Restores a state from a snapshot encoded by `encode_state()`.
+///
+/// As with `decode_module()`, the snapshot's checksum is verified before
+/// it is decoded. Snapshots encoded as Revision 0 files, which have no
+/// section table or checksum, may also be restored.
+#[cfg_attr(feature = "nightly", unstable(feature = "snapshot"))]
+pub fn decode_state<R>(source: &mut R) -> Result<State, String>
+    where R: Read
+{
+    let bytes = try!(read_file(source));
+    let mut cursor = Cursor::new(&bytes[..]);
+    let mut decoder = Decoder::new(&mut cursor);
+    try!(decoder.check_ident_bytes());
+    try!(decoder.check_version());
+    if decoder.version == 0 {
+        return match try!(decoder.read_byte("a state snapshot section")) {
+            SECTION_STATE => decoder.decode_registers().map_err(String::from),
+            other         => Err(format!(
+                "Expected state snapshot section, found {:#02x}", other))
+        };
+    }
+    try!(checksum::verify(&bytes));
+    match try!(decoder.read_u16("the number of sections")) {
+        2 => {},
+        n => return Err(format!(
+            "Expected a single state snapshot section, found {} sections", n - 1))
+    }
+    match try!(decoder.read_section_header()) {
+        (SECTION_STATE, len) => {
+            let start = decoder.num_read;
+            let state = try!(decoder.decode_registers());
+            try!(decoder.check_section_len(SECTION_STATE, start, len));
+            Ok(state)
+        },
+        (other, _) => Err(format!(
+            "Expected state snapshot section, found {:#02x}", other))
+    }
+}
+
+/// Reads the whole of a file.
+fn read_file<R>(source: &mut R) -> Result<Vec<u8>, String>
+    where R: Read
+{
+    let mut bytes = Vec::new();
+    try!(source.read_to_end(&mut bytes)
+               .map_err(|why| String::from(why.description())));
+    Ok(bytes)
 }
 
 #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
 pub struct Decoder<'a, R: 'a> {
     source: &'a mut R,
-    num_read: usize
+    num_read: usize,
+    version: u16,
+    boxes: Vec<SharedCell>,
+    channels: Vec<Channel>,
+    constants: Vec<SVMCell>,
+    failed: bool
+}
+
+/// An error decoding a cell, giving where in the source it occurred.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+pub struct DecodeError {
+    /// The offset, in bytes from the start of the source, of the item
+    /// which could not be decoded, or of the end of the source if it
+    /// ended part of the way through an item.
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    pub offset: usize,
+    /// The byte at that offset, or `None` at the end of the source.
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    pub byte: Option<u8>,
+    /// The kind of item the decoder expected to find there.
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    pub expected: String
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+impl fmt::Display for DecodeError {
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.byte {
+            Some(byte) => write!(f, "expected {} at offset {}, found {:#04x}",
+                self.expected, self.offset, byte),
+            None       => write!(f, "expected {} at offset {}, found end of file",
+                self.expected, self.offset)
+        }
+    }
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+impl Error for DecodeError {
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    fn description(&self) -> &str {
+        "could not decode bytecode"
+    }
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+impl From<DecodeError> for String {
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    fn from(why: DecodeError) -> String {
+        why.to_string()
+    }
 }
 
 #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
@@ -222,6 +791,69 @@ fn decode_inst(byte: &u8) -> Result<Inst, String> {
         0x1B => Ok(CDR),
         0x1C => Ok(LDC),
         0x1D => Ok(STOP),
+        0x1E => Ok(INTP),
+        0x1F => Ok(FLOATP),
+        0x20 => Ok(CHARP),
+        0x21 => Ok(PROCP),
+        0x22 => Ok(PAIRP),
+        0x23 => Ok(TOINT),
+        0x24 => Ok(TOUINT),
+        0x25 => Ok(TOFLOAT),
+        0x26 => Ok(TOCHAR),
+        0x27 => Ok(SQRT),
+        0x28 => Ok(EXP),
+        0x29 => Ok(LOG),
+        0x2A => Ok(SIN),
+        0x2B => Ok(COS),
+        0x2C => Ok(TAN),
+        0x2D => Ok(ASIN),
+        0x2E => Ok(ACOS),
+        0x2F => Ok(ATAN),
+        0x30 => Ok(FLOOR),
+        0x31 => Ok(CEIL),
+        0x32 => Ok(ROUND),
+        0x33 => Ok(NANP),
+        0x34 => Ok(INFP),
+        0x35 => Ok(POW),
+        0x36 => Ok(ATAN2),
+        0x37 => Ok(VMAKE),
+        0x38 => Ok(VGET),
+        0x39 => Ok(VSET),
+        0x3A => Ok(VLEN),
+        0x3B => Ok(VTOL),
+        0x3C => Ok(LTOV),
+        0x3D => Ok(BLEN),
+        0x3E => Ok(BGET),
+        0x3F => Ok(BSLICE),
+        0x40 => Ok(BCAT),
+        0x41 => Ok(STOB),
+        0x42 => Ok(BTOS),
+        0x43 => Ok(READB),
+        0x44 => Ok(WRITEB),
+        0x45 => Ok(MNEW),
+        0x46 => Ok(MPUT),
+        0x47 => Ok(MGET),
+        0x48 => Ok(MHAS),
+        0x49 => Ok(MDEL),
+        0x4A => Ok(MKEYS),
+        0x4B => Ok(MLEN),
+        0x4C => Ok(TRY),
+        0x4D => Ok(ENDTRY),
+        0x4E => Ok(THROW),
+        0x4F => Ok(CALLN),
+        0x50 => Ok(LDG),
+        0x51 => Ok(STG),
+        0x52 => Ok(BOX),
+        0x53 => Ok(UNBOX),
+        0x54 => Ok(SETBOX),
+        0x55 => Ok(RETN),
+        0x56 => Ok(RECVN),
+        0x57 => Ok(SPAWN),
+        0x58 => Ok(YIELD),
+        0x59 => Ok(WAIT),
+        0x5A => Ok(CHAN),
+        0x5B => Ok(SEND),
+        0x5C => Ok(RECV),
         b if b >= RESERVED_START &&
              b <= (RESERVED_START + RESERVED_LEN) =>
             Err(format!("Unimplemented: reserved byte {:#X}", b)),
@@ -252,6 +884,10 @@ impl<'a, R> Decoder<'a, R>
             })
     }
 
+    /// Reads the version of the file, checking that it can be decoded.
+    ///
+    /// Files encoded with any revision up to and including `VERSION` are
+    /// accepted; files encoded with a later revision are not.
     #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.3.0"))]
     pub fn check_version(&mut self) -> Result<(), String> {
         self.source
@@ -259,22 +895,33 @@ impl<'a, R> Decoder<'a, R>
             .map_err(|why| String::from(why.description()))
             .and_then(|version| {
                 self.num_read += 2;
-                match version {
-                    VERSION => Ok(()),
-                    bytes   => Err( // I expect this will generate a warning
-                                    // at the call site...
-                        format!("mismatched version {}, expected {}",
-                            bytes, version)
-                    )
+                if version <= VERSION {
+                    self.version = version;
+                    Ok(())
+                } else {
+                    Err(format!("unsupported version {}, expected at most {}",
+                        version, VERSION))
                 }
             })
     }
 
+    /// Returns the version of the file being decoded, once it has been
+    /// read by `check_version()`.
+    #[cfg_attr(feature = "nightly", unstable(feature="module"))]
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
     #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
     pub fn new(src: &'a mut R) -> Decoder<'a, R> {
         Decoder {
             source: src,
-            num_read: 0
+            num_read: 0,
+            version: VERSION,
+            boxes: Vec::new(),
+            channels: Vec::new(),
+            constants: Vec::new(),
+            failed: false
         }
     }
 
@@ -284,116 +931,349 @@ impl<'a, R> Decoder<'a, R>
         self.num_read
     }
 
+    // Returns an error for the item at `offset`, starting with `byte`
+    fn error(&self, offset: usize, byte: Option<u8>, expected: &str) -> DecodeError {
+        DecodeError { offset: offset, byte: byte, expected: String::from(expected) }
+    }
+
+    // Returns an error for an item which ended before it was complete
+    fn eof(&self, expected: &str) -> DecodeError {
+        self.error(self.num_read, None, expected)
+    }
+
+    // Reads exactly enough bytes to fill `buf`
+    fn read_exact(&mut self, buf: &mut [u8], expected: &str) -> Result<(), DecodeError> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.source.read(&mut buf[read..]) {
+                Ok(0)  => break,
+                Ok(n)  => read += n,
+                Err(ref why) if why.kind() == ErrorKind::Interrupted => {},
+                Err(_) => break
+            }
+        }
+        self.num_read += read;
+        if read < buf.len() {
+            Err(self.eof(expected))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_byte(&mut self, expected: &str) -> Result<u8, DecodeError> {
+        let mut buf = [0; 1];
+        try!(self.read_exact(&mut buf, expected));
+        Ok(buf[0])
+    }
+
+    fn read_u16(&mut self, expected: &str) -> Result<u16, DecodeError> {
+        let mut buf = [0; 2];
+        try!(self.read_exact(&mut buf, expected));
+        Ok(BigEndian::read_u16(&buf))
+    }
+
+    fn read_u64(&mut self, expected: &str) -> Result<u64, DecodeError> {
+        let mut buf = [0; 8];
+        try!(self.read_exact(&mut buf, expected));
+        Ok(BigEndian::read_u64(&buf))
+    }
+
+    // Reads exactly `len` bytes
+    fn read_bytes(&mut self, len: u64, expected: &str) -> Result<Vec<u8>, DecodeError> {
+        let mut result = Vec::new();
+        let read = (&mut self.source).take(len).read_to_end(&mut result);
+        self.num_read += result.len();
+        match read {
+            Ok(_) if result.len() as u64 == len => Ok(result),
+            _ => Err(self.eof(expected))
+        }
+    }
+
+    // Reads the type and length of a section
+    fn read_section_header(&mut self) -> Result<(u8, u64), DecodeError> {
+        let section = try!(self.read_byte("a section"));
+        let len = try!(self.read_u64("the length of a section"));
+        Ok((section, len))
+    }
+
+    // Checks that a section was exactly as long as it said it was
+    fn check_section_len(&self, section: u8, start: usize, len: u64) -> Result<(), String> {
+        let read = (self.num_read - start) as u64;
+        if read == len {
+            Ok(())
+        } else {
+            Err(format!("Section {:#04x} should be {} bytes long, but {} were read",
+                section, len, read))
+        }
+    }
+
+    // Decodes cells until `len` bytes have been read
+    fn decode_cells(&mut self, len: u64) -> Result<Vec<SVMCell>, DecodeError> {
+        let start = self.num_read;
+        let mut result = Vec::new();
+        while ((self.num_read - start) as u64) < len {
+            match try!(self.next_cell()) {
+                Some(cell) => result.push(cell),
+                None       => return Err(self.eof("a cell"))
+            }
+        }
+        Ok(result)
+    }
+
+    // Decodes a length-prefixed list of names and the cells bound to them
+    fn decode_named_cells(&mut self) -> Result<Vec<(String, SVMCell)>, DecodeError> {
+        let len = try!(self.read_u64("the number of symbols"));
+        let mut result = Vec::new();
+        for _ in 0..len {
+            let name = try!(self.decode_name());
+            match try!(self.next_cell()) {
+                Some(cell) => result.push((name, cell)),
+                None       => return Err(self.eof("the value of a symbol"))
+            }
+        }
+        Ok(result)
+    }
+
+    // Decodes the registers of a state snapshot
+    fn decode_registers(&mut self) -> Result<State, DecodeError> {
+        let mut registers = Vec::new();
+        for name in &["stack", "environment", "control", "dump"] {
+            let offset = self.num_read;
+            let expected = format!("the {} of a state snapshot", name);
+            let byte = try!(self.read_byte(&expected));
+            match try!(self.decode_tagged(offset, byte)) {
+                SVMCell::ListCell(box list) => registers.push(list),
+                _ => return Err(self.error(offset, Some(byte), &expected))
+            }
+        }
+        let dump = registers.pop().unwrap();
+        let control = registers.pop().unwrap();
+        let env = registers.pop().unwrap();
+        let stack = registers.pop().unwrap();
+        Ok(State { stack: stack, env: env, control: control, dump: dump })
+    }
+
     #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
-    fn decode_const(&mut self, byte: &u8) -> Result<Atom, String> {
-        match *byte & 0x0F { // extract the type tag
+    fn decode_const(&mut self, offset: usize, byte: u8) -> Result<Atom, DecodeError> {
+        match byte & 0x0F { // extract the type tag
             1 => {
-                self.num_read += 8; // this should be more FP i guess
-                self.source
-                    .read_u64::<BigEndian>()
-                    .map(Atom::UInt)
-                    .map_err(|why| String::from(why.description()))
-                },
+                let mut buf = [0; 8];
+                try!(self.read_exact(&mut buf, "a uint"));
+                Ok(Atom::UInt(BigEndian::read_u64(&buf)))
+            },
             2 => {
-                self.num_read += 8;
-                self.source
-                    .read_i64::<BigEndian>()
-                    .map(Atom::SInt)
-                    .map_err(|why| String::from(why.description()))
-                },
+                let mut buf = [0; 8];
+                try!(self.read_exact(&mut buf, "a sint"));
+                Ok(Atom::SInt(BigEndian::read_i64(&buf)))
+            },
             3 => {
-                self.num_read += 4;
-                self.source
-                    .read_u32::<BigEndian>()
-                    .map_err( |why | String::from(why.description()))
-                    .and_then(|byte|
-                        char::from_u32(byte)
-                            .ok_or(String::from("Could not read character."))
-                        )
+                let mut buf = [0; 4];
+                try!(self.read_exact(&mut buf, "a char"));
+                char::from_u32(BigEndian::read_u32(&buf))
                     .map(Atom::Char)
-                },
+                    .ok_or(self.error(offset, Some(byte), "a valid char"))
+            },
             4 => {
-                self.num_read += 8;
-                self.source
-                    .read_f64::<BigEndian>()
-                    .map(Atom::Float)
-                    .map_err(|why| String::from(why.description()))
-                },
-            _ => unimplemented!()
+                let mut buf = [0; 8];
+                try!(self.read_exact(&mut buf, "a float"));
+                Ok(Atom::Float(BigEndian::read_f64(&buf)))
+            },
+            _ => Err(self.error(offset, Some(byte), "an atom"))
         }
     }
-    // Decodes a CONS cell
+
+    // Decodes a CONS cell, and the rest of the list it begins
     #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
-    fn decode_cons(&mut self) -> Result<Option<Box<List<SVMCell>>>, String> {
-        self.next_cell()
-            .and_then(|car|
-                car.ok_or(String::from("EOF while decoding CONS cell"))
-            )
-            .map(|car| {
-                debug!("Decoded {:?}, {} bytes read", car, self.num_read);
-                car
-            })
-            .and_then(|car| {
-                let mut buf = [0;1];
-                try!(self.source.read(&mut buf) // try to get next byte
-                         .map_err(|why| String::from(why.description())));
-                self.num_read += 1;
-                match buf[0] {
-                    BYTE_CONS =>
-                        self.decode_cons()
-                            .and_then(|cdr| cdr.ok_or(
-                                String::from("EOF while decoding CONS")) )
-                            .map( |cdr| (car, cdr) ),
-                    BYTE_NIL  => Ok((car, Box::new(Nil))),
-                    b         => Err(
-                        format!("Unexpected byte {:#02x} while decoding CONS", b)
-                    )
-                }
+    fn decode_cons(&mut self) -> Result<List<SVMCell>, DecodeError> {
+        let mut cells = Vec::new();
+        loop {
+            match try!(self.next_cell()) {
+                Some(car) => {
+                    debug!("Decoded {:?}, {} bytes read", car, self.num_read);
+                    cells.push(car)
+                },
+                None => return Err(self.eof("the CAR of a CONS cell"))
+            }
+            let offset = self.num_read;
+            match try!(self.read_byte("a CONS cell or nil")) {
+                BYTE_CONS => {},
+                BYTE_NIL  => return Ok(cells.into_iter().collect()),
+                b         => return Err(self.error(offset, Some(b), "a CONS cell or nil"))
+            }
+        }
+    }
+
+    // Decodes a length-prefixed vector
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    fn decode_vector(&mut self) -> Result<Vec<SVMCell>, DecodeError> {
+        let len = try!(self.read_u64("the length of a vector"));
+        let mut result = Vec::new();
+        for _ in 0..len {
+            match try!(self.next_cell()) {
+                Some(cell) => result.push(cell),
+                None       => return Err(self.eof(&format!(
+                    "{} more elements of a vector", len - result.len() as u64)))
+            }
+        }
+        Ok(result)
+    }
+
+    // Decodes a length-prefixed byte buffer
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    fn decode_bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = try!(self.read_u64("the length of a byte buffer"));
+        self.read_bytes(len, "the contents of a byte buffer")
+    }
+
+    // Decodes a length-prefixed map
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    fn decode_map(&mut self) -> Result<BTreeMap<MapKey, SVMCell>, DecodeError> {
+        let len = try!(self.read_u64("the length of a map"));
+        let mut result = BTreeMap::new();
+        for _ in 0..len {
+            let offset = self.num_read;
+            let tag = try!(self.read_byte("a map key"));
+            let key = try!(self.decode_tagged(offset, tag));
+            let key = try!(MapKey::from_cell(&key)
+                .map_err(|_| self.error(offset, Some(tag), "a map key")));
+            match try!(self.next_cell()) {
+                Some(value) => { result.insert(key, value); },
+                None        => return Err(self.eof("the value of a map entry"))
+            }
+        }
+        Ok(result)
+    }
+
+    // Decodes a length-prefixed name, of a native function or global
+    #[cfg_attr(feature = "nightly", unstable(feature="decode"))]
+    fn decode_name(&mut self) -> Result<String, DecodeError> {
+        let bytes = try!(self.decode_bytes());
+        let start = self.num_read - bytes.len();
+        String::from_utf8(bytes)
+            .map_err(|why| {
+                let valid = why.utf8_error().valid_up_to();
+                self.error(start + valid, Some(why.as_bytes()[valid]), "a UTF-8 name")
             })
-            .map(|(car, cdr)| Some(Box::new( Cons(car, cdr)) ))
     }
 
     /// Decodes the next cell in the source
+    ///
+    /// Returns `Ok(None)` at the end of the source.
     #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
-    pub fn next_cell(&mut self) -> Result<Option<SVMCell>,String> {
+    pub fn next_cell(&mut self) -> Result<Option<SVMCell>, DecodeError> {
+        let offset = self.num_read;
         let mut buf = [0;1];
         match self.source.read(&mut buf) {
             Ok(1)   => { // a byte was read
                 self.num_read += 1;
                 debug!("Read {:#X}, {} bytes read", buf[0], self.num_read);
-                match buf[0] {
-                    b if b < 0x30 => decode_inst(&b)
-                                        .map(SVMCell::InstCell)
-                                        .map(Some),
-                    b if b >= CONST_START &&
-                         b < (CONST_START + CONST_LEN) =>
-                                    self.decode_const(&b)
-                                        .map(SVMCell::AtomCell)
-                                        .map(Some),
-                    BYTE_CONS    => self.decode_cons()
-                                        .map(|cell|
-                                              cell.map(SVMCell::ListCell)
-                                        ),
-                    b            => Err(format!("Unsupported byte {:#02x}", b))
-                }
+                self.decode_tagged(offset, buf[0]).map(Some)
             },
             Ok(0)    => Ok(None), //  we're out of bytes - EOF
             Ok(_)    => unreachable!(), //
-            Err(why) => Err(String::from(why.description()))
+            Err(_)   => Err(self.eof("a cell"))
+        }
+    }
+
+    // Decodes the cell beginning with the byte `byte`, at `offset`
+    fn decode_tagged(&mut self, offset: usize, byte: u8) -> Result<SVMCell, DecodeError> {
+        match byte {
+            b if b <= (RESERVED_START + RESERVED_LEN) =>
+                            decode_inst(&b)
+                                .map(SVMCell::InstCell)
+                                .map_err(|_| self.error(offset, Some(b), "an instruction")),
+            BYTE_VECTOR  => self.decode_vector()
+                                .map(|vec| VectorCell(Arc::new(vec))),
+            BYTE_BYTES   => self.decode_bytes()
+                                .map(SVMCell::BytesCell),
+            BYTE_MAP     => self.decode_map()
+                                .map(SVMCell::MapCell),
+            BYTE_NATIVE  => self.decode_name()
+                                .map(SVMCell::NativeCell),
+            BYTE_GLOBAL  => self.decode_name()
+                                .map(SVMCell::GlobalCell),
+            BYTE_BOX     => {
+                // register the box before decoding its contents,
+                // which may refer back to it
+                let shared = SharedCell::new(SVMCell::ListCell(Box::new(Nil)));
+                self.boxes.push(shared.clone());
+                match try!(self.next_cell()) {
+                    Some(cell) => {
+                        shared.set(cell);
+                        Ok(SVMCell::BoxCell(shared))
+                    },
+                    None       => Err(self.eof("the contents of a box"))
+                }
+            },
+            BYTE_BOX_REF => {
+                let index = try!(self.read_u64("the index of a box"));
+                match self.boxes.get(index as usize) {
+                    Some(shared) => Ok(SVMCell::BoxCell(shared.clone())),
+                    None         => Err(self.error(offset, Some(byte), &format!(
+                        "a reference to one of the {} boxes decoded so far",
+                        self.boxes.len())))
+                }
+            },
+            BYTE_EMPTY   => Ok(SVMCell::ListCell(Box::new(Nil))),
+            BYTE_CONST_REF => {
+                let index = try!(self.read_u64("the index of a constant"));
+                match self.constants.get(index as usize) {
+                    Some(cell) => Ok(cell.clone()),
+                    None       => Err(self.error(offset, Some(byte), &format!(
+                        "a reference to one of the {} constants in the pool",
+                        self.constants.len())))
+                }
+            },
+            BYTE_CHANNEL => {
+                // as with boxes, register the channel before decoding the
+                // values waiting on it, which may refer back to it
+                let channel = Channel::new();
+                self.channels.push(channel.clone());
+                for value in try!(self.decode_vector()) {
+                    channel.send(value);
+                }
+                Ok(SVMCell::ChannelCell(channel))
+            },
+            BYTE_CHANNEL_REF => {
+                let index = try!(self.read_u64("the index of a channel"));
+                match self.channels.get(index as usize) {
+                    Some(channel) => Ok(SVMCell::ChannelCell(channel.clone())),
+                    None          => Err(self.error(offset, Some(byte), &format!(
+                        "a reference to one of the {} channels decoded so far",
+                        self.channels.len())))
+                }
+            },
+            b if b >= CONST_START &&
+                 b < (CONST_START + CONST_LEN) =>
+                            self.decode_const(offset, b)
+                                .map(SVMCell::AtomCell),
+            BYTE_CONS    => self.decode_cons()
+                                .map(|list| SVMCell::ListCell(Box::new(list))),
+            b            => Err(self.error(offset, Some(b), "a cell"))
         }
     }
 
 }
 
+/// Decodes each cell in turn, until the end of the source, or until a
+/// cell cannot be decoded, after which no more cells are decoded.
 #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
 impl<'a, R> Iterator for Decoder<'a, R> where R: Read {
     #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
-    type Item = SVMCell;
+    type Item = Result<SVMCell, DecodeError>;
 
     #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
-    fn next(&mut self) -> Option<SVMCell> {
-        self.next_cell()
-            .unwrap()
+    fn next(&mut self) -> Option<Result<SVMCell, DecodeError>> {
+        if self.failed {
+            return None;
+        }
+        match self.next_cell() {
+            Ok(cell) => cell.map(Ok),
+            Err(why) => {
+                self.failed = true;
+                Some(Err(why))
+            }
+        }
     }
 }
 #[cfg_attr(feature = "nightly", stable(feature="decode", since="0.2.6"))]
@@ -408,10 +1288,338 @@ impl<'a, R> fmt::Debug for Decoder<'a, R>  where R: fmt::Debug {
 
 }
 
+/// Encodes cells, writing them to a sink as they are encoded.
+///
+/// An encoder numbers the boxes and channels it writes, as a decoder does
+/// when it reads them, so each is written once, and any further copies of
+/// it are written as references to it.
+///
+/// To write a complete bytecode file, with its section table and checksum,
+/// use `encode_program()` or `encode_module()`.
+#[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+pub struct Encoder<'a, W: 'a> {
+    sink: &'a mut W,
+    num_written: usize,
+    boxes: Vec<SharedCell>,
+    channels: Vec<Channel>,
+    constants: Option<&'a ConstantKeys>,
+    snapshot: Option<Snapshot>
+}
+
+/// The contents of the boxes and channels numbered by an encoder, in the
+/// order in which it numbered them, as they were when it first encoded
+/// them, so that cells may be encoded again exactly as they were before.
+#[derive(Default)]
+struct Snapshot {
+    boxes: Vec<SVMCell>,
+    channels: Vec<Vec<SVMCell>>
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+impl<'a, W> Encoder<'a, W>
+    where W: Write
+{
+    #[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+    pub fn new(sink: &'a mut W) -> Encoder<'a, W> {
+        Encoder {
+            sink: sink,
+            num_written: 0,
+            boxes: Vec::new(),
+            channels: Vec::new(),
+            constants: None,
+            snapshot: None
+        }
+    }
+
+    /// Returns the number of bytes written by the encoder
+    #[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+    pub fn num_written(&self) -> usize {
+        self.num_written
+    }
+
+    /// Writes the identifying bytes and the version of this revision of
+    /// the format.
+    ///
+    /// A Revision 1 file must then contain its section table, ending with
+    /// its checksum, as described above; to write a complete file, use
+    /// `encode_program()`.
+    #[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+    pub fn write_preamble(&mut self) -> Result<(), String> {
+        try!(self.write_u16(IDENT_BYTES));
+        self.write_u16(VERSION)
+    }
+
+    /// Encodes a cell, and writes it to the sink
+    #[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+    pub fn encode_cell(&mut self, cell: &SVMCell) -> Result<(), String> {
+        match (cell, self.constants) {
+            (&BoxCell(_), _) | (&ChannelCell(_), _) | (_, None) => {},
+            (_, Some(constants)) => if let Some(&index) = constants.get(&cell.emit()) {
+                try!(self.write(&[BYTE_CONST_REF]));
+                return self.write_u64(index);
+            }
+        }
+        match *cell {
+            ListCell(ref list) => self.encode_list(list),
+            VectorCell(ref vec) => {
+                try!(self.write(&[BYTE_VECTOR]));
+                try!(self.write_u64(vec.len() as u64));
+                for cell in vec.iter() {
+                    try!(self.encode_cell(cell));
+                }
+                Ok(())
+            },
+            BytesCell(ref bytes) => {
+                try!(self.write(&[BYTE_BYTES]));
+                try!(self.write_u64(bytes.len() as u64));
+                self.write(bytes)
+            },
+            MapCell(ref map) => {
+                try!(self.write(&[BYTE_MAP]));
+                try!(self.write_u64(map.len() as u64));
+                for (key, value) in map {
+                    try!(self.encode_cell(&key.to_cell()));
+                    try!(self.encode_cell(value));
+                }
+                Ok(())
+            },
+            BoxCell(ref shared) => match self.boxes.iter().position(|it| it.ptr_eq(shared)) {
+                Some(index) => {
+                    try!(self.write(&[BYTE_BOX_REF]));
+                    self.write_u64(index as u64)
+                },
+                None => {
+                    self.boxes.push(shared.clone());
+                    let contents = self.box_contents(shared);
+                    try!(self.write(&[BYTE_BOX]));
+                    self.encode_cell(&contents)
+                }
+            },
+            ChannelCell(ref channel) => match self.channels.iter().position(|it| it.ptr_eq(channel)) {
+                Some(index) => {
+                    try!(self.write(&[BYTE_CHANNEL_REF]));
+                    self.write_u64(index as u64)
+                },
+                None => {
+                    self.channels.push(channel.clone());
+                    let pending = self.channel_contents(channel);
+                    try!(self.write(&[BYTE_CHANNEL]));
+                    try!(self.write_u64(pending.len() as u64));
+                    for cell in &pending {
+                        try!(self.encode_cell(cell));
+                    }
+                    Ok(())
+                }
+            },
+            ref cell => self.write(&cell.emit())
+        }
+    }
+
+    // Returns the contents of the box just numbered, from the snapshot if
+    // the encoder has one, recording them there if they are not yet in it
+    fn box_contents(&mut self, shared: &SharedCell) -> SVMCell {
+        let index = self.boxes.len() - 1;
+        match self.snapshot {
+            Some(ref mut snapshot) => {
+                if index == snapshot.boxes.len() {
+                    snapshot.boxes.push(shared.get());
+                }
+                snapshot.boxes[index].clone()
+            },
+            None => shared.get()
+        }
+    }
+
+    // Returns the values waiting in the channel just numbered, as for
+    // the contents of a box
+    fn channel_contents(&mut self, channel: &Channel) -> Vec<SVMCell> {
+        let index = self.channels.len() - 1;
+        match self.snapshot {
+            Some(ref mut snapshot) => {
+                if index == snapshot.channels.len() {
+                    snapshot.channels.push(channel.pending());
+                }
+                snapshot.channels[index].clone()
+            },
+            None => channel.pending()
+        }
+    }
+
+    // Encodes a list cell
+    fn encode_list(&mut self, list: &List<SVMCell>) -> Result<(), String> {
+        if let Nil = *list {
+            return self.write(&[BYTE_EMPTY]);
+        }
+        for cell in list.iter() {
+            try!(self.write(&[BYTE_CONS]));
+            try!(self.encode_cell(cell));
+        }
+        self.write(&[BYTE_NIL])
+    }
+
+    // Encodes each cell of a list in turn, as the cells of a program
+    fn encode_cells(&mut self, cells: &List<SVMCell>) -> Result<(), String> {
+        for cell in cells.iter() {
+            try!(self.encode_cell(cell));
+        }
+        Ok(())
+    }
+
+    // Encodes a length-prefixed list of names and the cells bound to them
+    fn encode_named_cells(&mut self, cells: &[(String, SVMCell)]) -> Result<(), String> {
+        try!(self.write_u64(cells.len() as u64));
+        for &(ref name, ref cell) in cells {
+            try!(self.write_name(name));
+            try!(self.encode_cell(cell));
+        }
+        Ok(())
+    }
+
+    // Writes a length-prefixed name
+    fn write_name(&mut self, name: &str) -> Result<(), String> {
+        try!(self.write_u64(name.len() as u64));
+        self.write(name.as_bytes())
+    }
+
+    fn write_u16(&mut self, value: u16) -> Result<(), String> {
+        let mut buf = [0; 2];
+        BigEndian::write_u16(&mut buf, value);
+        self.write(&buf)
+    }
+
+    fn write_u64(&mut self, value: u64) -> Result<(), String> {
+        let mut buf = [0; 8];
+        BigEndian::write_u64(&mut buf, value);
+        self.write(&buf)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        try!(self.sink
+                 .write_all(bytes)
+                 .map_err(|why| String::from(why.description())));
+        self.num_written += bytes.len();
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+impl<'a, W> fmt::Debug for Encoder<'a, W>  where W: fmt::Debug {
+    #[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Encoding to: {:?}, {} bytes written",
+            self.sink,
+            self.num_written
+        )
+    }
+}
+
+/// The boxes and channels numbered by the sections encoded so far.
+#[derive(Default)]
+struct Numbering {
+    boxes: Vec<SharedCell>,
+    channels: Vec<Channel>
+}
+
+/// The indices of the constants in a module's pool, keyed by their
+/// encodings, so that cells are only encoded as references to constants
+/// which are the same bit for bit (`0.0` is not `-0.0`), and can be looked
+/// up without comparing them with every constant.
+type ConstantKeys = BTreeMap<Vec<u8>, u64>;
+
+/// Returns the keys of a module's constants. Constants which hold boxes
+/// or channels are left out, since a copy of one is not the same cell.
+fn constant_keys(constants: &[SVMCell]) -> ConstantKeys {
+    let mut keys = BTreeMap::new();
+    for (index, cell) in constants.iter().enumerate() {
+        if !holds_shared(cell) {
+            keys.entry(cell.emit()).or_insert(index as u64);
+        }
+    }
+    keys
+}
+
+/// Returns true if a cell is, or holds, a box or a channel.
+fn holds_shared(cell: &SVMCell) -> bool {
+    match *cell {
+        BoxCell(_) | ChannelCell(_) => true,
+        ListCell(ref list) => list.iter().any(holds_shared),
+        VectorCell(ref vec) => vec.iter().any(holds_shared),
+        MapCell(ref map) => map.values().any(holds_shared),
+        _ => false
+    }
+}
+
+/// Encodes the payload of a section, numbering boxes and channels after
+/// those in any earlier sections, and encoding cells which are the same as
+/// one of the given constants as references to them.
+fn emit_payload<F>(numbering: &mut Numbering, constants: Option<&ConstantKeys>, encode: F)
+                   -> Vec<u8>
+    where F: FnOnce(&mut Encoder<Vec<u8>>) -> Result<(), String>
+{
+    let mut payload = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut payload);
+        encoder.boxes = mem::replace(&mut numbering.boxes, Vec::new());
+        encoder.channels = mem::replace(&mut numbering.channels, Vec::new());
+        encoder.constants = constants;
+        // writing to a Vec can't fail
+        encode(&mut encoder).unwrap();
+        numbering.boxes = encoder.boxes;
+        numbering.channels = encoder.channels;
+    }
+    payload
+}
+
 #[cfg_attr(feature = "nightly", stable(feature="encode", since="0.2.6"))]
 pub trait Encode {
     #[cfg_attr(feature = "nightly", stable(feature="encode", since="0.2.6"))]
     fn emit(&self) -> Vec<u8>;
+
+    /// Encodes this item with an encoder, so that it shares the encoder's
+    /// numbering of boxes and channels.
+    #[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+    fn encode_with<W: Write>(&self, encoder: &mut Encoder<W>) -> Result<(), String> {
+        encoder.write(&self.emit())
+    }
+}
+
+/// Encodes a length-prefixed name, of a native function or global
+fn emit_name(name: &str) -> Vec<u8> {
+    let mut result = Vec::new();
+    result.write_u64::<BigEndian>(name.len() as u64)
+          .unwrap();
+    push_all!(result, name.as_bytes());
+    result
+}
+
+/// Encodes the preamble and section table of a Revision 1 file, ending
+/// with its signature, if it is signed, and its checksum
+fn emit_sections(sections: &[(u8, Vec<u8>)], sign: Option<&Fn(&[u8]) -> Vec<u8>>)
+                 -> Vec<u8> {
+    let mut result = Vec::new();
+    result.write_u16::<BigEndian>(IDENT_BYTES)
+          .unwrap();
+    result.write_u16::<BigEndian>(VERSION)
+          .unwrap();
+    let count = sections.len() + if sign.is_some() { 2 } else { 1 };
+    result.write_u16::<BigEndian>(count as u16)
+          .unwrap();
+    for &(section, ref payload) in sections {
+        result.push(section);
+        result.write_u64::<BigEndian>(payload.len() as u64)
+              .unwrap();
+        push_all!(result, &payload[..]);
+    }
+    if let Some(sign) = sign {
+        let signature = sign(&result);
+        result.push(SECTION_SIGNATURE);
+        result.write_u64::<BigEndian>(signature.len() as u64)
+              .unwrap();
+        push_all!(result, &signature[..]);
+    }
+    let checksum = checksum::emit_checksum(&result);
+    push_all!(result, &checksum[..]);
+    result
 }
 
 #[cfg_attr(feature = "nightly", stable(feature="encode", since="0.2.6"))]
@@ -421,9 +1629,39 @@ impl Encode for SVMCell {
         match *self {
             AtomCell(ref atom) => atom.emit(),
             InstCell(ref inst) => inst.emit(),
-            ListCell(ref list) => (*list).emit()
+            BytesCell(ref bytes) => {
+                let mut result = vec![BYTE_BYTES];
+                result.write_u64::<BigEndian>(bytes.len() as u64)
+                      .unwrap();
+                push_all!(result, bytes);
+                result
+            },
+            NativeCell(ref name) => {
+                let mut result = vec![BYTE_NATIVE];
+                push_all!(result, &emit_name(name)[..]);
+                result
+            },
+            GlobalCell(ref name) => {
+                let mut result = vec![BYTE_GLOBAL];
+                push_all!(result, &emit_name(name)[..]);
+                result
+            },
+            // cells which may hold boxes or channels are encoded with an
+            // encoder, which numbers them, so that a box or channel which
+            // holds itself is encoded as a reference to itself
+            _ => {
+                let mut result = Vec::new();
+                // writing to a Vec can't fail
+                self.encode_with(&mut Encoder::new(&mut result)).unwrap();
+                result
+            }
         }
     }
+
+    #[cfg_attr(feature = "nightly", unstable(feature="encode"))]
+    fn encode_with<W: Write>(&self, encoder: &mut Encoder<W>) -> Result<(), String> {
+        encoder.encode_cell(self)
+    }
 }
 
 #[cfg_attr(feature = "nightly", stable(feature="encode", since="0.2.6"))]
@@ -493,7 +1731,70 @@ impl Encode for Inst {
             CAR     => vec![0x1A],
             CDR     => vec![0x1B],
             LDC     => vec![0x1C],
-            STOP    => vec![0x1D]
+            STOP    => vec![0x1D],
+            INTP    => vec![0x1E],
+            FLOATP  => vec![0x1F],
+            CHARP   => vec![0x20],
+            PROCP   => vec![0x21],
+            PAIRP   => vec![0x22],
+            TOINT   => vec![0x23],
+            TOUINT  => vec![0x24],
+            TOFLOAT => vec![0x25],
+            TOCHAR  => vec![0x26],
+            SQRT    => vec![0x27],
+            EXP     => vec![0x28],
+            LOG     => vec![0x29],
+            SIN     => vec![0x2A],
+            COS     => vec![0x2B],
+            TAN     => vec![0x2C],
+            ASIN    => vec![0x2D],
+            ACOS    => vec![0x2E],
+            ATAN    => vec![0x2F],
+            FLOOR   => vec![0x30],
+            CEIL    => vec![0x31],
+            ROUND   => vec![0x32],
+            NANP    => vec![0x33],
+            INFP    => vec![0x34],
+            POW     => vec![0x35],
+            ATAN2   => vec![0x36],
+            VMAKE   => vec![0x37],
+            VGET    => vec![0x38],
+            VSET    => vec![0x39],
+            VLEN    => vec![0x3A],
+            VTOL    => vec![0x3B],
+            LTOV    => vec![0x3C],
+            BLEN    => vec![0x3D],
+            BGET    => vec![0x3E],
+            BSLICE  => vec![0x3F],
+            BCAT    => vec![0x40],
+            STOB    => vec![0x41],
+            BTOS    => vec![0x42],
+            READB   => vec![0x43],
+            WRITEB  => vec![0x44],
+            MNEW    => vec![0x45],
+            MPUT    => vec![0x46],
+            MGET    => vec![0x47],
+            MHAS    => vec![0x48],
+            MDEL    => vec![0x49],
+            MKEYS   => vec![0x4A],
+            MLEN    => vec![0x4B],
+            TRY     => vec![0x4C],
+            ENDTRY  => vec![0x4D],
+            THROW   => vec![0x4E],
+            CALLN   => vec![0x4F],
+            LDG     => vec![0x50],
+            STG     => vec![0x51],
+            BOX     => vec![0x52],
+            UNBOX   => vec![0x53],
+            SETBOX  => vec![0x54],
+            RETN    => vec![0x55],
+            RECVN   => vec![0x56],
+            SPAWN   => vec![0x57],
+            YIELD   => vec![0x58],
+            WAIT    => vec![0x59],
+            CHAN    => vec![0x5A],
+            SEND    => vec![0x5B],
+            RECV    => vec![0x5C]
         }
     }
 }
@@ -502,14 +1803,17 @@ impl Encode for Inst {
 impl<T> Encode for List<T> where T: Encode {
     #[cfg_attr(feature = "nightly", stable(feature="encode", since="0.2.6"))]
     fn emit(&self) -> Vec<u8> {
-        match *self {
-            Cons(ref it, ref tail) => {
-                let mut result = vec![BYTE_CONS];
-                push_all!(result, &it.emit());
-                push_all!(result, &(*tail.emit()));
-                result
-            },
-            Nil => vec![BYTE_NIL]
+        let mut result = Vec::new();
+        {
+            // the items share one numbering of boxes and channels
+            let mut encoder = Encoder::new(&mut result);
+            // writing to a Vec can't fail
+            for it in self.iter() {
+                encoder.write(&[BYTE_CONS]).unwrap();
+                it.encode_with(&mut encoder).unwrap();
+            }
+            encoder.write(&[BYTE_NIL]).unwrap();
         }
+        result
     }
 }
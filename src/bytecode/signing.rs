@@ -0,0 +1,259 @@
+//! Programs may be signed by their author with an Ed25519 key, using
+//! `encode_signed_module()`. A host which should only run programs from
+//! authors it trusts decodes them with `TrustedKeys::decode_module()`,
+//! which refuses any program that is not signed by one of its keys.
+//!
+//! This module is only available with the `signing` feature.
+
+use ed25519_dalek::{Signer, Signature};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+use std::io::{BufRead, Read, Cursor};
+
+use super::{Decoder, Module, SECTION_SIGNATURE};
+use super::{checksum, emit_module, read_file};
+
+/// The length of a signature section's contents: a public key, then a
+/// signature.
+const SIGNATURE_LEN: usize = 32 + 64;
+
+/// The prefix of every error returned when a program's signature is
+/// missing, invalid or not trusted.
+#[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+pub const SIGNATURE_ERROR: &'static str = "Signature check failed";
+
+/// Encodes a module as a Revision 1 bytecode file, signed with the
+/// author's key.
+#[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+pub fn encode_signed_module(module: &Module, key: &SigningKey) -> Vec<u8> {
+    emit_module(module, Some(&|bytes: &[u8]| {
+        let mut signature = key.verifying_key().to_bytes().to_vec();
+        signature.extend(key.sign(bytes).to_bytes().iter());
+        signature
+    }))
+}
+
+/// A set of public keys belonging to trusted authors.
+#[derive(Clone,Debug,Default)]
+#[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+pub struct TrustedKeys {
+    keys: Vec<VerifyingKey>
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+impl TrustedKeys {
+
+    /// Creates an empty set of keys, which trusts no one.
+    #[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+    pub fn new() -> TrustedKeys {
+        TrustedKeys { keys: Vec::new() }
+    }
+
+    /// Reads a set of keys, one to a line, each written as the 64
+    /// hexadecimal digits of an Ed25519 public key. Blank lines, and lines
+    /// beginning with `#`, are ignored.
+    #[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+    pub fn read<R>(source: R) -> Result<TrustedKeys, String>
+        where R: BufRead
+    {
+        let mut keys = TrustedKeys::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = try!(line.map_err(|why| format!("line {}: {}", i + 1, why)));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let key = try!(parse_key(line)
+                .map_err(|why| format!("line {}: {}", i + 1, why)));
+            keys.trust(key);
+        }
+        Ok(keys)
+    }
+
+    /// Trusts programs signed with the given key.
+    #[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+    pub fn trust(&mut self, key: VerifyingKey) {
+        if !self.is_trusted(&key) {
+            self.keys.push(key);
+        }
+    }
+
+    /// Returns true if programs signed with the given key are trusted.
+    #[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+    pub fn is_trusted(&self, key: &VerifyingKey) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Decodes a program and its metadata, as `bytecode::decode_module()`
+    /// does, but only if it is signed by a trusted key.
+    ///
+    /// The file's checksum and signature are checked before anything in
+    /// it is decoded. If the program is unsigned, is signed by a key which
+    /// is not trusted, or its signature does not match its contents, an
+    /// error beginning with "Signature check failed" is returned.
+    #[cfg_attr(feature = "nightly", unstable(feature="signing"))]
+    pub fn decode_module<R>(&self, source: &mut R) -> Result<Module, String>
+        where R: Read
+    {
+        let bytes = try!(read_file(source));
+        {
+            let mut cursor = Cursor::new(&bytes[..]);
+            let mut decoder = Decoder::new(&mut cursor);
+            try!(decoder.check_ident_bytes());
+            try!(decoder.check_version());
+            if decoder.version() == 0 {
+                return Err(format!("{}: program is not signed", SIGNATURE_ERROR));
+            }
+        }
+        try!(checksum::verify(&bytes));
+        let sections = try!(checksum::sections(&bytes));
+        let signature = match sections.iter()
+                                      .position(|it| it.section == SECTION_SIGNATURE) {
+            Some(i) if i + 2 == sections.len() => &sections[i],
+            Some(_) => return Err(format!(
+                "{}: signature must be the last section before the checksum",
+                SIGNATURE_ERROR)),
+            None => return Err(format!("{}: program is not signed", SIGNATURE_ERROR))
+        };
+        let contents = &bytes[signature.payload..signature.end];
+        if contents.len() != SIGNATURE_LEN {
+            return Err(format!("{}: malformed signature", SIGNATURE_ERROR));
+        }
+        let mut key = [0; 32];
+        key.copy_from_slice(&contents[..32]);
+        let key = try!(VerifyingKey::from_bytes(&key)
+            .map_err(|_| format!("{}: malformed public key", SIGNATURE_ERROR)));
+        if !self.is_trusted(&key) {
+            return Err(format!("{}: program is signed by untrusted key {}",
+                SIGNATURE_ERROR, to_hex(key.as_bytes())));
+        }
+        let mut sig = [0; 64];
+        sig.copy_from_slice(&contents[32..]);
+        try!(key.verify_strict(&bytes[..signature.start], &Signature::from_bytes(&sig))
+            .map_err(|_| format!("{}: signature does not match contents", SIGNATURE_ERROR)));
+        super::decode_module(&mut Cursor::new(bytes))
+    }
+}
+
+/// Parses a public key written as hexadecimal digits.
+fn parse_key(hex: &str) -> Result<VerifyingKey, String> {
+    // checking every byte first means the slices below fall on char
+    // boundaries, and rules out signs, which `from_str_radix` accepts
+    if hex.len() != 64 || !hex.bytes().all(|b| (b as char).is_digit(16)) {
+        return Err(format!("expected 64 hexadecimal digits, found {:?}", hex));
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = try!(u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("expected 64 hexadecimal digits, found {:?}", hex)));
+    }
+    VerifyingKey::from_bytes(&key)
+        .map_err(|_| format!("{} is not a valid public key", hex))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrustedKeys, SigningKey, encode_signed_module, to_hex};
+    use super::super::{Module, Encode, encode_module};
+    use super::super::checksum::{emit_checksum, CHECKSUM_SECTION_LEN};
+    use ::slist::List::{Cons,Nil};
+    use ::cell::SVMCell::*;
+    use ::cell::Atom::*;
+    use ::Inst::*;
+
+    use std::io::Cursor;
+    use std::iter::repeat;
+
+    fn author() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn stranger() -> SigningKey {
+        SigningKey::from_bytes(&[9; 32])
+    }
+
+    fn trusting(key: &SigningKey) -> TrustedKeys {
+        let mut keys = TrustedKeys::new();
+        keys.trust(key.verifying_key());
+        keys
+    }
+
+    fn program() -> Module {
+        Module::new(list!(InstCell(LDC), AtomCell(SInt(1))))
+    }
+
+    #[test]
+    fn test_signed_program() {
+        let encoded = encode_signed_module(&program(), &author());
+        assert_eq!(trusting(&author()).decode_module(&mut Cursor::new(encoded.clone())),
+                   Ok(program()));
+        // signed programs can still be decoded without checking signatures
+        assert_eq!(super::super::decode_module(&mut Cursor::new(encoded)), Ok(program()));
+    }
+
+    #[test]
+    fn test_unsigned_program() {
+        let encoded = encode_module(&program());
+        assert_eq!(trusting(&author()).decode_module(&mut Cursor::new(encoded)),
+                   Err(String::from("Signature check failed: program is not signed")));
+        let mut revision_0 = vec![0x5e, 0xcd, 0x00, 0x00];
+        revision_0.extend(InstCell(NIL).emit());
+        assert_eq!(trusting(&author()).decode_module(&mut Cursor::new(revision_0)),
+                   Err(String::from("Signature check failed: program is not signed")));
+    }
+
+    #[test]
+    fn test_untrusted_key() {
+        let encoded = encode_signed_module(&program(), &stranger());
+        assert_eq!(
+            trusting(&author()).decode_module(&mut Cursor::new(encoded)),
+            Err(format!("Signature check failed: program is signed by untrusted key {}",
+                to_hex(stranger().verifying_key().as_bytes())))
+        );
+        assert!(TrustedKeys::new()
+            .decode_module(&mut Cursor::new(encode_signed_module(&program(), &author())))
+            .is_err());
+    }
+
+    #[test]
+    fn test_tampered_program() {
+        let mut encoded = encode_signed_module(&program(), &author());
+        // change the constant, and fix the checksum to match
+        encoded[24] = 2;
+        let len = encoded.len() - CHECKSUM_SECTION_LEN;
+        encoded.truncate(len);
+        let checksum = emit_checksum(&encoded);
+        encoded.extend(checksum);
+        assert_eq!(
+            trusting(&author()).decode_module(&mut Cursor::new(encoded)),
+            Err(String::from("Signature check failed: signature does not match contents"))
+        );
+    }
+
+    #[test]
+    fn test_read_keys() {
+        let hex = to_hex(author().verifying_key().as_bytes());
+        let source = format!("# release builds\n{}\n\n  {}  \n", hex, hex);
+        let keys = TrustedKeys::read(Cursor::new(source)).unwrap();
+        assert!(keys.is_trusted(&author().verifying_key()));
+        assert!(!keys.is_trusted(&stranger().verifying_key()));
+        assert_eq!(
+            TrustedKeys::read(Cursor::new("# keys\nabcd\n")).map(|_| ()),
+            Err(String::from(
+                "line 2: expected 64 hexadecimal digits, found \"abcd\""))
+        );
+        // 64 bytes, with a two-byte char straddling the first pair of digits
+        let line = format!("a\u{e9}{}", repeat("0").take(61).collect::<String>());
+        assert_eq!(line.len(), 64);
+        assert_eq!(
+            TrustedKeys::read(Cursor::new(line.clone())).map(|_| ()),
+            Err(format!("line 1: expected 64 hexadecimal digits, found {:?}", line))
+        );
+        let line = format!("+f{}", repeat("0").take(62).collect::<String>());
+        assert!(TrustedKeys::read(Cursor::new(line)).is_err());
+    }
+}
@@ -1,11 +1,17 @@
-use super::{Encode,Decoder};
-use ::cell::{Atom,Inst,SVMCell};
+use super::{Encode,Decoder,DecodeError,Encoder,Module,encode_state,decode_state,encode_module,decode_module,
+            encode_program,decode_program};
+use super::checksum::{emit_checksum,CHECKSUM_SECTION_LEN};
+use ::State;
+use ::slist::{List,Stack};
+use ::cell::{Atom,Inst,SVMCell,MapKey,SharedCell,Channel};
 use ::cell::Atom::*;
 use ::cell::SVMCell::*;
 use ::Inst::*;
 use ::slist::List::{Cons,Nil};
 
 use std::io::Cursor;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 use quickcheck::quickcheck;
 
@@ -130,7 +136,7 @@ fn test_decode_program_wrong_version () {
     let mut encoded = vec![0x5e, 0xcd, 0x10, 0x00];
     push_all!(encoded,&cell.emit());
     assert_eq!(
-        Ok(list!(cell)),
+        Err(String::from("unsupported version 4096, expected at most 1")),
         super::decode_program(&mut Cursor::new(encoded))
     )
 }
@@ -251,6 +257,258 @@ impl_encode_test!(
     test_encode_inst_stop,
     SVMCell::InstCell(Inst::STOP)
 );
+impl_encode_test!(
+    test_encode_inst_intp,
+    SVMCell::InstCell(Inst::INTP)
+);
+impl_encode_test!(
+    test_encode_inst_floatp,
+    SVMCell::InstCell(Inst::FLOATP)
+);
+impl_encode_test!(
+    test_encode_inst_charp,
+    SVMCell::InstCell(Inst::CHARP)
+);
+impl_encode_test!(
+    test_encode_inst_procp,
+    SVMCell::InstCell(Inst::PROCP)
+);
+impl_encode_test!(
+    test_encode_inst_pairp,
+    SVMCell::InstCell(Inst::PAIRP)
+);
+impl_encode_test!(
+    test_encode_inst_toint,
+    SVMCell::InstCell(Inst::TOINT)
+);
+impl_encode_test!(
+    test_encode_inst_touint,
+    SVMCell::InstCell(Inst::TOUINT)
+);
+impl_encode_test!(
+    test_encode_inst_tofloat,
+    SVMCell::InstCell(Inst::TOFLOAT)
+);
+impl_encode_test!(
+    test_encode_inst_tochar,
+    SVMCell::InstCell(Inst::TOCHAR)
+);
+impl_encode_test!(
+    test_encode_inst_sqrt,
+    SVMCell::InstCell(Inst::SQRT)
+);
+impl_encode_test!(
+    test_encode_inst_exp,
+    SVMCell::InstCell(Inst::EXP)
+);
+impl_encode_test!(
+    test_encode_inst_log,
+    SVMCell::InstCell(Inst::LOG)
+);
+impl_encode_test!(
+    test_encode_inst_sin,
+    SVMCell::InstCell(Inst::SIN)
+);
+impl_encode_test!(
+    test_encode_inst_cos,
+    SVMCell::InstCell(Inst::COS)
+);
+impl_encode_test!(
+    test_encode_inst_tan,
+    SVMCell::InstCell(Inst::TAN)
+);
+impl_encode_test!(
+    test_encode_inst_asin,
+    SVMCell::InstCell(Inst::ASIN)
+);
+impl_encode_test!(
+    test_encode_inst_acos,
+    SVMCell::InstCell(Inst::ACOS)
+);
+impl_encode_test!(
+    test_encode_inst_atan,
+    SVMCell::InstCell(Inst::ATAN)
+);
+impl_encode_test!(
+    test_encode_inst_floor,
+    SVMCell::InstCell(Inst::FLOOR)
+);
+impl_encode_test!(
+    test_encode_inst_ceil,
+    SVMCell::InstCell(Inst::CEIL)
+);
+impl_encode_test!(
+    test_encode_inst_round,
+    SVMCell::InstCell(Inst::ROUND)
+);
+impl_encode_test!(
+    test_encode_inst_nanp,
+    SVMCell::InstCell(Inst::NANP)
+);
+impl_encode_test!(
+    test_encode_inst_infp,
+    SVMCell::InstCell(Inst::INFP)
+);
+impl_encode_test!(
+    test_encode_inst_pow,
+    SVMCell::InstCell(Inst::POW)
+);
+impl_encode_test!(
+    test_encode_inst_atan2,
+    SVMCell::InstCell(Inst::ATAN2)
+);
+impl_encode_test!(
+    test_encode_inst_vmake,
+    SVMCell::InstCell(Inst::VMAKE)
+);
+impl_encode_test!(
+    test_encode_inst_vget,
+    SVMCell::InstCell(Inst::VGET)
+);
+impl_encode_test!(
+    test_encode_inst_vset,
+    SVMCell::InstCell(Inst::VSET)
+);
+impl_encode_test!(
+    test_encode_inst_vlen,
+    SVMCell::InstCell(Inst::VLEN)
+);
+impl_encode_test!(
+    test_encode_inst_vtol,
+    SVMCell::InstCell(Inst::VTOL)
+);
+impl_encode_test!(
+    test_encode_inst_ltov,
+    SVMCell::InstCell(Inst::LTOV)
+);
+impl_encode_test!(
+    test_encode_inst_blen,
+    SVMCell::InstCell(Inst::BLEN)
+);
+impl_encode_test!(
+    test_encode_inst_bget,
+    SVMCell::InstCell(Inst::BGET)
+);
+impl_encode_test!(
+    test_encode_inst_bslice,
+    SVMCell::InstCell(Inst::BSLICE)
+);
+impl_encode_test!(
+    test_encode_inst_bcat,
+    SVMCell::InstCell(Inst::BCAT)
+);
+impl_encode_test!(
+    test_encode_inst_stob,
+    SVMCell::InstCell(Inst::STOB)
+);
+impl_encode_test!(
+    test_encode_inst_btos,
+    SVMCell::InstCell(Inst::BTOS)
+);
+impl_encode_test!(
+    test_encode_inst_readb,
+    SVMCell::InstCell(Inst::READB)
+);
+impl_encode_test!(
+    test_encode_inst_writeb,
+    SVMCell::InstCell(Inst::WRITEB)
+);
+impl_encode_test!(
+    test_encode_inst_mnew,
+    SVMCell::InstCell(Inst::MNEW)
+);
+impl_encode_test!(
+    test_encode_inst_mput,
+    SVMCell::InstCell(Inst::MPUT)
+);
+impl_encode_test!(
+    test_encode_inst_mget,
+    SVMCell::InstCell(Inst::MGET)
+);
+impl_encode_test!(
+    test_encode_inst_mhas,
+    SVMCell::InstCell(Inst::MHAS)
+);
+impl_encode_test!(
+    test_encode_inst_mdel,
+    SVMCell::InstCell(Inst::MDEL)
+);
+impl_encode_test!(
+    test_encode_inst_mkeys,
+    SVMCell::InstCell(Inst::MKEYS)
+);
+impl_encode_test!(
+    test_encode_inst_mlen,
+    SVMCell::InstCell(Inst::MLEN)
+);
+impl_encode_test!(
+    test_encode_inst_try,
+    SVMCell::InstCell(Inst::TRY)
+);
+impl_encode_test!(
+    test_encode_inst_endtry,
+    SVMCell::InstCell(Inst::ENDTRY)
+);
+impl_encode_test!(
+    test_encode_inst_throw,
+    SVMCell::InstCell(Inst::THROW)
+);
+impl_encode_test!(
+    test_encode_inst_calln,
+    SVMCell::InstCell(Inst::CALLN)
+);
+impl_encode_test!(
+    test_encode_inst_ldg,
+    SVMCell::InstCell(Inst::LDG)
+);
+impl_encode_test!(
+    test_encode_inst_stg,
+    SVMCell::InstCell(Inst::STG)
+);
+impl_encode_test!(
+    test_encode_inst_box,
+    SVMCell::InstCell(Inst::BOX)
+);
+impl_encode_test!(
+    test_encode_inst_unbox,
+    SVMCell::InstCell(Inst::UNBOX)
+);
+impl_encode_test!(
+    test_encode_inst_setbox,
+    SVMCell::InstCell(Inst::SETBOX)
+);
+impl_encode_test!(
+    test_encode_inst_retn,
+    SVMCell::InstCell(Inst::RETN)
+);
+impl_encode_test!(
+    test_encode_inst_recvn,
+    SVMCell::InstCell(Inst::RECVN)
+);
+impl_encode_test!(
+    test_encode_inst_spawn,
+    SVMCell::InstCell(Inst::SPAWN)
+);
+impl_encode_test!(
+    test_encode_inst_yield,
+    SVMCell::InstCell(Inst::YIELD)
+);
+impl_encode_test!(
+    test_encode_inst_wait,
+    SVMCell::InstCell(Inst::WAIT)
+);
+impl_encode_test!(
+    test_encode_inst_chan,
+    SVMCell::InstCell(Inst::CHAN)
+);
+impl_encode_test!(
+    test_encode_inst_send,
+    SVMCell::InstCell(Inst::SEND)
+);
+impl_encode_test!(
+    test_encode_inst_recv,
+    SVMCell::InstCell(Inst::RECV)
+);
 impl_encode_test!(
     test_encode_simple_program,
     list_cell![
@@ -346,3 +604,595 @@ impl_encode_test!(
         InstCell(ADD)
     ]
 );
+
+impl_encode_test!(
+    test_encode_vector,
+    SVMCell::VectorCell(Arc::new(vec![
+        AtomCell(SInt(1)),
+        InstCell(LDC),
+        list_cell![ AtomCell(Char('a')), AtomCell(Float(1.5)) ],
+        SVMCell::VectorCell(Arc::new(vec![]))
+    ]))
+);
+
+impl_encode_test!(
+    test_encode_bytes,
+    SVMCell::BytesCell(vec![0x00, 0x7F, 0xC6, 0xFF])
+);
+
+impl_encode_test!(
+    test_encode_map,
+    {
+        let mut map = BTreeMap::new();
+        map.insert(MapKey::SInt(-1), AtomCell(Char('a')));
+        map.insert(MapKey::Float(1.5f64.to_bits()), list_cell![ AtomCell(UInt(2)) ]);
+        map.insert(MapKey::Str(String::from("key")), InstCell(MNEW));
+        SVMCell::MapCell(map)
+    }
+);
+
+impl_encode_test!(
+    test_encode_native,
+    SVMCell::NativeCell(String::from("string-length"))
+);
+
+impl_encode_test!(
+    test_encode_global,
+    SVMCell::GlobalCell(String::from("*counter*"))
+);
+
+impl_encode_test!(
+    test_encode_box,
+    SVMCell::BoxCell(SharedCell::new(list_cell![
+        AtomCell(SInt(1)),
+        SVMCell::BoxCell(SharedCell::new(AtomCell(Char('a'))))
+    ]))
+);
+
+#[test]
+fn test_encode_box_cycle() {
+    // a box which contains itself, as made by BOX and then SETBOX
+    let cyclic = SharedCell::new(list_cell![]);
+    cyclic.set(BoxCell(cyclic.clone()));
+    let other = SharedCell::new(AtomCell(SInt(1)));
+    let list = list!(BoxCell(other.clone()), BoxCell(cyclic.clone()), BoxCell(other.clone()));
+    let encoded = ListCell(Box::new(list.clone())).emit();
+    assert_eq!(list.emit(), encoded);
+    match Decoder::new(&mut Cursor::new(encoded)).next_cell() {
+        Ok(Some(ListCell(box Cons(BoxCell(ref a),
+                         box Cons(BoxCell(ref b),
+                         box Cons(BoxCell(ref c), box Nil)))))) => {
+            assert!(a.ptr_eq(c));
+            assert_eq!(a.get(), AtomCell(SInt(1)));
+            match b.get() {
+                BoxCell(ref inner) => assert!(inner.ptr_eq(b)),
+                thing => panic!("expected cyclic box, found {:?}", thing)
+            }
+            // break the cycle so the box can be dropped
+            b.set(list_cell![]);
+        },
+        thing => panic!("expected boxes, found {:?}", thing)
+    }
+    cyclic.set(list_cell![]);
+}
+
+#[test]
+fn test_encode_channel() {
+    let channel = Channel::new();
+    channel.send(AtomCell(SInt(1)));
+    channel.send(list_cell![ AtomCell(Char('a')) ]);
+    let encoded = SVMCell::ChannelCell(channel.clone()).emit();
+    match Decoder::new(&mut Cursor::new(encoded)).next_cell() {
+        Ok(Some(SVMCell::ChannelCell(decoded))) => {
+            assert!(!decoded.ptr_eq(&channel));
+            assert_eq!(decoded.pending(), channel.pending());
+        },
+        thing => panic!("expected channel, found {:?}", thing)
+    }
+}
+
+impl_encode_test!(
+    test_encode_empty_list,
+    list_cell![ list_cell![], InstCell(NIL), list_cell![ list_cell![] ] ]
+);
+
+/// Evaluates `program` for `steps` steps, returning the state reached.
+fn run_for(program: List<SVMCell>, steps: usize) -> State {
+    let mut state = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: program,
+        dump: Stack::empty()
+    };
+    for _ in 0..steps {
+        state = state.eval(None, true).unwrap().0;
+    }
+    state
+}
+
+/// Evaluates a state until its control stack is empty.
+fn run_to_end(mut state: State) -> List<SVMCell> {
+    while state.control.peek().is_some() {
+        state = state.eval(None, true).unwrap().0;
+    }
+    state.stack
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    // ((lambda (x) (try (+ x 2.5) (lambda (e) 0))) 1), snapshotted after
+    // each step, including inside the closure and the body of the try
+    let program = list!(
+        InstCell(NIL),
+        InstCell(LDC), AtomCell(SInt(1)), InstCell(CONS),
+        InstCell(LDF),
+        list_cell![
+            InstCell(TRY),
+                list_cell![ InstCell(LDC), AtomCell(SInt(0)), InstCell(JOIN) ],
+                list_cell![
+                    InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+                    InstCell(LDC), AtomCell(Float(2.5)),
+                    InstCell(ADD),
+                    InstCell(ENDTRY)
+                ],
+            InstCell(RET)
+        ],
+        InstCell(AP),
+        InstCell(LDC), AtomCell(Char('x'))
+    );
+    for steps in 0..10 {
+        let state = run_for(program.clone(), steps);
+        let restored = decode_state(&mut Cursor::new(encode_state(&state)));
+        assert_eq!(restored.as_ref(), Ok(&state));
+        assert_eq!(run_to_end(restored.unwrap()), run_to_end(state));
+    }
+}
+
+#[test]
+fn test_snapshot_shared_boxes() {
+    let shared = SharedCell::new(AtomCell(SInt(1)));
+    let cyclic = SharedCell::new(list_cell![]);
+    cyclic.set(list_cell![ BoxCell(cyclic.clone()) ]);
+    let state = State {
+        stack: list!(BoxCell(shared.clone()), BoxCell(cyclic.clone())),
+        env: list!(list_cell![ BoxCell(shared.clone()) ]),
+        control: list!(InstCell(SETBOX)),
+        dump: Stack::empty()
+    };
+    let restored = decode_state(&mut Cursor::new(encode_state(&state))).unwrap();
+    // a box which contains itself is only equal to itself, so compare
+    // everything but the cyclic box
+    assert_eq!(restored.stack[0u64], state.stack[0u64]);
+    assert_eq!(restored.env, state.env);
+    assert_eq!(restored.control, state.control);
+    match (restored.stack.peek(), restored.env.peek()) {
+        (Some(&BoxCell(ref a)), Some(&ListCell(box Cons(BoxCell(ref b), _)))) => {
+            assert!(a.ptr_eq(b));
+            assert!(!a.ptr_eq(&shared));
+        },
+        thing => panic!("expected boxes, found {:?}", thing)
+    }
+    match restored.stack[1u64] {
+        BoxCell(ref restored) => match restored.get() {
+            ListCell(box Cons(BoxCell(ref inner), _)) => assert!(inner.ptr_eq(restored)),
+            thing => panic!("expected cyclic box, found {:?}", thing)
+        },
+        ref thing => panic!("expected box, found {:?}", thing)
+    }
+    // break the cycle so the box can be dropped
+    cyclic.set(list_cell![]);
+}
+
+#[test]
+fn test_snapshot_shared_channels() {
+    let shared = Channel::new();
+    shared.send(AtomCell(SInt(1)));
+    // a channel with itself waiting on it
+    let cyclic = Channel::new();
+    cyclic.send(ChannelCell(cyclic.clone()));
+    let state = State {
+        stack: list!(ChannelCell(shared.clone()), ChannelCell(cyclic.clone())),
+        env: list!(list_cell![ ChannelCell(shared.clone()) ]),
+        control: list!(InstCell(RECV)),
+        dump: Stack::empty()
+    };
+    let restored = decode_state(&mut Cursor::new(encode_state(&state))).unwrap();
+    assert_eq!(restored.control, state.control);
+    match (restored.stack.peek(), restored.env.peek()) {
+        (Some(&ChannelCell(ref a)), Some(&ListCell(box Cons(ChannelCell(ref b), _)))) => {
+            assert!(a.ptr_eq(b));
+            assert!(!a.ptr_eq(&shared));
+            assert_eq!(a.pending(), vec![AtomCell(SInt(1))]);
+            // a value sent on one copy is received on the other
+            a.send(AtomCell(SInt(2)));
+            assert_eq!(b.len(), 2);
+        },
+        thing => panic!("expected channels, found {:?}", thing)
+    }
+    match restored.stack[1u64] {
+        ChannelCell(ref restored) => match restored.pending().pop() {
+            Some(ChannelCell(ref inner)) => assert!(inner.ptr_eq(restored)),
+            thing => panic!("expected cyclic channel, found {:?}", thing)
+        },
+        ref thing => panic!("expected channel, found {:?}", thing)
+    }
+    // break the cycles so the channels can be dropped
+    cyclic.recv();
+    if let ChannelCell(ref restored) = restored.stack[1u64] {
+        restored.recv();
+    }
+}
+
+#[test]
+fn test_decode_state_not_snapshot() {
+    let mut bytes = encode_state(&State::new());
+    bytes[6] = 0xD1;
+    reseal(&mut bytes);
+    assert_eq!(
+        decode_state(&mut Cursor::new(bytes)),
+        Err(String::from("Expected state snapshot section, found 0xd1"))
+    );
+    let program = encode_module(&Module::new(list!(InstCell(NIL))));
+    assert_eq!(
+        decode_state(&mut Cursor::new(program)),
+        Err(String::from("Expected state snapshot section, found 0xd1"))
+    );
+}
+
+#[test]
+fn test_decode_state_revision_0() {
+    let state = run_for(list!(
+        InstCell(LDC), AtomCell(SInt(1)),
+        InstCell(LDC), AtomCell(SInt(2)),
+        InstCell(ADD)
+    ), 2);
+    let mut bytes = vec![0x5e, 0xcd, 0x00, 0x00, 0xD0];
+    for register in &[&state.stack, &state.env, &state.control, &state.dump] {
+        push_all!(bytes, &ListCell(Box::new((*register).clone())).emit());
+    }
+    assert_eq!(decode_state(&mut Cursor::new(bytes)), Ok(state));
+}
+
+#[test]
+fn test_decode_state_bad_register() {
+    let mut bytes = vec![0x5e, 0xcd, 0x00, 0x00, 0xD0];
+    push_all!(bytes, &AtomCell(UInt(1)).emit());
+    assert_eq!(
+        decode_state(&mut Cursor::new(bytes)),
+        Err(String::from("expected the stack of a state snapshot at offset 5, found 0xc1"))
+    );
+}
+
+/// Recomputes the checksum of a file which has been modified.
+fn reseal(bytes: &mut Vec<u8>) {
+    let len = bytes.len() - CHECKSUM_SECTION_LEN;
+    bytes.truncate(len);
+    let checksum = emit_checksum(&bytes);
+    push_all!(bytes, &checksum[..]);
+}
+
+/// Builds a module with every kind of metadata.
+fn module() -> Module {
+    let greeting = list_cell![
+        AtomCell(Char('h')), AtomCell(Char('e')), AtomCell(Char('l')),
+        AtomCell(Char('l')), AtomCell(Char('o'))
+    ];
+    Module {
+        code: list!(
+            InstCell(LDC), greeting.clone(),
+            InstCell(LDC), greeting.clone(),
+            InstCell(CONS),
+            InstCell(LDG), GlobalCell(String::from("count")),
+            InstCell(CALLN), NativeCell(String::from("print"))
+        ),
+        constants: vec![greeting.clone()],
+        symbols: vec![
+            (String::from("count"), AtomCell(UInt(2))),
+            (String::from("greeting"), greeting)
+        ],
+        debug: vec![(String::from("source"), BytesCell(b"hello.scm".to_vec()))],
+        imports: vec![String::from("print")],
+        custom: vec![(String::from("notes"), vec![1, 2, 3])]
+    }
+}
+
+#[test]
+fn test_module_round_trip() {
+    let module = module();
+    let encoded = encode_module(&module);
+    assert_eq!(&encoded[..4], &[0x5e, 0xcd, 0x00, 0x01]);
+    assert_eq!(decode_module(&mut Cursor::new(encoded.clone())), Ok(module.clone()));
+    assert_eq!(super::decode_program(&mut Cursor::new(encoded)), Ok(module.code));
+}
+
+#[test]
+fn test_module_constant_pool() {
+    let pooled = encode_module(&module());
+    let unpooled = encode_module(&Module { constants: Vec::new(), ..module() });
+    assert!(pooled.len() < unpooled.len());
+    assert_eq!(
+        decode_module(&mut Cursor::new(unpooled)).map(|module| module.code),
+        Ok(module().code)
+    );
+}
+
+#[test]
+fn test_module_constant_pool_exact() {
+    let shared = list_cell![BoxCell(SharedCell::new(AtomCell(SInt(1))))];
+    let module = Module {
+        code: list!(
+            InstCell(LDC), AtomCell(Float(-0.0)),
+            InstCell(LDC), list_cell![BoxCell(SharedCell::new(AtomCell(SInt(1))))]
+        ),
+        constants: vec![AtomCell(Float(0.0)), shared],
+        ..Module::new(Nil)
+    };
+    let encoded = encode_module(&module);
+    let decoded = decode_module(&mut Cursor::new(encoded)).unwrap();
+    match decoded.code.get(1) {
+        Some(&AtomCell(Float(f))) => assert!(f == 0.0 && f.is_sign_negative()),
+        other => panic!("expected -0.0, got {:?}", other)
+    }
+    let boxed = |cell: Option<&SVMCell>| match cell {
+        Some(&ListCell(box Cons(BoxCell(ref shared), _))) => shared.clone(),
+        other => panic!("expected a list holding a box, got {:?}", other)
+    };
+    boxed(decoded.code.get(3)).set(AtomCell(SInt(2)));
+    assert_eq!(boxed(decoded.constants.get(1)).get(), AtomCell(SInt(1)));
+}
+
+#[test]
+fn test_module_only_code() {
+    let module = Module::new(list!(InstCell(LDC), AtomCell(SInt(1))));
+    let encoded = encode_module(&module);
+    // preamble, two sections, then the code section's type and length
+    assert_eq!(&encoded[..15], &[
+        0x5e, 0xcd, 0x00, 0x01,
+        0x00, 0x02,
+        0xD1, 0, 0, 0, 0, 0, 0, 0, 10
+    ]);
+    // the checksum section
+    assert_eq!(&encoded[25..34], &[0xD6, 0, 0, 0, 0, 0, 0, 0, 4]);
+    assert_eq!(encoded.len(), 38);
+    assert_eq!(decode_module(&mut Cursor::new(encoded)), Ok(module));
+}
+
+#[test]
+fn test_decode_module_revision_0() {
+    let program = list!(InstCell(LDC), AtomCell(SInt(1)), InstCell(NIL));
+    let mut encoded = vec![0x5e, 0xcd, 0x00, 0x00];
+    for cell in program.iter() {
+        push_all!(encoded, &cell.emit());
+    }
+    assert_eq!(decode_module(&mut Cursor::new(encoded)), Ok(Module::new(program)));
+}
+
+#[test]
+fn test_decode_bad_section_len() {
+    // the four registers of an empty state, then a byte of padding
+    let mut bytes = encode_state(&State::new());
+    bytes.insert(19, 0x00);
+    bytes[14] = 5;
+    reseal(&mut bytes);
+    assert_eq!(
+        decode_state(&mut Cursor::new(bytes)),
+        Err(String::from("Section 0xd0 should be 5 bytes long, but 4 were read"))
+    );
+}
+
+#[test]
+fn test_decode_module_duplicate_section() {
+    let mut encoded = encode_module(&Module::new(list!(InstCell(NIL))));
+    let end = encoded.len() - CHECKSUM_SECTION_LEN;
+    let section = encoded[6..end].to_vec();
+    encoded[5] = 3;
+    encoded.truncate(end);
+    push_all!(encoded, &section[..]);
+    push_all!(encoded, &[0; CHECKSUM_SECTION_LEN][..]);
+    reseal(&mut encoded);
+    assert_eq!(
+        decode_module(&mut Cursor::new(encoded)),
+        Err(String::from("Duplicate section 0xd1"))
+    );
+}
+
+#[test]
+fn test_decode_undefined_constant() {
+    let encoded = vec![0xCE, 0, 0, 0, 0, 0, 0, 0, 3];
+    assert_eq!(
+        Decoder::new(&mut Cursor::new(encoded)).next_cell(),
+        Err(DecodeError {
+            offset: 0,
+            byte: Some(0xCE),
+            expected: String::from("a reference to one of the 0 constants in the pool")
+        })
+    );
+}
+
+#[test]
+fn test_decode_undefined_channel() {
+    let encoded = vec![0xCF, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(
+        Decoder::new(&mut Cursor::new(encoded)).next_cell(),
+        Err(DecodeError {
+            offset: 0,
+            byte: Some(0xCF),
+            expected: String::from("a reference to one of the 0 channels decoded so far")
+        })
+    );
+}
+
+#[test]
+fn test_decode_error_position() {
+    let mut encoded = AtomCell(UInt(1)).emit();
+    encoded.push(0xE0);
+    encoded.extend(AtomCell(UInt(2)).emit());
+    let mut cursor = Cursor::new(encoded);
+    let mut decoder = Decoder::new(&mut cursor);
+    assert_eq!(decoder.next(), Some(Ok(AtomCell(UInt(1)))));
+    let error = decoder.next().unwrap().unwrap_err();
+    assert_eq!(error, DecodeError { offset: 9, byte: Some(0xE0), expected: String::from("a cell") });
+    assert_eq!(error.to_string(), "expected a cell at offset 9, found 0xe0");
+    // nothing more is decoded after an error
+    assert_eq!(decoder.next(), None);
+}
+
+#[test]
+fn test_decode_error_truncated() {
+    let mut encoded = list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ].emit();
+    let len = encoded.len() - 4;
+    encoded.truncate(len);
+    let error = Decoder::new(&mut Cursor::new(encoded)).next().unwrap().unwrap_err();
+    assert_eq!(error.offset, len);
+    assert_eq!(error.byte, None);
+    assert_eq!(error.to_string(),
+               format!("expected a sint at offset {}, found end of file", len));
+}
+
+#[test]
+fn test_decode_error_in_list() {
+    let mut encoded = list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ].emit();
+    let i = encoded.iter().rposition(|&b| b == 0xC0).unwrap();
+    encoded[i] = 0x42;
+    assert_eq!(
+        Decoder::new(&mut Cursor::new(encoded)).next(),
+        Some(Err(DecodeError {
+            offset: i,
+            byte: Some(0x42),
+            expected: String::from("a CONS cell or nil")
+        }))
+    );
+}
+
+#[test]
+fn test_decode_program_error() {
+    let mut encoded = vec![0x5e, 0xcd, 0x00, 0x00];
+    encoded.extend(InstCell(NIL).emit());
+    encoded.push(0x5D);
+    assert_eq!(decode_program(&mut Cursor::new(encoded)),
+               Err(String::from("expected an instruction at offset 5, found 0x5d")));
+}
+
+#[test]
+fn test_decode_module_corrupted() {
+    let encoded = encode_module(&module());
+    for &i in &[2, 5, 6, 20, encoded.len() / 2, encoded.len() - 1] {
+        let mut corrupted = encoded.clone();
+        corrupted[i] ^= 0x40;
+        let why = decode_module(&mut Cursor::new(corrupted)).unwrap_err();
+        assert!(why.starts_with("Integrity check failed") ||
+                why.starts_with("unsupported version"), "{}: {}", i, why);
+    }
+}
+
+#[test]
+fn test_decode_module_truncated() {
+    let encoded = encode_module(&module());
+    for len in 6..encoded.len() {
+        let why = decode_module(&mut Cursor::new(&encoded[..len])).unwrap_err();
+        assert!(why.starts_with("Integrity check failed"), "{}: {}", len, why);
+    }
+    let mut extended = encoded.clone();
+    extended.push(0);
+    assert_eq!(
+        decode_module(&mut Cursor::new(extended)),
+        Err(String::from("Integrity check failed: 1 unexpected bytes after checksum"))
+    );
+}
+
+#[test]
+fn test_decode_state_corrupted() {
+    let mut bytes = encode_state(&State::new());
+    bytes[16] = 0x00;
+    assert!(decode_state(&mut Cursor::new(bytes)).unwrap_err()
+        .starts_with("Integrity check failed: checksum is"));
+}
+
+#[test]
+fn test_encoder() {
+    let shared = SharedCell::new(AtomCell(SInt(1)));
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut bytes);
+        encoder.write_preamble().unwrap();
+        assert_eq!(encoder.num_written(), 4);
+        encoder.encode_cell(&AtomCell(UInt(1))).unwrap();
+        encoder.encode_cell(&BoxCell(shared.clone())).unwrap();
+        encoder.encode_cell(&BoxCell(shared.clone())).unwrap();
+        assert_eq!(encoder.num_written(), 4 + 9 + 10 + 9);
+    }
+    assert_eq!(&bytes[..4], &[0x5e, 0xcd, 0x00, 0x01]);
+    let mut cursor = Cursor::new(&bytes[4..]);
+    let mut decoder = Decoder::new(&mut cursor);
+    assert_eq!(decoder.next_cell(), Ok(Some(AtomCell(UInt(1)))));
+    match (decoder.next_cell(), decoder.next_cell()) {
+        (Ok(Some(BoxCell(ref a))), Ok(Some(BoxCell(ref b)))) => {
+            assert!(a.ptr_eq(b));
+            assert_eq!(a.get(), AtomCell(SInt(1)));
+        },
+        thing => panic!("expected boxes, found {:?}", thing)
+    }
+}
+
+#[test]
+fn test_encode_program() {
+    let program = module().code;
+    let mut bytes = Vec::new();
+    let written = encode_program(&program, &mut bytes).unwrap();
+    assert_eq!(written, bytes.len());
+    assert_eq!(bytes, encode_module(&Module::new(program.clone())));
+    assert_eq!(decode_program(&mut Cursor::new(bytes)), Ok(program));
+}
+
+#[test]
+fn test_encode_long_program() {
+    let program = (0..2000).map(|i| AtomCell(UInt(i))).collect::<List<SVMCell>>();
+    let mut bytes = Vec::new();
+    encode_program(&program, &mut bytes).unwrap();
+    assert_eq!(decode_program(&mut Cursor::new(bytes)), Ok(program.clone()));
+    let cell = ListCell(Box::new(program));
+    assert_eq!(Decoder::new(&mut Cursor::new(cell.emit())).next_cell(), Ok(Some(cell)));
+}
+
+/// A sink which changes the contents of a box when it is first written to.
+struct ChangingSink {
+    bytes: Vec<u8>,
+    shared: SharedCell
+}
+
+impl ::std::io::Write for ChangingSink {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.shared.set(list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ]);
+        self.bytes.write(buf)
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_encode_program_changing_box() {
+    let shared = SharedCell::new(AtomCell(SInt(0)));
+    let program = list!(InstCell(LDC), BoxCell(shared.clone()));
+    let mut sink = ChangingSink { bytes: Vec::new(), shared: shared.clone() };
+    encode_program(&program, &mut sink).unwrap();
+    assert_eq!(
+        decode_program(&mut Cursor::new(sink.bytes)),
+        Ok(list!(InstCell(LDC), BoxCell(SharedCell::new(AtomCell(SInt(0))))))
+    );
+}
+
+#[test]
+fn test_encoder_preamble() {
+    let mut bytes = Vec::new();
+    Encoder::new(&mut bytes).write_preamble().unwrap();
+    assert_eq!(bytes, vec![0x5e, 0xcd, 0x00, 0x01]);
+}
+
+#[test]
+fn test_encode_program_write_error() {
+    let program = list!(InstCell(LDC), AtomCell(SInt(1)));
+    let mut buf = [0; 8];
+    assert!(encode_program(&program, &mut &mut buf[..]).is_err());
+}
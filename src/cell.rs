@@ -3,7 +3,10 @@ pub use self::Atom::*;
 
 use ::slist::List;
 
-use std::{fmt,ops};
+use std::{fmt,ops,char};
+use std::cell::RefCell;
+use std::collections::{BTreeMap,VecDeque};
+use std::sync::{Arc,Mutex,MutexGuard};
 
 #[macro_export]
 #[cfg_attr(feature = "nightly", unstable(feature = "list"))]
@@ -26,7 +29,46 @@ pub enum SVMCell {
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.1.0"))]
     ListCell(Box<List<SVMCell>>),
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.1.0"))]
-    InstCell(Inst)
+    InstCell(Inst),
+    /// A vector of cells, supporting constant-time indexed access.
+    ///
+    /// Copies of a vector share its items until one of them is changed.
+    #[cfg_attr(feature = "nightly", unstable(feature="vector"))]
+    VectorCell(Arc<Vec<SVMCell>>),
+    /// A buffer of raw bytes, for working with binary data.
+    #[cfg_attr(feature = "nightly", unstable(feature="bytes"))]
+    BytesCell(Vec<u8>),
+    /// A map from keys to cells.
+    ///
+    /// Maps are values, like every other cell: updating a map produces a
+    /// new map, leaving any other copies of it (such as those captured
+    /// by closures) unaffected.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    MapCell(BTreeMap<MapKey, SVMCell>),
+    /// A reference, by name, to a native function bound by the host.
+    ///
+    /// Native functions are resolved when they are called, so a program
+    /// containing native references may be run by any machine which binds
+    /// functions under the same names.
+    #[cfg_attr(feature = "nightly", unstable(feature="native"))]
+    NativeCell(String),
+    /// A reference, by name, to a global definition, for use with the
+    /// `LDG` and `STG` instructions.
+    #[cfg_attr(feature = "nightly", unstable(feature="globals"))]
+    GlobalCell(String),
+    /// A mutable box holding a single cell.
+    ///
+    /// Unlike every other cell, boxes are shared rather than copied:
+    /// all copies of a box refer to the same contents, so a value stored
+    /// with `SETBOX` is seen through any other copy of the box, including
+    /// those captured by closures.
+    #[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+    BoxCell(SharedCell),
+    /// A channel, on which tasks and the host may send each other values.
+    ///
+    /// Like boxes, channels are shared rather than copied.
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    ChannelCell(Channel)
 }
 
 #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.1.0"))]
@@ -43,7 +85,292 @@ impl fmt::Debug for SVMCell {
         match self {
             &AtomCell(atom) => write!(f, "{:?}", atom),
             &ListCell(ref list) => write!(f, "{:?}", list),
-            &InstCell(inst) => write!(f, "{:?}", inst)
+            &InstCell(inst) => write!(f, "{:?}", inst),
+            &VectorCell(ref vec) => {
+                try!(write!(f, "#("));
+                for (i, cell) in vec.iter().enumerate() {
+                    if i > 0 { try!(write!(f, " ")); }
+                    try!(write!(f, "{:?}", cell));
+                }
+                write!(f, ")")
+            },
+            &BytesCell(ref bytes) => {
+                try!(write!(f, "#u8("));
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i > 0 { try!(write!(f, " ")); }
+                    try!(write!(f, "{}", byte));
+                }
+                write!(f, ")")
+            },
+            &MapCell(ref map) => {
+                try!(write!(f, "{{"));
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 { try!(write!(f, ", ")); }
+                    try!(write!(f, "{:?} => {:?}", key, value));
+                }
+                write!(f, "}}")
+            },
+            &NativeCell(ref name) => write!(f, "#<native {}>", name),
+            &GlobalCell(ref name) => write!(f, "#<global {}>", name),
+            &BoxCell(ref shared) => write!(f, "{:?}", shared),
+            &ChannelCell(ref channel) => write!(f, "{:?}", channel)
+        }
+    }
+}
+
+/// The contents of a box cell, shared between every copy of the box.
+#[derive(Clone)]
+#[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+pub struct SharedCell(Arc<Mutex<SVMCell>>);
+
+#[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+impl SharedCell {
+    /// Creates a new box containing `value`.
+    #[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+    pub fn new(value: SVMCell) -> SharedCell {
+        SharedCell(Arc::new(Mutex::new(value)))
+    }
+
+    /// Returns a copy of the contents of the box.
+    #[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+    pub fn get(&self) -> SVMCell {
+        self.lock().clone()
+    }
+
+    /// Replaces the contents of the box, returning the previous contents.
+    #[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+    pub fn set(&self, value: SVMCell) -> SVMCell {
+        ::std::mem::replace(&mut *self.lock(), value)
+    }
+
+    /// Returns true if `self` and `other` are copies of the same box.
+    #[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+    pub fn ptr_eq(&self, other: &SharedCell) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Returns the address of the box, which is the same for every copy.
+    fn addr(&self) -> usize {
+        &*self.0 as *const Mutex<SVMCell> as usize
+    }
+
+    /// Locks the contents of the box, even if another thread panicked
+    /// while holding its lock.
+    fn lock(&self) -> MutexGuard<SVMCell> {
+        match self.0.lock() {
+            Ok(contents) => contents,
+            Err(poisoned) => poisoned.into_inner()
+        }
+    }
+}
+
+/// Two boxes are equal if they are the same box, or if their contents
+/// are equal. A box which (indirectly) contains itself is only equal to
+/// itself. Comparing boxes waits for any other thread using them.
+#[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+impl PartialEq for SharedCell {
+    fn eq(&self, other: &SharedCell) -> bool {
+        if self.ptr_eq(other) { return true; }
+        let (a, b) = (self.addr(), other.addr());
+        let contains_itself = COMPARING.with(|comparing| {
+            let mut comparing = comparing.borrow_mut();
+            if comparing.contains(&a) || comparing.contains(&b) {
+                true
+            } else {
+                comparing.push(a);
+                comparing.push(b);
+                false
+            }
+        });
+        if contains_itself { return false; }
+        // the contents are copied out so that no lock is held while they
+        // are compared, and other threads may still use the boxes
+        let equal = self.get() == other.get();
+        COMPARING.with(|comparing| {
+            let mut comparing = comparing.borrow_mut();
+            comparing.pop();
+            comparing.pop();
+        });
+        equal
+    }
+}
+
+thread_local! {
+    /// The addresses of the boxes whose contents are being compared on
+    /// this thread, used to find boxes which contain themselves.
+    static COMPARING: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+    /// The addresses of the boxes whose contents are being printed on
+    /// this thread, used to find boxes which contain themselves.
+    static PRINTING: RefCell<Vec<usize>> = RefCell::new(Vec::new())
+}
+
+/// A queue of values sent between tasks, or between tasks and the host.
+///
+/// Values are received in the order in which they were sent. Every copy
+/// of a channel refers to the same queue, so the host may keep a copy of
+/// a channel it passes to a program, and use it to communicate with the
+/// program while it runs.
+#[derive(Clone)]
+#[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+pub struct Channel(Arc<Mutex<VecDeque<SVMCell>>>);
+
+#[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+impl Channel {
+    /// Creates a new, empty channel.
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    pub fn new() -> Channel {
+        Channel(Arc::new(Mutex::new(VecDeque::new())))
+    }
+
+    /// Sends a value on the channel.
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    pub fn send(&self, value: SVMCell) {
+        self.lock().push_back(value)
+    }
+
+    /// Receives the next value sent on the channel, or returns `None` if
+    /// the channel is empty.
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    pub fn recv(&self) -> Option<SVMCell> {
+        self.lock().pop_front()
+    }
+
+    /// Returns the number of values waiting to be received.
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns true if there are no values waiting to be received.
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the values waiting to be received, without receiving them.
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    pub fn pending(&self) -> Vec<SVMCell> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Returns true if `self` and `other` are copies of the same channel.
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    pub fn ptr_eq(&self, other: &Channel) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Locks the queue, even if another thread panicked while holding
+    /// its lock.
+    fn lock(&self) -> MutexGuard<VecDeque<SVMCell>> {
+        match self.0.lock() {
+            Ok(queue) => queue,
+            Err(poisoned) => poisoned.into_inner()
+        }
+    }
+}
+
+/// Channels are only equal to copies of themselves.
+#[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+impl PartialEq for Channel {
+    fn eq(&self, other: &Channel) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<channel {}>", self.len())
+    }
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+impl fmt::Debug for SharedCell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let addr = self.addr();
+        let contains_itself = PRINTING.with(|printing| {
+            let mut printing = printing.borrow_mut();
+            if printing.contains(&addr) {
+                true
+            } else {
+                printing.push(addr);
+                false
+            }
+        });
+        // the box is already being printed further up, so it contains itself
+        if contains_itself { return write!(f, "#&..."); }
+        let result = write!(f, "#&{:?}", self.get());
+        PRINTING.with(|printing| { printing.borrow_mut().pop(); });
+        result
+    }
+}
+
+/// A key in a map cell.
+///
+/// Maps may be keyed by atoms or by strings (lists of chars). Unlike atoms,
+/// keys are totally ordered, so float keys are compared by their bit
+/// patterns rather than numerically; `0.0` and `-0.0` are the same key.
+#[derive(PartialEq,Eq,PartialOrd,Ord,Clone)]
+#[cfg_attr(feature = "nightly", unstable(feature="map"))]
+pub enum MapKey {
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    UInt(u64),
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    SInt(i64),
+    /// A float key, stored as its bit pattern
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    Float(u64),
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    Char(char),
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    Str(String)
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="map"))]
+impl MapKey {
+    /// Converts a cell to a map key.
+    ///
+    /// Returns an error if the cell is neither an atom nor a string.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    pub fn from_cell(cell: &SVMCell) -> Result<MapKey, String> {
+        match *cell {
+            AtomCell(UInt(a))  => Ok(MapKey::UInt(a)),
+            AtomCell(SInt(a))  => Ok(MapKey::SInt(a)),
+            AtomCell(Float(a)) => Ok(MapKey::Float(
+                if a == 0.0 { 0.0f64 } else { a }.to_bits())),
+            AtomCell(Char(a))  => Ok(MapKey::Char(a)),
+            ListCell(ref list) => list.iter()
+                .map(|item| match *item {
+                    AtomCell(Char(ch)) => Ok(ch),
+                    _ => Err(format!("{:?} is not a valid map key", cell))
+                })
+                .collect::<Result<String, String>>()
+                .map(MapKey::Str),
+            _ => Err(format!("{:?} is not a valid map key", cell))
+        }
+    }
+
+    /// Converts this key back into the cell it was made from.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    pub fn to_cell(&self) -> SVMCell {
+        match *self {
+            MapKey::UInt(a)     => AtomCell(UInt(a)),
+            MapKey::SInt(a)     => AtomCell(SInt(a)),
+            MapKey::Float(bits) => AtomCell(Float(f64::from_bits(bits))),
+            MapKey::Char(a)     => AtomCell(Char(a)),
+            MapKey::Str(ref string) => ListCell(Box::new(
+                string.chars().map(|ch| AtomCell(Char(ch))).collect()))
+        }
+    }
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="map"))]
+impl fmt::Debug for MapKey {
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapKey::Str(ref string) => write!(f, "{:?}", string),
+            _ => write!(f, "{:?}", self.to_cell())
         }
     }
 }
@@ -93,37 +420,87 @@ impl fmt::Debug for Atom {
     }
 }
 
+/// Returns the Unicode scalar value of a char atom, or the integral value
+/// of a numeric atom, for use in char arithmetic.
+fn scalar_value(atom: Atom) -> Result<i64, String> {
+    match atom {
+        Char(c)                         => Ok(c as u32 as i64),
+        SInt(a)                         => Ok(a),
+        UInt(a) if a <= i64::MAX as u64 => Ok(a as i64),
+        Float(a) if a >= -9223372036854775808.0 &&
+                    a < 9223372036854775808.0
+                                        => Ok(a as i64),
+        _                               => Err(format!(
+            "{:?} is out of range for char arithmetic", atom))
+    }
+}
+
+/// Converts an unsigned integer to a signed one, if it is in range.
+fn as_sint(a: u64) -> Option<i64> {
+    if a <= i64::MAX as u64 { Some(a as i64) } else { None }
+}
+
+/// Returns the error for an integer operation `a op b` which overflowed.
+fn overflow_error(a: Atom, op: &str, b: Atom) -> String {
+    format!("overflow in {:?} {} {:?}", a, op, b)
+}
+
+/// Returns the error for a failed integer division of `a` by `b`.
+fn divide_error(a: Atom, b: Atom) -> String {
+    match b {
+        SInt(0) | UInt(0) => format!("attempted to divide {:?} by zero", a),
+        _                 => format!("overflow dividing {:?} by {:?}", a, b)
+    }
+}
+
+/// Applies an arithmetic operation to the scalar values of two atoms, at
+/// least one of which is a char, and returns the resulting char.
+///
+/// This operates on full Unicode scalar values, so the result is an error
+/// if it is not a valid `char` (i.e. it is negative, a surrogate, or above
+/// `0x10FFFF`), or if the operation itself overflows or divides by zero.
+fn char_op<F>(a: Atom, b: Atom, op: F) -> Result<Atom, String>
+    where F: Fn(i64, i64) -> Option<i64>
+{
+    let (x, y) = (try!(scalar_value(a)), try!(scalar_value(b)));
+    op(x, y)
+        .ok_or(format!("invalid char arithmetic on {:?} and {:?}", a, b))
+        .and_then(|result|
+            if result >= 0 && result <= u32::MAX as i64 {
+                char::from_u32(result as u32)
+                    .map(Char)
+                    .ok_or(format!("{:#x} is not a valid char", result))
+            } else {
+                Err(format!("{:#x} is not a valid char", result))
+            })
+}
+
+/// Applies an arithmetic operation to two atoms as the operators on `Atom`
+/// do: integer operations which overflow wrap around, and any other
+/// operands are handled by the `checked` operation, panicking where it
+/// returns an error.
+fn wrapping_op(a: Atom, b: Atom,
+               sint: fn(i64, i64) -> i64,
+               uint: fn(u64, u64) -> u64,
+               checked: fn(Atom, Atom) -> Result<Atom, String>) -> Atom {
+    match (a, b) {
+        (SInt(x), SInt(y))  => SInt(sint(x, y)),
+        (UInt(x), UInt(y))  => UInt(uint(x, y)),
+        (UInt(x), SInt(y))  => SInt(sint(x as i64, y)),
+        (SInt(x), UInt(y))  => SInt(sint(x, y as i64)),
+        _                   => checked(a, b).unwrap_or_else(|why| panic!("{}", why))
+    }
+}
+
 #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
 impl ops::Add for Atom {
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     type Output = Atom;
+    /// Integer overflow wraps around, as with `i64::wrapping_add`; panics
+    /// where `Atom::checked_add` would return any other error.
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     fn add(self, other: Atom) -> Atom {
-        match (self, other) {
-            // same type:  no coercion
-            (SInt(a), SInt(b))      => SInt(a + b),
-            (UInt(a), UInt(b))      => UInt(a + b),
-            (Float(a), Float(b))    => Float(a + b),
-            (Char(a), Char(b))      => Char((a as u8 + b as u8) as char),
-            // float + int: coerce to float
-            (Float(a), SInt(b))     => Float(a + b as f64),
-            (Float(a), UInt(b))     => Float(a + b as f64),
-            (SInt(a), Float(b))     => Float(a as f64 + b),
-            (UInt(a), Float(b))     => Float(a as f64 + b),
-            // uint + sint: coerce to sint
-            (UInt(a), SInt(b))      => SInt(a as i64 + b),
-            (SInt(a), UInt(b))      => SInt(a + b as i64),
-            // char + any: coerce to char
-            // because of the supported operations on Ru64t chars,
-            // everything has to be cast to u8 (byte) to allow
-            // arithmetic ops and then cast back to char.
-            (Char(a), UInt(b))      => Char((a as u8 + b as u8) as char),
-            (Char(a), SInt(b))      => Char((a as u8 + b as u8) as char),
-            (Char(a), Float(b))     => Char((a as u8 + b as u8) as char),
-            (UInt(a), Char(b))      => Char((a as u8 + b as u8) as char),
-            (SInt(a), Char(b))      => Char((a as u8 + b as u8) as char),
-            (Float(a), Char(b))     => Char((a as u8 + b as u8) as char)
-        }
+        wrapping_op(self, other, i64::wrapping_add, u64::wrapping_add, Atom::checked_add)
     }
 
 }
@@ -131,30 +508,11 @@ impl ops::Add for Atom {
 impl ops::Sub for Atom {
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     type Output = Atom;
+    /// Integer overflow wraps around, as with `i64::wrapping_sub`; panics
+    /// where `Atom::checked_sub` would return any other error.
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     fn sub(self, other: Atom) -> Atom {
-        match (self, other) {
-            // same type:  no coercion
-            (SInt(a), SInt(b))      => SInt(a - b),
-            (UInt(a), UInt(b))      => UInt(a - b),
-            (Float(a), Float(b))    => Float(a - b),
-            (Char(a), Char(b))      => Char((a as u8 - b as u8) as char),
-            // float + int: coerce to float
-            (Float(a), SInt(b))     => Float(a - b as f64),
-            (Float(a), UInt(b))     => Float(a - b as f64),
-            (SInt(a), Float(b))     => Float(a as f64 - b),
-            (UInt(a), Float(b))     => Float(a as f64 - b),
-            // uint + sint: coerce to sint
-            (UInt(a), SInt(b))      => SInt(a as i64 - b),
-            (SInt(a), UInt(b))      => SInt(a - b as i64),
-            // char + any: coerce to char
-            (Char(a), UInt(b))      => Char((a as u8 - b as u8) as char),
-            (Char(a), SInt(b))      => Char((a as u8 - b as u8) as char),
-            (Char(a), Float(b))     => Char((a as u8 - b as u8) as char),
-            (UInt(a), Char(b))      => Char((a as u8 - b as u8) as char),
-            (SInt(a), Char(b))      => Char((a as u8 - b as u8) as char),
-            (Float(a), Char(b))     => Char((a as u8 - b as u8) as char)
-        }
+        wrapping_op(self, other, i64::wrapping_sub, u64::wrapping_sub, Atom::checked_sub)
     }
 
 }
@@ -162,30 +520,11 @@ impl ops::Sub for Atom {
 impl ops::Div for Atom {
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     type Output = Atom;
+    /// Integer overflow wraps around, as with `i64::wrapping_div`; panics
+    /// where `Atom::checked_div` would return any other error.
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     fn div(self, other: Atom) -> Atom {
-        match (self, other) {
-            // same type:  no coercion
-            (SInt(a), SInt(b))      => SInt(a / b),
-            (UInt(a), UInt(b))      => UInt(a / b),
-            (Float(a), Float(b))    => Float(a / b),
-            (Char(a), Char(b))      => Char((a as u8 / b as u8) as char),
-            // float + int: coerce to float
-            (Float(a), SInt(b))     => Float(a / b as f64),
-            (Float(a), UInt(b))     => Float(a / b as f64),
-            (SInt(a), Float(b))     => Float(a as f64 / b),
-            (UInt(a), Float(b))     => Float(a as f64 / b),
-            // uint + sint: coerce to sint
-            (UInt(a), SInt(b))      => SInt(a as i64 / b),
-            (SInt(a), UInt(b))      => SInt(a / b as i64),
-            // char + any: coerce to char
-            (Char(a), UInt(b))      => Char((a as u8 / b as u8) as char),
-            (Char(a), SInt(b))      => Char((a as u8 / b as u8) as char),
-            (Char(a), Float(b))     => Char((a as u8 / b as u8) as char),
-            (UInt(a), Char(b))      => Char((a as u8 / b as u8) as char),
-            (SInt(a), Char(b))      => Char((a as u8 / b as u8) as char),
-            (Float(a), Char(b))     => Char((a as u8 / b as u8) as char)
-        }
+        wrapping_op(self, other, i64::wrapping_div, u64::wrapping_div, Atom::checked_div)
     }
 
 }
@@ -193,31 +532,11 @@ impl ops::Div for Atom {
 impl ops::Mul for Atom {
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     type Output = Atom;
-
+    /// Integer overflow wraps around, as with `i64::wrapping_mul`; panics
+    /// where `Atom::checked_mul` would return any other error.
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     fn mul(self, other: Atom) -> Atom {
-        match (self, other) {
-            // same type:  no coercion
-            (SInt(a), SInt(b))      => SInt(a * b),
-            (UInt(a), UInt(b))      => UInt(a * b),
-            (Float(a), Float(b))    => Float(a * b),
-            (Char(a), Char(b))      => Char((a as u8 * b as u8) as char),
-            // float + int: coerce to float
-            (Float(a), SInt(b))     => Float(a * b as f64),
-            (Float(a), UInt(b))     => Float(a * b as f64),
-            (SInt(a), Float(b))     => Float(a as f64* b),
-            (UInt(a), Float(b))     => Float(a as f64* b),
-            // uint + sint: coerce to sint
-            (UInt(a), SInt(b))      => SInt(a as i64 * b),
-            (SInt(a), UInt(b))      => SInt(a * b as i64),
-            // char + any: coerce to char
-            (Char(a), UInt(b))      => Char((a as u8 * b as u8) as char),
-            (Char(a), SInt(b))      => Char((a as u8 * b as u8) as char),
-            (Char(a), Float(b))     => Char((a as u8 * b as u8) as char),
-            (UInt(a), Char(b))      => Char((a as u8 * b as u8) as char),
-            (SInt(a), Char(b))      => Char((a as u8 * b as u8) as char),
-            (Float(a), Char(b))     => Char((a as u8 * b as u8) as char)
-        }
+        wrapping_op(self, other, i64::wrapping_mul, u64::wrapping_mul, Atom::checked_mul)
     }
 
 }
@@ -225,33 +544,250 @@ impl ops::Mul for Atom {
 impl ops::Rem for Atom {
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     type Output = Atom;
-
+    /// Integer overflow wraps around, as with `i64::wrapping_rem`; panics
+    /// where `Atom::checked_rem` would return any other error.
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     fn rem(self, other: Atom) -> Atom {
+        wrapping_op(self, other, i64::wrapping_rem, u64::wrapping_rem, Atom::checked_rem)
+    }
+
+}
+#[cfg_attr(feature = "nightly", unstable(feature="checked_arith"))]
+impl Atom {
+    /// Adds `other` to this atom, returning an error if an integer result
+    /// overflows, or if the result is a char which is not a valid Unicode
+    /// scalar value.
+    #[cfg_attr(feature = "nightly", unstable(feature="checked_arith"))]
+    pub fn checked_add(self, other: Atom) -> Result<Atom, String> {
         match (self, other) {
             // same type:  no coercion
-            (SInt(a), SInt(b))      => SInt(a % b),
-            (UInt(a), UInt(b))      => UInt(a % b),
-            (Float(a), Float(b))    => Float(a % b),
-            (Char(a), Char(b))      => Char((a as u8 % b as u8) as char),
+            (SInt(a), SInt(b))      => a.checked_add(b).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "+", other)),
+            (UInt(a), UInt(b))      => a.checked_add(b).map(UInt)
+                                        .ok_or_else(|| overflow_error(self, "+", other)),
+            (Float(a), Float(b))    => Ok(Float(a + b)),
             // float + int: coerce to float
-            (Float(a), SInt(b))     => Float(a % b as f64),
-            (Float(a), UInt(b))     => Float(a % b as f64),
-            (SInt(a), Float(b))     => Float(a as f64 % b),
-            (UInt(a), Float(b))     => Float(a as f64 % b),
+            (Float(a), SInt(b))     => Ok(Float(a + b as f64)),
+            (Float(a), UInt(b))     => Ok(Float(a + b as f64)),
+            (SInt(a), Float(b))     => Ok(Float(a as f64 + b)),
+            (UInt(a), Float(b))     => Ok(Float(a as f64 + b)),
             // uint + sint: coerce to sint
-            (UInt(a), SInt(b))      => SInt(a as i64 % b),
-            (SInt(a), UInt(b))      => SInt(a % b as i64),
-            // char + any: coerce to char
-            (Char(a), UInt(b))      => Char((a as u8 % b as u8) as char),
-            (Char(a), SInt(b))      => Char((a as u8 % b as u8) as char),
-            (Char(a), Float(b))     => Char((a as u8 % b as u8) as char),
-            (UInt(a), Char(b))      => Char((a as u8 % b as u8) as char),
-            (SInt(a), Char(b))      => Char((a as u8 % b as u8) as char),
-            (Float(a), Char(b))     => Char((a as u8 % b as u8) as char)
+            (UInt(a), SInt(b))      => as_sint(a).and_then(|a| a.checked_add(b)).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "+", other)),
+            (SInt(a), UInt(b))      => as_sint(b).and_then(|b| a.checked_add(b)).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "+", other)),
+            // char + any: coerce to char, operating on the
+            // Unicode scalar values of the operands.
+            (Char(_), _) | (_, Char(_)) => char_op(self, other, i64::checked_add)
         }
     }
 
+    /// Subtracts `other` from this atom, returning an error if an integer
+    /// result overflows, or if the result is a char which is not a valid
+    /// Unicode scalar value.
+    #[cfg_attr(feature = "nightly", unstable(feature="checked_arith"))]
+    pub fn checked_sub(self, other: Atom) -> Result<Atom, String> {
+        match (self, other) {
+            // same type:  no coercion
+            (SInt(a), SInt(b))      => a.checked_sub(b).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "-", other)),
+            (UInt(a), UInt(b))      => a.checked_sub(b).map(UInt)
+                                        .ok_or_else(|| overflow_error(self, "-", other)),
+            (Float(a), Float(b))    => Ok(Float(a - b)),
+            // float + int: coerce to float
+            (Float(a), SInt(b))     => Ok(Float(a - b as f64)),
+            (Float(a), UInt(b))     => Ok(Float(a - b as f64)),
+            (SInt(a), Float(b))     => Ok(Float(a as f64 - b)),
+            (UInt(a), Float(b))     => Ok(Float(a as f64 - b)),
+            // uint + sint: coerce to sint
+            (UInt(a), SInt(b))      => as_sint(a).and_then(|a| a.checked_sub(b)).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "-", other)),
+            (SInt(a), UInt(b))      => as_sint(b).and_then(|b| a.checked_sub(b)).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "-", other)),
+            // char + any: coerce to char, operating on the
+            // Unicode scalar values of the operands.
+            (Char(_), _) | (_, Char(_)) => char_op(self, other, i64::checked_sub)
+        }
+    }
+
+    /// Divides this atom by `other`, returning an error on integer division
+    /// by zero or overflow, or if the result is a char which is not a valid
+    /// Unicode scalar value.
+    #[cfg_attr(feature = "nightly", unstable(feature="checked_arith"))]
+    pub fn checked_div(self, other: Atom) -> Result<Atom, String> {
+        match (self, other) {
+            // same type:  no coercion
+            (SInt(a), SInt(b))      => a.checked_div(b).map(SInt)
+                                        .ok_or_else(|| divide_error(self, other)),
+            (UInt(a), UInt(b))      => a.checked_div(b).map(UInt)
+                                        .ok_or_else(|| divide_error(self, other)),
+            (Float(a), Float(b))    => Ok(Float(a / b)),
+            // float + int: coerce to float
+            (Float(a), SInt(b))     => Ok(Float(a / b as f64)),
+            (Float(a), UInt(b))     => Ok(Float(a / b as f64)),
+            (SInt(a), Float(b))     => Ok(Float(a as f64 / b)),
+            (UInt(a), Float(b))     => Ok(Float(a as f64 / b)),
+            // uint + sint: coerce to sint
+            (UInt(a), SInt(b))      => as_sint(a).and_then(|a| a.checked_div(b)).map(SInt)
+                                        .ok_or_else(|| divide_error(self, other)),
+            (SInt(a), UInt(b))      => as_sint(b).and_then(|b| a.checked_div(b)).map(SInt)
+                                        .ok_or_else(|| divide_error(self, other)),
+            // char + any: coerce to char, operating on the
+            // Unicode scalar values of the operands.
+            (Char(_), _) | (_, Char(_)) => char_op(self, other, i64::checked_div)
+        }
+    }
+
+    /// Multiplies this atom by `other`, returning an error if an integer
+    /// result overflows, or if the result is a char which is not a valid
+    /// Unicode scalar value.
+    #[cfg_attr(feature = "nightly", unstable(feature="checked_arith"))]
+    pub fn checked_mul(self, other: Atom) -> Result<Atom, String> {
+        match (self, other) {
+            // same type:  no coercion
+            (SInt(a), SInt(b))      => a.checked_mul(b).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "*", other)),
+            (UInt(a), UInt(b))      => a.checked_mul(b).map(UInt)
+                                        .ok_or_else(|| overflow_error(self, "*", other)),
+            (Float(a), Float(b))    => Ok(Float(a * b)),
+            // float + int: coerce to float
+            (Float(a), SInt(b))     => Ok(Float(a * b as f64)),
+            (Float(a), UInt(b))     => Ok(Float(a * b as f64)),
+            (SInt(a), Float(b))     => Ok(Float(a as f64 * b)),
+            (UInt(a), Float(b))     => Ok(Float(a as f64 * b)),
+            // uint + sint: coerce to sint
+            (UInt(a), SInt(b))      => as_sint(a).and_then(|a| a.checked_mul(b)).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "*", other)),
+            (SInt(a), UInt(b))      => as_sint(b).and_then(|b| a.checked_mul(b)).map(SInt)
+                                        .ok_or_else(|| overflow_error(self, "*", other)),
+            // char + any: coerce to char, operating on the
+            // Unicode scalar values of the operands.
+            (Char(_), _) | (_, Char(_)) => char_op(self, other, i64::checked_mul)
+        }
+    }
+
+    /// Returns the remainder of dividing this atom by `other`, or an error
+    /// on integer division by zero or overflow, or if the result is a char
+    /// which is not a valid Unicode scalar value.
+    #[cfg_attr(feature = "nightly", unstable(feature="checked_arith"))]
+    pub fn checked_rem(self, other: Atom) -> Result<Atom, String> {
+        match (self, other) {
+            // same type:  no coercion
+            (SInt(a), SInt(b))      => a.checked_rem(b).map(SInt)
+                                        .ok_or_else(|| divide_error(self, other)),
+            (UInt(a), UInt(b))      => a.checked_rem(b).map(UInt)
+                                        .ok_or_else(|| divide_error(self, other)),
+            (Float(a), Float(b))    => Ok(Float(a % b)),
+            // float + int: coerce to float
+            (Float(a), SInt(b))     => Ok(Float(a % b as f64)),
+            (Float(a), UInt(b))     => Ok(Float(a % b as f64)),
+            (SInt(a), Float(b))     => Ok(Float(a as f64 % b)),
+            (UInt(a), Float(b))     => Ok(Float(a as f64 % b)),
+            // uint + sint: coerce to sint
+            (UInt(a), SInt(b))      => as_sint(a).and_then(|a| a.checked_rem(b)).map(SInt)
+                                        .ok_or_else(|| divide_error(self, other)),
+            (SInt(a), UInt(b))      => as_sint(b).and_then(|b| a.checked_rem(b)).map(SInt)
+                                        .ok_or_else(|| divide_error(self, other)),
+            // char + any: coerce to char, operating on the
+            // Unicode scalar values of the operands.
+            (Char(_), _) | (_, Char(_)) => char_op(self, other, i64::checked_rem)
+        }
+    }
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+impl Atom {
+    /// Converts this atom to a signed integer atom.
+    ///
+    /// Chars are converted to their Unicode scalar value. Returns an
+    /// error if the conversion would lose information, such as when
+    /// converting a float with a fractional part or an unsigned integer
+    /// too large to fit in an `i64`.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    pub fn to_sint(self) -> Result<Atom, String> {
+        match self {
+            SInt(_)                         => Ok(self),
+            UInt(a) if a <= i64::MAX as u64 => Ok(SInt(a as i64)),
+            Float(a) if a.fract() == 0.0 &&
+                        a >= -9223372036854775808.0 &&
+                        a < 9223372036854775808.0
+                                            => Ok(SInt(a as i64)),
+            Char(a)                         => Ok(SInt(a as u32 as i64)),
+            _                               => Err(format!(
+                "cannot convert {:?} to sint without loss", self))
+        }
+    }
+
+    /// Converts this atom to an unsigned integer atom.
+    ///
+    /// Chars are converted to their Unicode scalar value. Returns an
+    /// error if the conversion would lose information, such as when
+    /// converting a negative number or a float with a fractional part.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    pub fn to_uint(self) -> Result<Atom, String> {
+        match self {
+            UInt(_)             => Ok(self),
+            SInt(a) if a >= 0   => Ok(UInt(a as u64)),
+            Float(a) if a.fract() == 0.0 &&
+                        a >= 0.0 &&
+                        a < 18446744073709551616.0
+                                => Ok(UInt(a as u64)),
+            Char(a)             => Ok(UInt(a as u32 as u64)),
+            _                   => Err(format!(
+                "cannot convert {:?} to uint without loss", self))
+        }
+    }
+
+    /// Converts this atom to a floating-point atom.
+    ///
+    /// Chars are converted to their Unicode scalar value. Returns an
+    /// error if an integer is too large to be represented exactly as an
+    /// `f64`.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    pub fn to_float(self) -> Result<Atom, String> {
+        match self {
+            Float(_)    => Ok(self),
+            SInt(a) if (a as f64) < 9223372036854775808.0 &&
+                       (a as f64) as i64 == a
+                        => Ok(Float(a as f64)),
+            UInt(a) if (a as f64) < 18446744073709551616.0 &&
+                       (a as f64) as u64 == a
+                        => Ok(Float(a as f64)),
+            Char(a)     => Ok(Float(a as u32 as f64)),
+            _           => Err(format!(
+                "cannot convert {:?} to float without loss", self))
+        }
+    }
+
+    /// Returns the value of this atom as an `f64`.
+    ///
+    /// This follows the same coercion rules as `FDIV`: integers are
+    /// converted to the nearest float, and chars are converted to their
+    /// Unicode scalar value.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    pub fn as_float(self) -> f64 {
+        match self {
+            UInt(a)     => a as f64,
+            SInt(a)     => a as f64,
+            Float(a)    => a,
+            Char(a)     => a as u32 as f64
+        }
+    }
+
+    /// Converts this atom to a char atom.
+    ///
+    /// Numbers are interpreted as Unicode scalar values. Returns an error
+    /// if the number is not integral or is not a valid scalar value.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    pub fn to_char(self) -> Result<Atom, String> {
+        match self.to_uint() {
+            Ok(UInt(a)) if a <= u32::MAX as u64 =>
+                char::from_u32(a as u32)
+                    .map(Char)
+                    .ok_or(format!("{:?} is not a valid char", self)),
+            _ => Err(format!("{:?} is not a valid char", self))
+        }
+    }
 }
 
 /// SVM instruction types.
@@ -293,8 +829,17 @@ pub enum Inst {
     ///  a closure (a pair containing the function and the current
     ///  environment) and pushes that onto the stack.
     ///
+    ///  The function may be preceded by an arity, a list of the number
+    ///  of required parameters, the number of optional parameters, and
+    ///  `1` if the function takes rest arguments or `0` if not. A closure
+    ///  with an arity checks the number of arguments it is applied to;
+    ///  missing optional arguments are bound to `nil`, and any rest
+    ///  arguments are packed into a list bound to the last parameter.
+    ///
     /// _Operational semantics_: `(s, e, (LDF f.c), d) → ( ([f e].s), e, c, d)`
     ///
+    /// _Operational semantics_: `(s, e, (LDF a.f.c), d) → ( ([f e a].s), e, c, d)`
+    ///
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.2.4"))]
     LDF,
     /// `join`
@@ -480,6 +1025,465 @@ pub enum Inst {
     /// then be applied with `ap`.
     #[cfg_attr(feature = "nightly", unstable(feature="callcc"))]
     APCC,
+    /// `int?`: test if `int`eger
+    ///
+    /// Pops an item from the stack and returns true if it's a signed or
+    /// unsigned integer atom, false otherwise.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    INTP,
+    /// `float?`: test if `float`
+    ///
+    /// Pops an item from the stack and returns true if it's a float atom,
+    /// false otherwise.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    FLOATP,
+    /// `char?`: test if `char`
+    ///
+    /// Pops an item from the stack and returns true if it's a char atom,
+    /// false otherwise.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    CHARP,
+    /// `proc?`: test if `proc`edure
+    ///
+    /// Pops an item from the stack and returns true if it's a closure
+    /// (a pair `[f e]` as constructed by `ldf`), false otherwise.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    PROCP,
+    /// `pair?`: test if `pair`
+    ///
+    /// Pops an item from the stack and returns true if it's a non-empty
+    /// list, false otherwise.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    PAIRP,
+    /// `toint`: convert `to` signed `int`eger
+    ///
+    /// Pops an atom from the stack and pushes it converted to a signed
+    /// integer. Conversions that would lose information (such as from a
+    /// float with a fractional part) are errors.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    TOINT,
+    /// `touint`: convert `to` `u`nsigned `int`eger
+    ///
+    /// Pops an atom from the stack and pushes it converted to an unsigned
+    /// integer. Conversions that would lose information (such as from a
+    /// negative number) are errors.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    TOUINT,
+    /// `tofloat`: convert `to` `float`
+    ///
+    /// Pops an atom from the stack and pushes it converted to a float.
+    /// Integers too large to be represented exactly are errors.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    TOFLOAT,
+    /// `tochar`: convert `to` `char`
+    ///
+    /// Pops an atom from the stack and pushes the char with that Unicode
+    /// scalar value. Values which are not valid chars are errors.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_types"))]
+    TOCHAR,
+    /// `sqrt`: `sq`uare `r`oo`t`
+    ///
+    /// Pops a number from the stack and pushes its square root.
+    ///
+    /// As with `fdiv`, integer operands are coerced to floats; this is
+    /// the case for all of the floating-point math instructions.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    SQRT,
+    /// `exp`: `exp`onential
+    ///
+    /// Pops a number `x` from the stack and pushes _e_ raised to the power `x`.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    EXP,
+    /// `log`: natural `log`arithm
+    ///
+    /// Pops a number from the stack and pushes its natural logarithm.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    LOG,
+    /// `sin`: `sin`e
+    ///
+    /// Pops a number (in radians) from the stack and pushes its sine.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    SIN,
+    /// `cos`: `cos`ine
+    ///
+    /// Pops a number (in radians) from the stack and pushes its cosine.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    COS,
+    /// `tan`: `tan`gent
+    ///
+    /// Pops a number (in radians) from the stack and pushes its tangent.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    TAN,
+    /// `asin`: `a`rc`sin`e
+    ///
+    /// Pops a number from the stack and pushes its arcsine, in radians.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    ASIN,
+    /// `acos`: `a`rc`cos`ine
+    ///
+    /// Pops a number from the stack and pushes its arccosine, in radians.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    ACOS,
+    /// `atan`: `a`rc`tan`gent
+    ///
+    /// Pops a number from the stack and pushes its arctangent, in radians.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    ATAN,
+    /// `floor`
+    ///
+    /// Pops a number from the stack and pushes the largest integral float
+    /// less than or equal to it.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    FLOOR,
+    /// `ceil`: `ceil`ing
+    ///
+    /// Pops a number from the stack and pushes the smallest integral float
+    /// greater than or equal to it.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    CEIL,
+    /// `round`
+    ///
+    /// Pops a number from the stack and pushes the nearest integral float,
+    /// rounding half-way cases away from zero.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    ROUND,
+    /// `nan?`: test if `NaN`
+    ///
+    /// Pops an item from the stack and returns true if it's a float atom
+    /// which is not a number, false otherwise.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    NANP,
+    /// `inf?`: test if `inf`inite
+    ///
+    /// Pops an item from the stack and returns true if it's a float atom
+    /// which is positive or negative infinity, false otherwise.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    INFP,
+    /// `pow`: `pow`er
+    ///
+    /// Pops two numbers off of the stack and pushes the first raised to
+    /// the power of the second.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    POW,
+    /// `atan2`: four-quadrant `a`rc`tan`gent
+    ///
+    /// Pops two numbers `y` and `x` off of the stack and pushes the
+    /// arctangent of `y / x`, using the signs of both to determine the
+    /// quadrant.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_math"))]
+    ATAN2,
+    /// `vmake`: `make` `v`ector
+    ///
+    /// Pops a length `n` and an item from the stack, and pushes a vector
+    /// containing `n` copies of the item.
+    #[cfg_attr(feature = "nightly", unstable(feature="vector"))]
+    VMAKE,
+    /// `vget`: `get` `v`ector element
+    ///
+    /// Pops a vector and an index from the stack, and pushes the element of
+    /// the vector at that index. This is an O(1) operation.
+    #[cfg_attr(feature = "nightly", unstable(feature="vector"))]
+    VGET,
+    /// `vset`: `set` `v`ector element
+    ///
+    /// Pops a vector, an index, and an item from the stack, and pushes a
+    /// vector with the element at that index replaced by the item. Other
+    /// copies of the vector (such as in closures' environments) are
+    /// unaffected: if there are none, the vector is updated in place, so
+    /// this is an O(1) operation, and otherwise it is first copied.
+    #[cfg_attr(feature = "nightly", unstable(feature="vector"))]
+    VSET,
+    /// `vlen`: `v`ector `len`gth
+    ///
+    /// Pops a vector from the stack and pushes its length as an unsigned
+    /// integer.
+    #[cfg_attr(feature = "nightly", unstable(feature="vector"))]
+    VLEN,
+    /// `vtol`: `v`ector `to` `l`ist
+    ///
+    /// Pops a vector from the stack and pushes a list of its elements.
+    #[cfg_attr(feature = "nightly", unstable(feature="vector"))]
+    VTOL,
+    /// `ltov`: `l`ist `to` `v`ector
+    ///
+    /// Pops a list from the stack and pushes a vector of its elements.
+    #[cfg_attr(feature = "nightly", unstable(feature="vector"))]
+    LTOV,
+    /// `blen`: `b`yte buffer `len`gth
+    ///
+    /// Pops a byte buffer from the stack and pushes its length as an unsigned
+    /// integer.
+    #[cfg_attr(feature = "nightly", unstable(feature="bytes"))]
+    BLEN,
+    /// `bget`: `get` `b`yte
+    ///
+    /// Pops a byte buffer and an index from the stack, and pushes the byte
+    /// at that index as an unsigned integer.
+    #[cfg_attr(feature = "nightly", unstable(feature="bytes"))]
+    BGET,
+    /// `bslice`: `slice` `b`yte buffer
+    ///
+    /// Pops a byte buffer, a start index, and an end index from the stack,
+    /// and pushes a byte buffer containing the bytes from the start index
+    /// up to (but not including) the end index.
+    #[cfg_attr(feature = "nightly", unstable(feature="bytes"))]
+    BSLICE,
+    /// `bcat`: con`cat`enate `b`yte buffers
+    ///
+    /// Pops two byte buffers from the stack and pushes a byte buffer
+    /// containing the bytes of the first followed by the bytes of the
+    /// second.
+    #[cfg_attr(feature = "nightly", unstable(feature="bytes"))]
+    BCAT,
+    /// `stob`: `s`tring `to` `b`ytes
+    ///
+    /// Pops a string (a list of chars) from the stack and pushes a byte
+    /// buffer containing its UTF-8 encoding.
+    #[cfg_attr(feature = "nightly", unstable(feature="bytes"))]
+    STOB,
+    /// `btos`: `b`ytes `to` `s`tring
+    ///
+    /// Pops a byte buffer from the stack, decodes it as UTF-8, and pushes
+    /// the resulting string (a list of chars). Invalid UTF-8 is an error.
+    #[cfg_attr(feature = "nightly", unstable(feature="bytes"))]
+    BTOS,
+    /// `readb`: `read` `b`yte
+    ///
+    /// Reads a byte from the machine's input stream and places it on top of
+    /// the stack as an unsigned integer.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_io"))]
+    READB,
+    /// `writeb`: `write` `b`ytes
+    ///
+    /// Writes a byte buffer from the top of the stack to the machine's
+    /// output stream.
+    #[cfg_attr(feature = "nightly", unstable(feature="vm_io"))]
+    WRITEB,
+    /// `mnew`: `new` `m`ap
+    ///
+    /// Pushes a new, empty map onto the stack.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    MNEW,
+    /// `mput`: `put` in `m`ap
+    ///
+    /// Pops a map, a key, and a value from the stack, and pushes a map
+    /// with the key bound to the value, replacing any existing binding.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    MPUT,
+    /// `mget`: `get` from `m`ap
+    ///
+    /// Pops a map and a key from the stack, and pushes the value bound to
+    /// the key. It is an error if the key is not bound.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    MGET,
+    /// `mhas`: `m`ap `has` key
+    ///
+    /// Pops a map and a key from the stack, and pushes true if the key is
+    /// bound in the map, or false otherwise.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    MHAS,
+    /// `mdel`: `del`ete from `m`ap
+    ///
+    /// Pops a map and a key from the stack, and pushes a map with the key
+    /// removed. Removing a key which is not bound has no effect.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    MDEL,
+    /// `mkeys`: `m`ap `keys`
+    ///
+    /// Pops a map from the stack and pushes a list of its keys, in order.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    MKEYS,
+    /// `mlen`: `m`ap `len`gth
+    ///
+    /// Pops a map from the stack and pushes the number of entries in it as
+    /// an unsigned integer.
+    #[cfg_attr(feature = "nightly", unstable(feature="map"))]
+    MLEN,
+    /// `try`: install exception handler
+    ///
+    /// Expects two list arguments on the control stack: a handler and a
+    /// body. A handler frame, recording the handler along with the current
+    /// stack, environment, and the instructions following `try`, is saved
+    /// on the dump, and the body is made the new `$c`. The body should end
+    /// with `endtry`.
+    ///
+    /// If `throw` is evaluated, or an error occurs, while the handler
+    /// frame is on the dump, the dump is unwound to the frame and the
+    /// handler is evaluated with the recorded stack and environment, and
+    /// the thrown value on top of the stack. A pointer to the instructions
+    /// following `try` is saved on the dump, so the handler should end
+    /// with `join`. Errors raised by the VM itself are passed to the
+    /// handler as a string describing the error.
+    ///
+    /// __Operational semantics__: `(s, e, TRY.h.b.c, d) → (s, e, b, TRY.[h s e c].d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="exceptions"))]
+    TRY,
+    /// `endtry`: `end` `try` body
+    ///
+    /// Pops the handler frame saved by `try` from the dump, and continues
+    /// with the instructions following that `try`.
+    ///
+    /// __Operational semantics__: `(s, e, ENDTRY.c, TRY.[h s´ e´ c´].d) → (s, e, c´, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="exceptions"))]
+    ENDTRY,
+    /// `throw`
+    ///
+    /// Pops a value from the stack and unwinds the dump to the innermost
+    /// handler frame saved by `try`, passing the value to its handler. It
+    /// is an error if there is no handler.
+    ///
+    /// __Operational semantics__: `(v.s, e, THROW.c, ….TRY.[h s´ e´ c´].d) → (v.s´, e´, h, c´.d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="exceptions"))]
+    THROW,
+    /// `calln`: `call` `n`ative function
+    ///
+    /// Expects a reference to a native function on the control stack,
+    /// either a native cell naming the function or an unsigned integer
+    /// giving the index at which it was bound. Pops a list of arguments
+    /// from the stack, calls the native function with them, and pushes
+    /// the result.
+    ///
+    /// Native functions are bound by the host on a `Machine`, and may
+    /// only be called by a `Machine`.
+    ///
+    /// __Operational semantics__: `(v.s, e, CALLN.n.c, d) → (n(v).s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="native"))]
+    CALLN,
+    /// `ldg`: `l`oa`d` `g`lobal
+    ///
+    /// Expects a reference to a global on the control stack, either a
+    /// global cell naming it or an unsigned integer giving the index at
+    /// which it was defined, and pushes the value of that global. It is
+    /// an error if the global is not defined.
+    ///
+    /// Globals belong to a `Machine`, and may only be accessed by one.
+    ///
+    /// __Operational semantics__: `(s, e, LDG.g.c, d) → (g.s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="globals"))]
+    LDG,
+    /// `stg`: `st`ore `g`lobal
+    ///
+    /// Expects a reference to a global on the control stack, as `ldg`
+    /// does, and pops a value from the stack and stores it in that
+    /// global. A global referred to by name is defined if it is not
+    /// already; a global referred to by index must already be defined.
+    ///
+    /// __Operational semantics__: `(v.s, e, STG.g.c, d) → (s, e, c, d)`, where `g := v`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="globals"))]
+    STG,
+    /// `box`: create a `box`
+    ///
+    /// Pops a value from the stack and pushes a new box containing it.
+    ///
+    /// __Operational semantics__: `(v.s, e, BOX.c, d) → (b.s, e, c, d)`, where `b := v`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+    BOX,
+    /// `unbox`: read the contents of a box
+    ///
+    /// Pops a box from the stack and pushes its contents.
+    ///
+    /// __Operational semantics__: `(b.s, e, UNBOX.c, d) → (v.s, e, c, d)`, where `b = v`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+    UNBOX,
+    /// `setbox`: `set` the contents of a `box`
+    ///
+    /// Pops a box and then a value from the stack, and replaces the
+    /// contents of the box with that value. The change is seen through
+    /// every copy of the box.
+    ///
+    /// __Operational semantics__: `(b.v.s, e, SETBOX.c, d) → (s, e, c, d)`, where `b := v`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="boxes"))]
+    SETBOX,
+    /// `retn`: `ret`urn `n` values
+    ///
+    /// Expects an unsigned integer `n` on the control stack. Works like
+    /// `ret`, but moves the top `n` values of the stack onto the restored
+    /// stack, in the same order, and then pushes `n` on top of them, so
+    /// that several values may be returned without consing a list.
+    ///
+    /// __Operational semantics__: `(v1...vn.s, e, RETN.n.c, s'.e'.c'.d) → (n.v1...vn.s', e', c', d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="values"))]
+    RETN,
+    /// `recvn`: `rec`ei`v`e `n` values
+    ///
+    /// Expects an unsigned integer `n` on the control stack. Pops the
+    /// count pushed by `retn`, and leaves the values it returned on the
+    /// stack. It is an error if the count is not `n`.
+    ///
+    /// __Operational semantics__: `(n.v1...vn.s, e, RECVN.n.c, d) → (v1...vn.s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="values"))]
+    RECVN,
+    /// `spawn`: `spawn` a task
+    ///
+    /// Pops a closure and a list of arguments from the stack, and starts
+    /// a new task which applies the closure to the arguments, as if by
+    /// `ap`. Pushes the new task's ID, an unsigned integer.
+    ///
+    /// Tasks are run by a `Scheduler`, and may only be spawned by one.
+    ///
+    /// __Operational semantics__: `(f.v.s, e, SPAWN.c, d) → (t.s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    SPAWN,
+    /// `yield`: `yield` to other tasks
+    ///
+    /// Suspends the current task, so that the scheduler may run the next
+    /// task that is ready to run.
+    ///
+    /// __Operational semantics__: `(s, e, YIELD.c, d) → (s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    YIELD,
+    /// `wait`: `wait` for a task
+    ///
+    /// Pops a task ID from the stack and, once that task has finished,
+    /// pushes its result (the value on top of its stack). The current
+    /// task is suspended until then. If the task failed, its error is
+    /// raised in the waiting task.
+    ///
+    /// __Operational semantics__: `(t.s, e, WAIT.c, d) → (r.s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    WAIT,
+    /// `chan`: create a `chan`nel
+    ///
+    /// Pushes a new, empty channel.
+    ///
+    /// __Operational semantics__: `(s, e, CHAN.c, d) → (ch.s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    CHAN,
+    /// `send`: `send` on a channel
+    ///
+    /// Pops a channel and then a value from the stack, and sends the
+    /// value on the channel.
+    ///
+    /// __Operational semantics__: `(ch.v.s, e, SEND.c, d) → (s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    SEND,
+    /// `recv`: `rec`ei`v`e from a channel
+    ///
+    /// Pops a channel from the stack, and pushes the next value sent on
+    /// it. If the channel is empty, a task run by a `Scheduler` waits
+    /// until a value is sent on it, by another task or by the host;
+    /// otherwise, it is an error.
+    ///
+    /// __Operational semantics__: `(ch.s, e, RECV.c, d) → (v.s, e, c, d)`
+    ///
+    #[cfg_attr(feature = "nightly", unstable(feature="channels"))]
+    RECV,
 }
 
 #[cfg(test)]
@@ -509,4 +1513,149 @@ mod tests {
         assert_eq!(format!("{}", a), "1");
 
     }
+
+    #[test]
+    fn test_atom_conversions () {
+        assert_eq!(UInt(5).to_sint(), Ok(SInt(5)));
+        assert_eq!(Float(-3.0).to_sint(), Ok(SInt(-3)));
+        assert_eq!(Char('A').to_sint(), Ok(SInt(65)));
+        assert!(Float(1.5).to_sint().is_err());
+        assert!(UInt(u64::max_value()).to_sint().is_err());
+
+        assert_eq!(SInt(7).to_uint(), Ok(UInt(7)));
+        assert!(SInt(-1).to_uint().is_err());
+        assert!(Float(-2.0).to_uint().is_err());
+
+        assert_eq!(SInt(-2).to_float(), Ok(Float(-2.0)));
+        assert_eq!(UInt(1 << 53).to_float(), Ok(Float(9007199254740992.0)));
+        assert!(UInt((1 << 53) + 1).to_float().is_err());
+        assert!(SInt(i64::max_value()).to_float().is_err());
+
+        assert_eq!(UInt(0x3BB).to_char(), Ok(Char('λ')));
+        assert_eq!(Float(97.0).to_char(), Ok(Char('a')));
+        assert!(UInt(0xD800).to_char().is_err());
+        assert!(SInt(-97).to_char().is_err());
+        assert!(Float(97.5).to_char().is_err());
+    }
+
+    #[test]
+    fn test_char_arithmetic () {
+        assert_eq!(Char('λ').checked_add(UInt(1)), Ok(Char('μ')));
+        assert_eq!(Char('Ā').checked_sub(SInt(0xC0)), Ok(Char('@')));
+        assert_eq!(UInt(0x10000).checked_add(Char('\u{F600}')), Ok(Char('\u{1F600}')));
+        assert_eq!(Char('\u{1F600}').checked_rem(UInt(0x100)), Ok(Char('\u{0}')));
+        assert!(Char('\u{10FFFF}').checked_add(UInt(1)) ==
+                Err(String::from("0x110000 is not a valid char")));
+        assert!(Char('a').checked_sub(Char('b')).is_err());
+        assert!(Char('a').checked_div(UInt(0)).is_err());
+        assert_eq!(Char('a') + Float(1.5), Char('b'));
+        assert!(Char('a').checked_add(Float(::std::f64::NAN)).is_err());
+        assert!(Char('a').checked_add(Float(1e19)).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "0x110000 is not a valid char")]
+    fn test_char_arithmetic_panics () {
+        let _ = Char('\u{10FFFF}') + UInt(1);
+    }
+
+    #[test]
+    fn test_divide_by_zero () {
+        assert_eq!(SInt(1).checked_div(SInt(0)),
+                   Err(String::from("attempted to divide 1 by zero")));
+        assert_eq!(UInt(1).checked_rem(UInt(0)),
+                   Err(String::from("attempted to divide 1u by zero")));
+        assert!(SInt(i64::min_value()).checked_div(SInt(-1)).is_err());
+        assert_eq!(Float(1.0) / SInt(0), Float(::std::f64::INFINITY));
+    }
+
+    #[test]
+    fn test_integer_overflow () {
+        assert_eq!(UInt(1).checked_sub(UInt(2)),
+                   Err(String::from("overflow in 1u - 2u")));
+        assert!(SInt(i64::max_value()).checked_add(SInt(1)).is_err());
+        assert!(UInt(u64::max_value()).checked_mul(UInt(2)).is_err());
+        assert!(UInt(u64::max_value()).checked_add(SInt(1)).is_err());
+        assert_eq!(SInt(-1).checked_add(UInt(3)), Ok(SInt(2)));
+    }
+
+    #[test]
+    fn test_integer_wrapping () {
+        assert_eq!(UInt(1) - UInt(2), UInt(u64::max_value()));
+        assert_eq!(SInt(i64::max_value()) + SInt(1), SInt(i64::min_value()));
+        assert_eq!(UInt(u64::max_value()) * UInt(2), UInt(u64::max_value() - 1));
+        assert_eq!(SInt(i64::min_value()) / SInt(-1), SInt(i64::min_value()));
+        assert_eq!(SInt(-1) + UInt(3), SInt(2));
+    }
+
+    #[test]
+    fn test_map_keys () {
+        use super::MapKey;
+        use std::sync::Arc;
+        use super::SVMCell::*;
+        use ::slist::List::{Cons,Nil};
+
+        let cell = list_cell![ AtomCell(Char('h')), AtomCell(Char('i')) ];
+        assert_eq!(MapKey::from_cell(&cell), Ok(MapKey::Str(String::from("hi"))));
+        assert_eq!(MapKey::from_cell(&cell).unwrap().to_cell(), cell);
+        assert_eq!(MapKey::from_cell(&AtomCell(Float(-0.0))),
+                   MapKey::from_cell(&AtomCell(Float(0.0))));
+        assert_eq!(MapKey::from_cell(&AtomCell(Float(1.5))).unwrap().to_cell(),
+                   AtomCell(Float(1.5)));
+        assert!(MapKey::from_cell(&list_cell![ AtomCell(SInt(1)) ]).is_err());
+        assert!(MapKey::from_cell(&VectorCell(Arc::new(vec![]))).is_err());
+    }
+
+    #[test]
+    fn test_shared_cell () {
+        use super::SharedCell;
+        use super::SVMCell::*;
+
+        let shared = SharedCell::new(AtomCell(SInt(1)));
+        let copy = shared.clone();
+        assert_eq!(copy.set(AtomCell(SInt(2))), AtomCell(SInt(1)));
+        assert_eq!(shared.get(), AtomCell(SInt(2)));
+        assert!(shared.ptr_eq(&copy));
+        assert!(!shared.ptr_eq(&SharedCell::new(AtomCell(SInt(2)))));
+        assert_eq!(shared, SharedCell::new(AtomCell(SInt(2))));
+        assert!(shared != SharedCell::new(AtomCell(SInt(3))));
+        assert_eq!(SharedCell::new(BoxCell(shared.clone())),
+                   SharedCell::new(BoxCell(SharedCell::new(AtomCell(SInt(2))))));
+        assert_eq!(format!("{:?}", BoxCell(shared)), "#&2");
+    }
+
+    #[test]
+    fn test_shared_cell_cycle () {
+        use super::SharedCell;
+        use super::SVMCell::*;
+        use ::slist::List::{Cons,Nil};
+
+        let shared = SharedCell::new(list_cell![]);
+        shared.set(list_cell![ BoxCell(shared.clone()) ]);
+        assert_eq!(format!("{:?}", BoxCell(shared.clone())), "#&(#&... . nil)");
+        assert_eq!(shared, shared.clone());
+        let other = SharedCell::new(list_cell![]);
+        other.set(list_cell![ BoxCell(other.clone()) ]);
+        assert!(shared != other);
+        // break the cycles so the boxes can be dropped
+        shared.set(list_cell![]);
+        other.set(list_cell![]);
+    }
+
+    #[test]
+    fn test_shared_cell_poisoned () {
+        use super::SharedCell;
+        use super::SVMCell::*;
+        use std::thread;
+
+        let shared = SharedCell::new(AtomCell(SInt(1)));
+        let copy = shared.clone();
+        assert!(thread::spawn(move || {
+            let _contents = copy.0.lock().unwrap();
+            panic!("poisoning the box");
+        }).join().is_err());
+        assert_eq!(shared.set(AtomCell(SInt(2))), AtomCell(SInt(1)));
+        assert_eq!(shared.get(), AtomCell(SInt(2)));
+        assert_eq!(format!("{:?}", BoxCell(shared)), "#&2");
+    }
 }
@@ -0,0 +1,382 @@
+use super::VmError;
+use super::slist::Stack;
+use super::slist::List::{Cons,Nil};
+use super::cell::{SVMCell,Atom};
+use super::cell::SVMCell::*;
+use super::cell::Atom::*;
+
+use std::sync::Arc;
+
+/// Conversion of a host value into an SVM cell.
+#[cfg_attr(feature = "nightly", unstable(feature="convert"))]
+pub trait IntoSvm {
+    /// Converts this value into a cell.
+    #[cfg_attr(feature = "nightly", unstable(feature="convert"))]
+    fn into_svm(self) -> SVMCell;
+}
+
+/// Conversion of an SVM cell into a host value.
+#[cfg_attr(feature = "nightly", unstable(feature="convert"))]
+pub trait FromSvm: Sized {
+    /// Converts a cell into a value of this type, returning an error
+    /// describing the mismatch if the cell does not have the expected
+    /// shape.
+    #[cfg_attr(feature = "nightly", unstable(feature="convert"))]
+    fn from_svm(cell: SVMCell) -> Result<Self, VmError>;
+}
+
+/// Takes the items of a list cell, without cloning them.
+fn list_items(cell: SVMCell, expected: &str) -> Result<Vec<SVMCell>, VmError> {
+    match cell {
+        ListCell(box list) => {
+            let mut items = Vec::new();
+            let mut list = list;
+            while let Some((item, rest)) = list.pop() {
+                items.push(item);
+                list = rest;
+            }
+            Ok(items)
+        },
+        thing => Err(format!("expected {}, found {:?}", expected, thing))
+    }
+}
+
+impl IntoSvm for SVMCell {
+    fn into_svm(self) -> SVMCell { self }
+}
+
+impl FromSvm for SVMCell {
+    fn from_svm(cell: SVMCell) -> Result<SVMCell, VmError> { Ok(cell) }
+}
+
+impl IntoSvm for Atom {
+    fn into_svm(self) -> SVMCell { AtomCell(self) }
+}
+
+impl FromSvm for Atom {
+    fn from_svm(cell: SVMCell) -> Result<Atom, VmError> {
+        match cell {
+            AtomCell(atom) => Ok(atom),
+            thing          => Err(format!("expected atom, found {:?}", thing))
+        }
+    }
+}
+
+macro_rules! impl_convert_sint {
+    ( $( $t:ty ),+ ) => { $(
+        impl IntoSvm for $t {
+            fn into_svm(self) -> SVMCell { AtomCell(SInt(self as i64)) }
+        }
+
+        impl FromSvm for $t {
+            fn from_svm(cell: SVMCell) -> Result<$t, VmError> {
+                match cell {
+                    AtomCell(atom @ SInt(_)) | AtomCell(atom @ UInt(_)) =>
+                        match atom.to_sint() {
+                            Ok(SInt(a)) if a >= <$t>::min_value() as i64 &&
+                                           a <= <$t>::max_value() as i64 => Ok(a as $t),
+                            _ => Err(format!(
+                                "{:?} is out of range for {}", atom, stringify!($t)))
+                        },
+                    thing => Err(format!(
+                        "expected {}, found {:?}", stringify!($t), thing))
+                }
+            }
+        }
+    )+ }
+}
+
+macro_rules! impl_convert_uint {
+    ( $( $t:ty ),+ ) => { $(
+        impl IntoSvm for $t {
+            fn into_svm(self) -> SVMCell { AtomCell(UInt(self as u64)) }
+        }
+
+        impl FromSvm for $t {
+            fn from_svm(cell: SVMCell) -> Result<$t, VmError> {
+                match cell {
+                    AtomCell(atom @ SInt(_)) | AtomCell(atom @ UInt(_)) =>
+                        match atom.to_uint() {
+                            Ok(UInt(a)) if a <= <$t>::max_value() as u64 => Ok(a as $t),
+                            _ => Err(format!(
+                                "{:?} is out of range for {}", atom, stringify!($t)))
+                        },
+                    thing => Err(format!(
+                        "expected {}, found {:?}", stringify!($t), thing))
+                }
+            }
+        }
+    )+ }
+}
+
+impl_convert_sint!(i8, i16, i32, i64, isize);
+impl_convert_uint!(u8, u16, u32, u64, usize);
+
+/// Converts a cell to a float, for the conversions to `f64` and `f32`.
+fn float_from_svm(cell: SVMCell, expected: &str) -> Result<f64, VmError> {
+    match cell {
+        AtomCell(Float(a))                                  => Ok(a),
+        AtomCell(atom @ SInt(_)) | AtomCell(atom @ UInt(_)) =>
+            atom.to_float().map(|a| a.as_float()),
+        thing => Err(format!("expected {}, found {:?}", expected, thing))
+    }
+}
+
+impl IntoSvm for f64 {
+    fn into_svm(self) -> SVMCell { AtomCell(Float(self)) }
+}
+
+impl FromSvm for f64 {
+    fn from_svm(cell: SVMCell) -> Result<f64, VmError> {
+        float_from_svm(cell, "f64")
+    }
+}
+
+impl IntoSvm for f32 {
+    fn into_svm(self) -> SVMCell { AtomCell(Float(self as f64)) }
+}
+
+impl FromSvm for f32 {
+    fn from_svm(cell: SVMCell) -> Result<f32, VmError> {
+        float_from_svm(cell, "f32").map(|a| a as f32)
+    }
+}
+
+impl IntoSvm for char {
+    fn into_svm(self) -> SVMCell { AtomCell(Char(self)) }
+}
+
+impl FromSvm for char {
+    fn from_svm(cell: SVMCell) -> Result<char, VmError> {
+        match cell {
+            AtomCell(Char(a)) => Ok(a),
+            thing             => Err(format!("expected char, found {:?}", thing))
+        }
+    }
+}
+
+/// Booleans are converted as `SEL` and the predicate instructions treat
+/// them: true is `(1)`, false is `nil`, and any cell other than `nil` is
+/// read back as true.
+impl IntoSvm for bool {
+    fn into_svm(self) -> SVMCell {
+        match self {
+            true    => list_cell![AtomCell(SInt(1))],
+            false   => list_cell![]
+        }
+    }
+}
+
+impl FromSvm for bool {
+    fn from_svm(cell: SVMCell) -> Result<bool, VmError> {
+        match cell {
+            ListCell(box Nil) => Ok(false),
+            _                 => Ok(true)
+        }
+    }
+}
+
+/// Strings are converted to and from lists of chars.
+impl<'a> IntoSvm for &'a str {
+    fn into_svm(self) -> SVMCell {
+        ListCell(Box::new(self.chars().map(|ch| AtomCell(Char(ch))).collect()))
+    }
+}
+
+impl IntoSvm for String {
+    fn into_svm(self) -> SVMCell { (&self[..]).into_svm() }
+}
+
+impl FromSvm for String {
+    fn from_svm(cell: SVMCell) -> Result<String, VmError> {
+        let items = try!(list_items(cell, "string"));
+        items.into_iter().enumerate()
+            .map(|(i, item)| match item {
+                AtomCell(Char(ch)) => Ok(ch),
+                thing => Err(format!(
+                    "expected string, found {:?} at index {}", thing, i))
+            })
+            .collect()
+    }
+}
+
+/// Vectors are converted to lists. Lists and vector cells may both be
+/// converted back into vectors.
+impl<T> IntoSvm for Vec<T> where T: IntoSvm {
+    fn into_svm(self) -> SVMCell {
+        ListCell(Box::new(self.into_iter().map(IntoSvm::into_svm).collect()))
+    }
+}
+
+impl<T> FromSvm for Vec<T> where T: FromSvm {
+    fn from_svm(cell: SVMCell) -> Result<Vec<T>, VmError> {
+        let items = match cell {
+            VectorCell(items) => Arc::try_unwrap(items).unwrap_or_else(|items| (*items).clone()),
+            thing             => try!(list_items(thing, "list"))
+        };
+        items.into_iter().enumerate()
+            .map(|(i, item)| T::from_svm(item)
+                .map_err(|why| format!("{} at index {}", why, i)))
+            .collect()
+    }
+}
+
+/// `None` is converted to `nil`, and `Some` to the converted value; note
+/// that this means that a `Some` whose value converts to `nil` will be
+/// read back as `None`.
+impl<T> IntoSvm for Option<T> where T: IntoSvm {
+    fn into_svm(self) -> SVMCell {
+        match self {
+            Some(value) => value.into_svm(),
+            None        => list_cell![]
+        }
+    }
+}
+
+impl<T> FromSvm for Option<T> where T: FromSvm {
+    fn from_svm(cell: SVMCell) -> Result<Option<T>, VmError> {
+        match cell {
+            ListCell(box Nil) => Ok(None),
+            thing             => T::from_svm(thing).map(Some)
+        }
+    }
+}
+
+macro_rules! impl_convert_tuple {
+    ( $len:expr; $( $name:ident ),+ ) => {
+        /// Tuples are converted to lists of the same length.
+        impl<$( $name ),+> IntoSvm for ( $( $name, )+ ) where $( $name: IntoSvm ),+ {
+            #[allow(non_snake_case)]
+            fn into_svm(self) -> SVMCell {
+                let ( $( $name, )+ ) = self;
+                ListCell(Box::new(list!( $( $name.into_svm() ),+ )))
+            }
+        }
+
+        impl<$( $name ),+> FromSvm for ( $( $name, )+ ) where $( $name: FromSvm ),+ {
+            fn from_svm(cell: SVMCell) -> Result<( $( $name, )+ ), VmError> {
+                let expected = format!("list of length {}", $len);
+                let items = try!(list_items(cell, &expected));
+                if items.len() != $len {
+                    return Err(format!(
+                        "expected {}, found list of length {}", expected, items.len()))
+                }
+                let mut items = items.into_iter();
+                Ok(( $( try!($name::from_svm(items.next().unwrap())), )+ ))
+            }
+        }
+    }
+}
+
+impl_convert_tuple!(1; A);
+impl_convert_tuple!(2; A, B);
+impl_convert_tuple!(3; A, B, C);
+impl_convert_tuple!(4; A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::{IntoSvm,FromSvm};
+    use ::slist::List::{Cons,Nil};
+    use ::cell::SVMCell;
+    use ::cell::SVMCell::*;
+    use ::cell::Atom::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_convert_numbers () {
+        assert_eq!(10i32.into_svm(), AtomCell(SInt(10)));
+        assert_eq!(10u8.into_svm(), AtomCell(UInt(10)));
+        assert_eq!(1.5f64.into_svm(), AtomCell(Float(1.5)));
+        assert_eq!(i32::from_svm(AtomCell(SInt(-3))), Ok(-3));
+        assert_eq!(u16::from_svm(AtomCell(SInt(3))), Ok(3));
+        assert_eq!(f64::from_svm(AtomCell(UInt(2))), Ok(2.0));
+        assert_eq!(
+            u8::from_svm(AtomCell(SInt(256))),
+            Err(String::from("256 is out of range for u8"))
+        );
+        assert_eq!(
+            i64::from_svm(AtomCell(Char('a'))),
+            Err(String::from("expected i64, found 'a'"))
+        );
+    }
+
+    #[test]
+    fn test_convert_chars_and_strings () {
+        assert_eq!('λ'.into_svm(), AtomCell(Char('λ')));
+        assert_eq!(char::from_svm(AtomCell(Char('λ'))), Ok('λ'));
+        assert_eq!(
+            "hi".into_svm(),
+            list_cell![ AtomCell(Char('h')), AtomCell(Char('i')) ]
+        );
+        assert_eq!(String::from_svm("hi".into_svm()), Ok(String::from("hi")));
+        assert_eq!(
+            String::from_svm(list_cell![ AtomCell(Char('h')), AtomCell(SInt(1)) ]),
+            Err(String::from("expected string, found 1 at index 1"))
+        );
+    }
+
+    #[test]
+    fn test_convert_bool () {
+        assert_eq!(true.into_svm(), list_cell![ AtomCell(SInt(1)) ]);
+        assert_eq!(false.into_svm(), list_cell![]);
+        assert_eq!(bool::from_svm(AtomCell(SInt(0))), Ok(true));
+        assert_eq!(bool::from_svm(list_cell![]), Ok(false));
+    }
+
+    #[test]
+    fn test_convert_vec () {
+        assert_eq!(
+            vec![1i64, 2, 3].into_svm(),
+            list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)), AtomCell(SInt(3)) ]
+        );
+        assert_eq!(Vec::<i64>::from_svm(vec![1i64, 2].into_svm()), Ok(vec![1, 2]));
+        assert_eq!(
+            Vec::<u8>::from_svm(VectorCell(Arc::new(vec![ AtomCell(UInt(7)) ]))),
+            Ok(vec![7])
+        );
+        assert_eq!(
+            Vec::<i64>::from_svm(list_cell![ AtomCell(SInt(1)), AtomCell(Char('a')) ]),
+            Err(String::from("expected i64, found 'a' at index 1"))
+        );
+    }
+
+    #[test]
+    fn test_convert_option () {
+        assert_eq!(Some(1i64).into_svm(), AtomCell(SInt(1)));
+        assert_eq!(None::<i64>.into_svm(), list_cell![]);
+        assert_eq!(Option::<i64>::from_svm(AtomCell(SInt(1))), Ok(Some(1)));
+        assert_eq!(Option::<i64>::from_svm(list_cell![]), Ok(None));
+    }
+
+    #[test]
+    fn test_convert_tuples () {
+        let cell = (1i64, 'a', "b").into_svm();
+        assert_eq!(
+            cell,
+            list_cell![
+                AtomCell(SInt(1)),
+                AtomCell(Char('a')),
+                list_cell![ AtomCell(Char('b')) ]
+            ]
+        );
+        assert_eq!(
+            <(i64, char, String)>::from_svm(cell),
+            Ok((1, 'a', String::from("b")))
+        );
+        assert_eq!(
+            <(i64, i64)>::from_svm(vec![1i64].into_svm()),
+            Err(String::from("expected list of length 2, found list of length 1"))
+        );
+        assert_eq!(
+            <(i64,)>::from_svm(AtomCell(SInt(1))),
+            Err(String::from("expected list of length 1, found 1"))
+        );
+    }
+
+    #[test]
+    fn test_convert_cells () {
+        let cell: SVMCell = list_cell![ AtomCell(SInt(1)) ];
+        assert_eq!(SVMCell::from_svm(cell.clone()), Ok(cell.clone()));
+        assert_eq!(cell.clone().into_svm(), cell);
+    }
+}
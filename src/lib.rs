@@ -13,6 +13,7 @@
 
 #[macro_use] extern crate log;
 extern crate byteorder;
+#[cfg(feature = "signing")] extern crate ed25519_dalek;
 
 /// Singly-linked list and stack implementations.
 ///
@@ -35,6 +36,21 @@ pub mod cell;
 #[cfg_attr(feature = "nightly", unstable(feature="bytecode"))]
 pub mod bytecode;
 
+/// Conversions between host values and SVM cells.
+///
+/// The `IntoSvm` and `FromSvm` traits are implemented for Rust numbers,
+/// chars, strings, booleans, vectors, tuples and options, so that code
+/// embedding the SVM can build arguments and read results concisely.
+#[cfg_attr(feature = "nightly", unstable(feature="convert"))]
+pub mod convert;
+
+/// The SVM machine.
+///
+/// A `Machine` evaluates a `State` along with the resources owned by the
+/// host which embeds it, such as native functions.
+#[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+pub mod machine;
+
 #[cfg(test)]
 mod tests;
 
@@ -44,12 +60,21 @@ mod tests;
 // Reexports
 pub use self::slist::{List, Stack};
 pub use self::slist::List::{Cons,Nil};
-pub use self::cell::{SVMCell,Atom,Inst};
+pub use self::cell::{SVMCell,Atom,Inst,MapKey,SharedCell,Channel};
+pub use self::machine::{Machine,LogEntry};
+pub use self::machine::scheduler::Scheduler;
+pub use self::machine::pool::{Pool,Limits};
+pub use self::convert::{IntoSvm,FromSvm};
 
 use self::cell::SVMCell::*;
 use self::cell::Atom::*;
 use self::cell::Inst::*;
 
+use std::io;
+use std::mem;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
 /// Represents a SVM machine state
 #[derive(PartialEq,Clone,Debug)]
 #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.1.0"))]
@@ -72,12 +97,52 @@ pub enum IOEvent {
     /// A character was requested from the buffer
     Req,
     /// A character was buffered
-    Buf(char)
+    Buf(char),
+    /// A sequence of bytes was buffered
+    BufBytes(Vec<u8>)
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="eval"))]
+impl IOEvent {
+    /// Writes the character or bytes buffered by this event to an output
+    /// stream.
+    ///
+    /// A character is written as a complete UTF-8 sequence, so characters
+    /// outside of ASCII are written as multiple bytes; bytes are written
+    /// as-is. Events which do not buffer anything write nothing.
+    #[cfg_attr(feature = "nightly", unstable(feature="eval"))]
+    pub fn write_to<W>(&self, out: &mut W) -> io::Result<()>
+        where W: io::Write
+    {
+        match *self {
+            IOEvent::Buf(ch) => {
+                let mut buf = [0; 4];
+                out.write_all(ch.encode_utf8(&mut buf).as_bytes())
+            },
+            IOEvent::BufBytes(ref bytes) => out.write_all(bytes),
+            IOEvent::Req => Ok(())
+        }
+    }
 }
 
 #[cfg_attr(feature = "nightly", unstable(feature="eval"))]
 pub type EvalResult = Result<(State,Option<IOEvent>), String>;
 
+/// An error raised while evaluating a program.
+#[cfg_attr(feature = "nightly", unstable(feature="eval"))]
+pub type VmError = String;
+
+/// The greatest number of cells in a vector made by `VMAKE`, counting both
+/// its items and the cells copied along with each of them. Vectors, boxes
+/// and channels held by an item are shared by its copies, so count as one
+/// cell, but lists and maps are copied, and each byte of a byte buffer
+/// counts as a cell.
+///
+/// Since the length of the vector is taken from the program, this stops a
+/// program from exhausting the host's memory within a single step.
+#[cfg_attr(feature = "nightly", unstable(feature="vector"))]
+pub const MAX_VECTOR_LEN: usize = 1 << 24;
+
 #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.1.0"))]
 impl State {
 
@@ -126,23 +191,39 @@ impl State {
     ///     provides more detailed debugging information on errors, but may have
     ///     a significant impact on performance.
     ///
+    /// If an error occurs while an exception handler installed by `TRY`
+    /// is on the dump, the error is passed to that handler rather than
+    /// returned.
+    ///
     #[cfg_attr(feature = "nightly", stable(feature="vm_core", since="0.3.0"))]
     pub fn eval(self,
                 input: Option<u8>,
                 debug: bool)
                 -> EvalResult {
+        let prev = if debug { Some(self.clone()) } else { None };
+        catch(self, prev.as_ref(), |state, dump, prev| state.step(dump, prev, input))
+    }
+
+    /// Evaluates an instruction, returning any errors that occur.
+    ///
+    /// The dump is passed separately from the rest of the state, and is
+    /// only taken from `dump` by instructions which succeed.
+    fn step(self,
+            dump: &mut List<SVMCell>,
+            mut prev: Option<&State>,
+            input: Option<u8>)
+            -> EvalResult {
         debug!("[eval]: Evaluating {:?}", self.control);
-        // TODO: this (by which I mean "the whole caching deal") could likely be made
-        // better and/or faster with some clever (mis?)use of RefCell; look into that.
-        let mut prev = if debug { Some(self.clone()) } else { None };
         // in ths pattern match, we begin The Great Work
-        match self.control.pop().unwrap() {
+        match try!(self.control.pop().ok_or_else(|| format!(
+            "[fatal]: expected an instruction on control stack\n{}",
+            prev.as_ref().map_or(String::new(), |x| x.dump_state("fatal") )))) {
             // NIL: pop an empty list onto the stack
             (InstCell(NIL), new_control) => Ok((State {
                 stack: self.stack.push(list_cell![]),
                 env: self.env,
                 control: new_control,
-                dump: self.dump
+                dump: take_dump(dump)
             }, None)),
             // LDC: load constant
             (InstCell(LDC), new_control) => {
@@ -153,7 +234,7 @@ impl State {
                     stack: self.stack.push(atom),
                     env: self.env,
                     control: newer_control,
-                    dump: self.dump
+                    dump: take_dump(dump)
                 }, None))
             },
             // LD: load variable
@@ -162,43 +243,56 @@ impl State {
                     box Cons(AtomCell(UInt(lvl)),
                     box Cons(AtomCell(UInt(idx)),
                     box Nil))
-                    ), newer_control)) => match self.env[(lvl-1)] {
-                        ListCell(ref level) => Ok((State {
-                            stack: match level.get(idx-1) {
-                                Some(thing) => self.stack.push(thing.clone()),
-                                None        => self.stack
-                            },
-                            env: self.env.clone(),
-                            control: newer_control,
-                            dump: self.dump
-                        }, None)),
+                    ), newer_control)) => match nth_from_one(&self.env, lvl) {
+                        Some(&ListCell(ref level)) => match nth_from_one(level, idx) {
+                            Some(thing) => Ok((State {
+                                stack: self.stack.push(thing.clone()),
+                                env: self.env.clone(),
+                                control: newer_control,
+                                dump: take_dump(dump)
+                            }, None)),
+                            None => Err(format!(
+                                "[fatal][LD]: no variable at ({}, {}) in $e\n{}",
+                                lvl, idx, prev.map_or(String::new(), |x| x.dump_state("fatal") )))
+                        },
                         // This is a special case for something that, as far as I know,
                         // should never happen. But despite everything, it DOES happen.
-                        ref thing @ AtomCell(_) => Ok((State {
+                        Some(thing @ &AtomCell(_)) => Ok((State {
                         // I give up. Have your special case.
                             stack: self.stack.push(thing.clone()),
                             env: self.env.clone(),
                             control: newer_control,
-                            dump: self.dump
+                            dump: take_dump(dump)
                         }, None)),
-                        _ => Err(format!(
+                        Some(thing) => Err(format!(
                             "[fatal][LD]: expected list in $e, found {:?}\n{}",
-                            self.env[lvl-1], prev.map_or(String::new(), |x| x.dump_state("fatal") )))
+                            thing, prev.map_or(String::new(), |x| x.dump_state("fatal") ))),
+                        None => Err(format!(
+                            "[fatal][LD]: no level {} in $e\n{}",
+                            lvl, prev.map_or(String::new(), |x| x.dump_state("fatal") )))
                 },
                Some((ListCell( // TODO: this uses deprecated signed int indexing, remove
                     box Cons(AtomCell(SInt(lvl)),
                     box Cons(AtomCell(SInt(idx)),
                     box Nil))
-                    ), newer_control)) =>  match self.env[(lvl-1)] {
-                        ListCell(ref level) => Ok((State {
-                            stack: self.stack.push(level[(idx-1)].clone()),
-                            env: self.env.clone(),
-                            control: newer_control,
-                            dump: self.dump
-                        }, None)),
-                        _ => Err(format!(
+                    ), newer_control)) =>  match nth_from_one(&self.env, lvl as u64) {
+                        Some(&ListCell(ref level)) => match nth_from_one(level, idx as u64) {
+                            Some(thing) => Ok((State {
+                                stack: self.stack.push(thing.clone()),
+                                env: self.env.clone(),
+                                control: newer_control,
+                                dump: take_dump(dump)
+                            }, None)),
+                            None => Err(format!(
+                                "[fatal][LD]: no variable at ({}, {}) in $e\n{}",
+                                lvl, idx, prev.map_or(String::new(), |x| x.dump_state("fatal") )))
+                        },
+                        Some(thing) => Err(format!(
                             "[fatal][LD]: expected list in $e, found {:?}\n{}",
-                            self.env[lvl-1], prev.map_or(String::new(), |x| x.dump_state("fatal") )))
+                            thing, prev.map_or(String::new(), |x| x.dump_state("fatal") ))),
+                        None => Err(format!(
+                            "[fatal][LD]: no level {} in $e\n{}",
+                            lvl, prev.map_or(String::new(), |x| x.dump_state("fatal") )))
                 },
                Some((thing,newer_control)) => Err(format!(
                     "[fatal][LD]: expected pair, found {:?}\n[fatal] new control: {:?}\n{}",
@@ -216,22 +310,36 @@ impl State {
                     Some(thing) => Ok(thing),
                     None        => Err(format!(
                         "[fatal][LDF]: pop on empty control stack\n{}",
-                        prev.map_or(String::new(), |x| x.dump_state("fatal") )))
+                        prev.as_ref().map_or(String::new(), |x| x.dump_state("fatal") )))
                 });
+                let env = self.env.get(0)
+                    .map_or(list_cell![], |it| it.clone());
+                // an arity, if given, precedes the function body
+                let (closure, newer_control) = match func {
+                    spec @ ListCell(box Cons(AtomCell(UInt(_)), _)) => {
+                        try!(parse_arity(&spec).ok_or_else(|| format!(
+                            "[fatal][LDF]: malformed arity {:?}\n{}",
+                            spec, prev.as_ref().map_or(String::new(), |x| x.dump_state("fatal") ))));
+                        match newer_control.pop() {
+                            Some((body, newest_control)) =>
+                                (list_cell![ body, env, spec ], newest_control),
+                            None => return Err(format!(
+                                "[fatal][LDF]: expected function body after arity\n{}",
+                                prev.map_or(String::new(), |x| x.dump_state("fatal") )))
+                        }
+                    },
+                    body => (list_cell![ body, env ], newer_control)
+                };
                 Ok((State {
-                    stack: self.stack.push(list_cell![
-                        func,
-                        self.env.get(0)
-                            .map_or(list_cell![], |it| it.clone())
-                        ]),
+                    stack: self.stack.push(closure),
                     env: self.env,
                     control: newer_control,
-                    dump: self.dump
+                    dump: take_dump(dump)
                 }, None))
             },
 
             (InstCell(JOIN), new_control) => {
-                let (top, new_dump) = try!(match self.dump.pop() {
+                let (top, new_dump) = try!(match take_dump(dump).pop() {
                     Some(thing) => Ok(thing),
                     None        => Err(format!(
                         "[fatal][JOIN]: pop on empty dump stack") )
@@ -249,19 +357,29 @@ impl State {
                         control: it,
                         dump: new_dump
                     }, None)),
-                    anything          => Err(format!(
-                        "[fatal][JOIN]: expected list on dump, found {:?}\n{}",
-                        anything, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    anything          => {
+                        let why = format!(
+                            "[fatal][JOIN]: expected list on dump, found {:?}\n{}",
+                            anything, prev.map_or(String::new(), |x| x.dump_state("fatal") ));
+                        *dump = new_dump.push(anything);
+                        Err(why)
+                    }
                 }
             },
             (InstCell(ADD), new_control) => match self.stack.pop() {
                 Some((AtomCell(op1), new_stack)) => match new_stack.pop() {
-                    Some((AtomCell(op2), newer_stack)) => Ok((State {
-                            stack: newer_stack.push(AtomCell(op1 + op2)),
+                    Some((AtomCell(op2), newer_stack)) => match op1.checked_add(op2) {
+                        Ok(result) => Ok((State {
+                            stack: newer_stack.push(AtomCell(result)),
                             env: self.env,
                             control: new_control,
-                            dump: self.dump
+                            dump: take_dump(dump)
                         }, None)),
+                        Err(why) => Err(format!(
+                            "[fatal][ADD]: {}\n{}",
+                            why,
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
                     any => Err(format!(
                         "[fatal][ADD]: expected second operand, found {:?}\n{}",
                         any,
@@ -274,12 +392,18 @@ impl State {
             },
             (InstCell(SUB), new_control) => match self.stack.pop() {
                 Some((AtomCell(op1), new_stack)) => match new_stack.pop() {
-                    Some((AtomCell(op2), newer_stack)) => Ok((State {
-                            stack: newer_stack.push(AtomCell(op1 - op2)),
+                    Some((AtomCell(op2), newer_stack)) => match op1.checked_sub(op2) {
+                        Ok(result) => Ok((State {
+                            stack: newer_stack.push(AtomCell(result)),
                             env: self.env,
                             control: new_control,
-                            dump: self.dump
+                            dump: take_dump(dump)
                         }, None)),
+                        Err(why) => Err(format!(
+                            "[fatal][SUB]: {}\n{}",
+                            why,
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
                     any => Err(format!(
                         "[fatal][SUB]: expected second operand, found {:?}\n{}",
                         any,
@@ -321,18 +445,18 @@ impl State {
                                         (SInt(a), UInt(b))      => Float(a as f64 / b as f64),
                                         // char + any: coerce to int -> float
                                         // but if you ever actually do this, then ...wat?
-                                        (Char(a), Char(b))      => Float(a as u8 as f64 / b as u8 as f64),
-                                        (Char(a), UInt(b))      => Float(a as u8 as f64 / b as f64),
-                                        (Char(a), SInt(b))      => Float(a as u8 as f64 / b as f64),
-                                        (Char(a), Float(b))     => Float(a as u8 as f64 / b as f64),
-                                        (UInt(a), Char(b))      => Float(a as f64 / b as u8 as f64),
-                                        (SInt(a), Char(b))      => Float(a as f64 / b as u8 as f64),
-                                        (Float(a), Char(b))     => Float(a as f64 / b as u8 as f64)
+                                        (Char(a), Char(b))      => Float(a as u32 as f64 / b as u32 as f64),
+                                        (Char(a), UInt(b))      => Float(a as u32 as f64 / b as f64),
+                                        (Char(a), SInt(b))      => Float(a as u32 as f64 / b as f64),
+                                        (Char(a), Float(b))     => Float(a as u32 as f64 / b as f64),
+                                        (UInt(a), Char(b))      => Float(a as f64 / b as u32 as f64),
+                                        (SInt(a), Char(b))      => Float(a as f64 / b as u32 as f64),
+                                        (Float(a), Char(b))     => Float(a as f64 / b as u32 as f64)
                                     }
                                     )),
                                 env: self.env,
                                 control: new_control,
-                                dump: self.dump
+                                dump: take_dump(dump)
                             }, None)),
                             b => Err(format!(
                                 "[fatal][FDIV]: TypeError: expected compatible operands, found (FDIV {:?} {:?})", a, b) )
@@ -344,12 +468,18 @@ impl State {
             },
             (InstCell(DIV), new_control) => match self.stack.pop() {
                 Some((AtomCell(op1), new_stack)) => match new_stack.pop() {
-                    Some((AtomCell(op2), newer_stack)) => Ok((State {
-                            stack: newer_stack.push(AtomCell(op1 / op2)),
+                    Some((AtomCell(op2), newer_stack)) => match op1.checked_div(op2) {
+                        Ok(result) => Ok((State {
+                            stack: newer_stack.push(AtomCell(result)),
                             env: self.env,
                             control: new_control,
-                            dump: self.dump
-                        },None)),
+                            dump: take_dump(dump)
+                        }, None)),
+                        Err(why) => Err(format!(
+                            "[fatal][DIV]: {}\n{}",
+                            why,
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
                     any => Err(format!(
                         "[fatal][DIV]: expected second operand, found {:?}\n{}",
                         any,
@@ -362,12 +492,18 @@ impl State {
             },
             (InstCell(MUL), new_control) => match self.stack.pop() {
                 Some((AtomCell(op1), new_stack)) => match new_stack.pop() {
-                    Some((AtomCell(op2), newer_stack)) => Ok((State {
-                            stack: newer_stack.push(AtomCell(op1 * op2)),
+                    Some((AtomCell(op2), newer_stack)) => match op1.checked_mul(op2) {
+                        Ok(result) => Ok((State {
+                            stack: newer_stack.push(AtomCell(result)),
                             env: self.env,
                             control: new_control,
-                            dump: self.dump
+                            dump: take_dump(dump)
                         }, None)),
+                        Err(why) => Err(format!(
+                            "[fatal][MUL]: {}\n{}",
+                            why,
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
                     any => Err(format!(
                         "[fatal][MUL]: expected second operand, found {:?}\n{}",
                         any,
@@ -380,12 +516,18 @@ impl State {
             },
             (InstCell(MOD), new_control) => match self.stack.pop() {
                 Some((AtomCell(op1), new_stack)) => match new_stack.pop() {
-                    Some((AtomCell(op2), newer_stack)) => Ok((State {
-                            stack: newer_stack.push(AtomCell(op1 % op2)),
+                    Some((AtomCell(op2), newer_stack)) => match op1.checked_rem(op2) {
+                        Ok(result) => Ok((State {
+                            stack: newer_stack.push(AtomCell(result)),
                             env: self.env,
                             control: new_control,
-                            dump: self.dump
+                            dump: take_dump(dump)
                         }, None)),
+                        Err(why) => Err(format!(
+                            "[fatal][MOD]: {}\n{}",
+                            why,
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
                     any => Err(format!(
                         "[fatal][MOD]: expected second operand, found {:?}\n{}",
                         any,
@@ -396,11 +538,9 @@ impl State {
                     any,
                     prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(EQ), new_control) => {
-                let (op1, new_stack) = self.stack.pop().unwrap();
-                let (op2, newer_stack) = new_stack.pop().unwrap();
-                match (op1,op2) {
-                    (AtomCell(a), AtomCell(b)) => Ok((State {
+            (InstCell(EQ), new_control) => match self.stack.pop() {
+                Some((AtomCell(a), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(b), newer_stack)) => Ok((State {
                         stack: newer_stack.push(
                             match a == b {
                                 true    => list_cell![AtomCell(SInt(1))],
@@ -408,16 +548,21 @@ impl State {
                             }),
                         env: self.env,
                         control: new_control,
-                        dump: self.dump
+                        dump: take_dump(dump)
                     }, None)),
-                    (_,_) => unimplemented!() // TODO: sane error pls
-                }
+                    any => Err(format!(
+                        "[fatal][EQ]: expected second operand, found {:?}\n{}",
+                        any,
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][EQ]: expected first operand, found {:?}\n{}",
+                    any,
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(GT), new_control) => {
-                let (op1, new_stack) = self.stack.pop().unwrap();
-                let (op2, newer_stack) = new_stack.pop().unwrap();
-                match (op1,op2) {
-                    (AtomCell(a), AtomCell(b)) => Ok((State {
+            (InstCell(GT), new_control) => match self.stack.pop() {
+                Some((AtomCell(a), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(b), newer_stack)) => Ok((State {
                         stack: newer_stack.push(
                             match a > b {
                                 true    => list_cell![AtomCell(SInt(1))],
@@ -426,16 +571,21 @@ impl State {
                         ),
                         env: self.env,
                         control: new_control,
-                        dump: self.dump
+                        dump: take_dump(dump)
                     }, None)),
-                (_,_) => unimplemented!()
-                }
+                    any => Err(format!(
+                        "[fatal][GT]: expected second operand, found {:?}\n{}",
+                        any,
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][GT]: expected first operand, found {:?}\n{}",
+                    any,
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(GTE), new_control) => {
-                let (op1, new_stack) = self.stack.pop().unwrap();
-                let (op2, newer_stack) = new_stack.pop().unwrap();
-                match (op1,op2) {
-                    (AtomCell(a), AtomCell(b)) => Ok((State {
+            (InstCell(GTE), new_control) => match self.stack.pop() {
+                Some((AtomCell(a), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(b), newer_stack)) => Ok((State {
                         stack: newer_stack.push(
                             match a >= b {
                                 true    => list_cell![AtomCell(SInt(1))],
@@ -444,16 +594,21 @@ impl State {
                         ),
                         env: self.env,
                         control: new_control,
-                        dump: self.dump
+                        dump: take_dump(dump)
                     }, None)),
-                (_,_) => unimplemented!()
-                }
+                    any => Err(format!(
+                        "[fatal][GTE]: expected second operand, found {:?}\n{}",
+                        any,
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][GTE]: expected first operand, found {:?}\n{}",
+                    any,
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(LT), new_control) => {
-                let (op1, new_stack) = self.stack.pop().unwrap();
-                let (op2, newer_stack) = new_stack.pop().unwrap();
-                match (op1,op2) {
-                    (AtomCell(a), AtomCell(b)) => Ok((State {
+            (InstCell(LT), new_control) => match self.stack.pop() {
+                Some((AtomCell(a), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(b), newer_stack)) => Ok((State {
                         stack: newer_stack.push(
                             match a < b {
                                 true    => list_cell![AtomCell(SInt(1))],
@@ -462,16 +617,21 @@ impl State {
                         ),
                         env: self.env,
                         control: new_control,
-                        dump: self.dump
+                        dump: take_dump(dump)
                     }, None)),
-                (_,_) => unimplemented!()
-                }
+                    any => Err(format!(
+                        "[fatal][LT]: expected second operand, found {:?}\n{}",
+                        any,
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][LT]: expected first operand, found {:?}\n{}",
+                    any,
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(LTE), new_control) => {
-                let (op1, new_stack) = self.stack.pop().unwrap();
-                let (op2, newer_stack) = new_stack.pop().unwrap();
-                match (op1,op2) {
-                    (AtomCell(a), AtomCell(b)) => Ok((State {
+            (InstCell(LTE), new_control) => match self.stack.pop() {
+                Some((AtomCell(a), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(b), newer_stack)) => Ok((State {
                         stack: newer_stack.push(
                             match a <= b {
                                 true    => list_cell![AtomCell(SInt(1))],
@@ -479,14 +639,20 @@ impl State {
                             }),
                         env: self.env,
                         control: new_control,
-                        dump: self.dump
+                        dump: take_dump(dump)
                     }, None)),
-                (_,_) => unimplemented!()
-                }
+                    any => Err(format!(
+                        "[fatal][LTE]: expected second operand, found {:?}\n{}",
+                        any,
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][LTE]: expected first operand, found {:?}\n{}",
+                    any,
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(ATOM), new_control) => {
-                let (target, new_stack) = self.stack.pop().unwrap();
-                Ok((State {
+            (InstCell(ATOM), new_control) => match self.stack.pop() {
+                Some((target, new_stack)) => Ok((State {
                     stack: new_stack.push(
                         match target {
                             AtomCell(_) => list_cell![AtomCell(SInt(1))],
@@ -495,29 +661,34 @@ impl State {
                         ),
                     env: self.env,
                     control: new_control,
-                    dump: self.dump
-                },None))
+                    dump: take_dump(dump)
+                }, None)),
+                None => Err(format!(
+                    "[fatal][ATOM]: expected non-empty stack\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(AP), new_control) => match self.stack.pop().unwrap() {
-                (ListCell(box Cons(ListCell(box func), box Cons(ListCell(params), box Nil))), new_stack) => {
+            (InstCell(AP), new_control) => match self.stack.pop() {
+                Some((ListCell(box Cons(ListCell(box func), box Cons(ListCell(params), box arity))), new_stack)) => {
                         match new_stack.pop() {
                             Some((v, newer_stack)) => Ok((State {
                                 stack: Stack::empty(),
-                                env: match v {
-                                    ListCell(_) => params.push(v),
-                                    _           => list!(v)
+                                env: match try!(bind_args(&arity, v).map_err(|why| format!(
+                                    "[fatal][AP]: {}\n{}",
+                                    why, prev.as_ref().map_or(String::new(), |x| x.dump_state("fatal") )))) {
+                                    v @ ListCell(_) => params.push(v),
+                                    v               => list!(v)
                                 },
                                 control: func,
-                                dump: self.dump
-                                    .push(ListCell(Box::new(newer_stack)))
-                                    .push(ListCell(Box::new(self.env)))
+                                dump: take_dump(dump)
                                     .push(ListCell(Box::new(new_control)))
+                                    .push(ListCell(Box::new(self.env)))
+                                    .push(ListCell(Box::new(newer_stack)))
                             }, None)),/*
                             Some((v @ AtomCell(_), newer_stack)) => State {
                                 stack: Stack::empty(),
                                 env: list!( params,ListCell(box list!(v)) ),
                                 control: func,
-                                dump: self.dump
+                                dump: take_dump(dump)
                                     .push(ListCell(box newer_stack))
                                     .push(ListCell(box self.env))
                                     .push(ListCell(box new_control))
@@ -531,65 +702,114 @@ impl State {
                                 prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
                         }
                 },
-                (_, thing) => Err(format!(
+                Some((_, thing)) => Err(format!(
                     "[fatal][AP]: Expected closure on stack, got:\n[fatal]\t{:?}\n{}",
-                    thing, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    thing, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                None => Err(format!(
+                    "[fatal][AP]: expected closure on stack, found nothing\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(RAP), new_control) => match self.stack.pop().unwrap() {
-                (ListCell(box Cons(ListCell(box func), box Cons(ListCell(box params), box Nil))), new_stack) => {
-                    match new_stack.pop() {
-                        Some((v @ ListCell(_), newer_stack)) => Ok(( State {
+            (InstCell(RAP), new_control) => match self.stack.pop() {
+                Some((ListCell(box Cons(ListCell(box func), box Cons(ListCell(box params), box arity))), new_stack)) => {
+                    match (new_stack.pop(), self.env.pop()) {
+                        (Some((v @ ListCell(_), newer_stack)), Some((_, new_env))) => Ok(( State {
                             stack: Stack::empty(),
-                            env: params.push(v),
+                            env: params.push(try!(bind_args(&arity, v).map_err(|why| format!(
+                                "[fatal][RAP]: {}\n{}",
+                                why, prev.as_ref().map_or(String::new(), |x| x.dump_state("fatal") ))))),
                             control: func,
-                            dump: self.dump
+                            dump: take_dump(dump)
                                     .push(ListCell(Box::new(new_control)))
-                                    .push(ListCell(Box::new(self.env.pop().unwrap().1)))
+                                    .push(ListCell(Box::new(new_env)))
                                     .push(ListCell(Box::new(newer_stack)))
                         }, None)),
-                        Some((thing, _)) => Err(format!(
+                        (Some((ListCell(_), _)), None) => Err(format!(
+                            "[fatal][RAP]: expected dummy environment, found nothing\n{}",
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                        (Some((thing, _)), _) => Err(format!(
                             "[fatal][RAP]: Expected closure on stack, got:\n[fatal]\t{:?}\n{}",
                             thing, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
-                        None => Err(format!(
+                        (None, _) => Err(format!(
                             "[fatal][RAP]: expected non-empty stack\n{}",
                             prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
                     }
                 },
-                (_, thing) => Err(format!(
+                Some((_, thing)) => Err(format!(
                     "[fatal][RAP]: Expected closure on stack, got:\n[fatal]\t{:?}\n{}",
-                    thing, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
-            },
-            (InstCell(RET), _) => {
-                let (head, _) = self.stack.pop().unwrap();
-                let (new_stack, new_dump) = try!(match self.dump.pop()  {
-                    Some((ListCell(s), d))      => Ok((*s, d)),
-                    Some(it @ (AtomCell(_),_))  => Ok((list!(it.0), it.1)),
-                    _                           => Err(
-                        "[fatal][RET]: Expected non-empty stack")
-                });
-                let (new_env, newer_dump) = try!(match new_dump.pop() {
-                    Some((ListCell(e), d))  => Ok((*e, d)),
-                    _                       => Err(
-                        "[fatal][RET]: Expected new environment on dump stack")
-                });
-                let (newer_control, newest_dump) = try!(match newer_dump.pop() {
-                    Some((ListCell(c), d))      => Ok((*c, d)),
-                    Some(it @ (InstCell(_),_))  => Ok((list!(it.0), it.1)),
-                    _                           => Err(
-                        "[fatal][RET]: Expected new control stack on dump stack")
-                });
-                Ok((State {
-                    stack: new_stack.push(head),
-                    env: new_env,
+                    thing, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                None => Err(format!(
+                    "[fatal][RAP]: expected closure on stack, found nothing\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(RET), _) => match self.stack.pop() {
+                Some((head, _)) => {
+                    let (new_stack, new_env, new_control) =
+                        try!(pop_frame(dump, RET));
+                    Ok((State {
+                        stack: new_stack.push(head),
+                        env: new_env,
+                        control: new_control,
+                        dump: take_dump(dump)
+                    }, None))
+                },
+                None => Err(format!(
+                    "[fatal][RET]: expected return value on stack, found nothing\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(RETN), new_control) => match new_control.pop() {
+                Some((AtomCell(UInt(n)), _)) => {
+                    let mut values = Vec::new();
+                    let mut rest = self.stack;
+                    for _ in 0..n {
+                        match rest.pop() {
+                            Some((value, new_rest)) => {
+                                values.push(value);
+                                rest = new_rest;
+                            },
+                            None => return Err(format!(
+                                "[fatal][RETN]: expected {} values, found {}\n{}",
+                                n, values.len(),
+                                prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                        }
+                    }
+                    let (new_stack, new_env, newer_control) =
+                        try!(pop_frame(dump, RETN));
+                    Ok((State {
+                        stack: values.into_iter().rev()
+                            .fold(new_stack, |stack, value| stack.push(value))
+                            .push(AtomCell(UInt(n))),
+                        env: new_env,
+                        control: newer_control,
+                        dump: take_dump(dump)
+                    }, None))
+                },
+                any => Err(format!(
+                    "[fatal][RETN]: expected count, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(RECVN), new_control) => match (new_control.pop(), self.stack.pop()) {
+                (Some((AtomCell(UInt(n)), newer_control)),
+                 Some((AtomCell(UInt(count)), new_stack))) if n == count => Ok((State {
+                    stack: new_stack,
+                    env: self.env,
                     control: newer_control,
-                    dump: newest_dump
-                }, None))
+                    dump: take_dump(dump)
+                }, None)),
+                (Some((AtomCell(UInt(n)), _)), Some((AtomCell(UInt(count)), _))) => Err(format!(
+                    "[fatal][RECVN]: expected {} values, received {}\n{}",
+                    n, count, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                (Some((AtomCell(UInt(_)), _)), any) => Err(format!(
+                    "[fatal][RECVN]: expected value count, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                (any, _) => Err(format!(
+                    "[fatal][RECVN]: expected count, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
             (InstCell(DUM), new_control) => Ok((State {
                 stack: self.stack,
                 env: self.env.push(ListCell(list!())),
                 control: new_control,
-                dump: self.dump
+                dump: take_dump(dump)
             }, None)),
             (InstCell(SEL), new_control) => match new_control.pop() {
                 Some((ListCell(box true_case), newer_control)) => {
@@ -601,14 +821,14 @@ impl State {
                                     stack: new_stack,
                                     env: self.env,
                                     control: false_case,
-                                    dump: self.dump.push(ListCell(Box::new(newest_control)))
+                                    dump: take_dump(dump).push(ListCell(Box::new(newest_control)))
                                 }, None)),
                                 // True case
                                 Some((_, new_stack)) => Ok((State {
                                     stack: new_stack,
                                     env: self.env,
                                     control: true_case,
-                                    dump: self.dump.push(ListCell(Box::new(newest_control)))
+                                    dump: take_dump(dump).push(ListCell(Box::new(newest_control)))
                                 }, None)),
                                 None => Err(format!(
                                     "[fatal][SEL]: expected non-empty stack\n{}",
@@ -635,7 +855,7 @@ impl State {
                     stack: new_stack.push(car),
                     env: self.env,
                     control: new_control,
-                    dump: self.dump
+                    dump: take_dump(dump)
                 }, None)),
                 Some((ListCell(box Nil), _)) => Err(format!(
                     "[fatal][CAR]: expected non-empty list, found Nil\n{}",
@@ -652,17 +872,17 @@ impl State {
                     stack: new_stack.push(ListCell(cdr)),
                     env: self.env,
                     control: new_control,
-                    dump: self.dump
+                    dump: take_dump(dump)
                 }, None)),
-                Some((ListCell(box Nil), _)) => panic!(
+                Some((ListCell(box Nil), _)) => Err(format!(
                     "[fatal][CDR]: expected non-empty list, found Nil\n{}",
-                    prev.map_or(String::new(), |x| x.dump_state("fatal") )),
-                Some((thing, _))             => panic!(
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                Some((thing, _))             => Err(format!(
                     "[fatal][CDR]: expected non-empty list, found {:?}\n{}",
-                    thing, prev.map_or(String::new(), |x| x.dump_state("fatal") )),
-                None                        => panic!(
+                    thing, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                None                        => Err(format!(
                     "[fatal][CDR]: Expected non-empty list, found nothing\n{}",
-                    prev.map_or(String::new(), |x| x.dump_state("fatal") ))
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
             (InstCell(CONS), new_control) => match self.stack.pop() {
                 Some((thing, new_stack)) => {
@@ -671,7 +891,7 @@ impl State {
                             stack: newer_stack.push(ListCell(Box::new(Cons(thing, list)))),
                             env: self.env,
                             control: new_control,
-                            dump: self.dump
+                            dump: take_dump(dump)
                         }, None)),
                         Some((thing_else, _)) => Err(format!(
                             "[fatal][CONS]: Expected a list on the stack, found {:?}\n{}",
@@ -686,40 +906,38 @@ impl State {
                     "[fatal][CONS]: Expected an item on the stack, found nothing\n{}",
                     prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
-            (InstCell(NULL), new_control) => {
-                let (target, new_stack) = self.stack.pop().unwrap();
-                Ok((State {
+            (InstCell(NULL), new_control) => match self.stack.pop() {
+                Some((target, new_stack)) => Ok((State {
                     stack: new_stack.push(
                         match target {
                             ListCell(box Nil) => list_cell![AtomCell(SInt(1))],
                             _                 => list_cell![]
-                        }
-                        ),
+                        }),
                     env: self.env,
                     control: new_control,
-                    dump: self.dump
-                }, None))
+                    dump: take_dump(dump)
+                }, None)),
+                None => Err(format!(
+                    "[fatal][NULL]: expected non-empty stack\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
             (InstCell(WRITEC), new_control) => match self.stack.pop() {
-                Some((AtomCell(Char(ch)), new_stack)) => {/*
-                    if let Err(msg) = outp.write(&[ch as u8,1]) {
-                        panic!("[fatal][WRITEC]: writing failed: {:?}\n{}",
-                            msg,
-                            prev.map_or(String::new(), |x| x.dump_state("fatal") ))
-                    };*/
+                Some((AtomCell(Char(ch)), new_stack)) => {
+                    // the char is handed to the host as a whole Unicode
+                    // scalar value; see `IOEvent::write_to()`.
                     Ok((State {
                         stack: new_stack,
                         env: self.env,
                         control: new_control,
-                        dump: self.dump
+                        dump: take_dump(dump)
                     }, Some(IOEvent::Buf(ch))) )
                 },
-                Some((thing_else,_)) => panic!(
+                Some((thing_else,_)) => Err(format!(
                     "[fatal][WRITEC]: expected char, found {:?}\n{}",
-                    thing_else,prev.map_or(String::new(), |x| x.dump_state("fatal") )),
-                None => panic!(
+                    thing_else,prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                None => Err(format!(
                     "[fatal][WRITEC]: expected char, found nothing\n{}",
-                    prev.map_or(String::new(), |x| x.dump_state("fatal") ))
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
             },
             (InstCell(READC), new_control) => {
                 // todo: figure out how to make it work with the new thing
@@ -728,27 +946,891 @@ impl State {
                         stack: self.stack.push(AtomCell(Char(ch as char))),
                         env: self.env,
                         control: new_control,
-                        dump: self.dump
+                        dump: take_dump(dump)
                     }, None)),
-                    _       => panic!("No input, something went wrong (this is not supposed to happen")
+                    None    => Err(format!(
+                        "[fatal][READC]: no input available\n{}",
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
                 } /*,
                     .map_err(|msg| format!(
                         "[fatal][READC]: could not read, {:?}\n{}",
                         msg,prev.map_or(String::new(), |x| x.dump_state("fatal") )))*/
             },
+            (InstCell(inst @ INTP), new_control)   |
+            (InstCell(inst @ FLOATP), new_control) |
+            (InstCell(inst @ CHARP), new_control)  |
+            (InstCell(inst @ PROCP), new_control)  |
+            (InstCell(inst @ PAIRP), new_control)  |
+            (InstCell(inst @ NANP), new_control)   |
+            (InstCell(inst @ INFP), new_control)   => match self.stack.pop() {
+                Some((target, new_stack)) => {
+                    let result = match (inst, &target) {
+                        (INTP, &AtomCell(SInt(_)))    |
+                        (INTP, &AtomCell(UInt(_)))    |
+                        (FLOATP, &AtomCell(Float(_))) |
+                        (CHARP, &AtomCell(Char(_)))   |
+                        (PAIRP, &ListCell(box Cons(_,_))) => true,
+                        (PROCP, &NativeCell(_)) => true,
+                        (PROCP, _)  => is_closure(&target),
+                        (NANP, &AtomCell(Float(x))) => x.is_nan(),
+                        (INFP, &AtomCell(Float(x))) => x.is_infinite(),
+                        _           => false
+                    };
+                    Ok((State {
+                        stack: new_stack.push(
+                            match result {
+                                true    => list_cell![AtomCell(SInt(1))],
+                                false   => list_cell![]
+                            }),
+                        env: self.env,
+                        control: new_control,
+                        dump: take_dump(dump)
+                    }, None))
+                },
+                None => Err(format!(
+                    "[fatal][{:?}]: expected non-empty stack\n{}",
+                    inst, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(inst @ TOINT), new_control)   |
+            (InstCell(inst @ TOUINT), new_control)  |
+            (InstCell(inst @ TOFLOAT), new_control) |
+            (InstCell(inst @ TOCHAR), new_control)  => match self.stack.pop() {
+                Some((AtomCell(atom), new_stack)) => {
+                    let result = match inst {
+                        TOINT   => atom.to_sint(),
+                        TOUINT  => atom.to_uint(),
+                        TOFLOAT => atom.to_float(),
+                        _       => atom.to_char()
+                    };
+                    match result {
+                        Ok(converted) => Ok((State {
+                            stack: new_stack.push(AtomCell(converted)),
+                            env: self.env,
+                            control: new_control,
+                            dump: take_dump(dump)
+                        }, None)),
+                        Err(why) => Err(format!(
+                            "[fatal][{:?}]: {}\n{}",
+                            inst, why,
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    }
+                },
+                any => Err(format!(
+                    "[fatal][{:?}]: expected atom, found {:?}\n{}",
+                    inst, any,
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(inst @ SQRT), new_control)  |
+            (InstCell(inst @ EXP), new_control)   |
+            (InstCell(inst @ LOG), new_control)   |
+            (InstCell(inst @ SIN), new_control)   |
+            (InstCell(inst @ COS), new_control)   |
+            (InstCell(inst @ TAN), new_control)   |
+            (InstCell(inst @ ASIN), new_control)  |
+            (InstCell(inst @ ACOS), new_control)  |
+            (InstCell(inst @ ATAN), new_control)  |
+            (InstCell(inst @ FLOOR), new_control) |
+            (InstCell(inst @ CEIL), new_control)  |
+            (InstCell(inst @ ROUND), new_control) => match self.stack.pop() {
+                Some((AtomCell(atom), new_stack)) => {
+                    let x = atom.as_float();
+                    Ok((State {
+                        stack: new_stack.push(AtomCell(Float(
+                            match inst {
+                                SQRT    => x.sqrt(),
+                                EXP     => x.exp(),
+                                LOG     => x.ln(),
+                                SIN     => x.sin(),
+                                COS     => x.cos(),
+                                TAN     => x.tan(),
+                                ASIN    => x.asin(),
+                                ACOS    => x.acos(),
+                                ATAN    => x.atan(),
+                                FLOOR   => x.floor(),
+                                CEIL    => x.ceil(),
+                                _       => x.round()
+                            }))),
+                        env: self.env,
+                        control: new_control,
+                        dump: take_dump(dump)
+                    }, None))
+                },
+                any => Err(format!(
+                    "[fatal][{:?}]: expected number, found {:?}\n{}",
+                    inst, any,
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(inst @ POW), new_control)   |
+            (InstCell(inst @ ATAN2), new_control) => match self.stack.pop() {
+                Some((AtomCell(op1), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(op2), newer_stack)) => {
+                        let (a, b) = (op1.as_float(), op2.as_float());
+                        Ok((State {
+                            stack: newer_stack.push(AtomCell(Float(
+                                match inst {
+                                    POW => a.powf(b),
+                                    _   => a.atan2(b)
+                                }))),
+                            env: self.env,
+                            control: new_control,
+                            dump: take_dump(dump)
+                        }, None))
+                    },
+                    any => Err(format!(
+                        "[fatal][{:?}]: expected second operand, found {:?}\n{}",
+                        inst, any,
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][{:?}]: expected first operand, found {:?}\n{}",
+                    inst, any,
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(VMAKE), new_control) => match self.stack.pop() {
+                Some((AtomCell(n), new_stack)) => match (n.to_uint(), new_stack.pop()) {
+                    (Ok(UInt(len)), Some((fill, newer_stack))) => match make_vector(fill, len) {
+                        Ok(vec) => Ok((State {
+                            stack: newer_stack.push(VectorCell(Arc::new(vec))),
+                            env: self.env,
+                            control: new_control,
+                            dump: take_dump(dump)
+                        }, None)),
+                        Err(why) => Err(format!(
+                            "[fatal][VMAKE]: {}\n{}",
+                            why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
+                    (Err(why), _) => Err(format!(
+                        "[fatal][VMAKE]: invalid length: {}\n{}",
+                        why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                    (_, _) => Err(format!(
+                        "[fatal][VMAKE]: expected fill item, found nothing\n{}",
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][VMAKE]: expected length, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(VGET), new_control) => match self.stack.pop() {
+                Some((VectorCell(vec), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(idx), newer_stack)) => match checked_index(idx, vec.len(), "vector") {
+                        Ok(i) => Ok((State {
+                            stack: newer_stack.push(vec[i].clone()),
+                            env: self.env,
+                            control: new_control,
+                            dump: take_dump(dump)
+                        }, None)),
+                        Err(why) => Err(format!(
+                            "[fatal][VGET]: {}\n{}",
+                            why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
+                    any => Err(format!(
+                        "[fatal][VGET]: expected index, found {:?}\n{}",
+                        any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][VGET]: expected vector, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(VSET), new_control) => match self.stack.pop() {
+                Some((VectorCell(mut vec), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(idx), newer_stack)) => match (checked_index(idx, vec.len(), "vector"),
+                                                                 newer_stack.pop()) {
+                        (Ok(i), Some((item, newest_stack))) => {
+                            // copies the vector only if it is shared
+                            Arc::make_mut(&mut vec)[i] = item;
+                            Ok((State {
+                                stack: newest_stack.push(VectorCell(vec)),
+                                env: self.env,
+                                control: new_control,
+                                dump: take_dump(dump)
+                            }, None))
+                        },
+                        (Err(why), _) => Err(format!(
+                            "[fatal][VSET]: {}\n{}",
+                            why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                        (_, None) => Err(format!(
+                            "[fatal][VSET]: expected item, found nothing\n{}",
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
+                    any => Err(format!(
+                        "[fatal][VSET]: expected index, found {:?}\n{}",
+                        any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][VSET]: expected vector, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(VLEN), new_control) => match self.stack.pop() {
+                Some((VectorCell(vec), new_stack)) => Ok((State {
+                    stack: new_stack.push(AtomCell(UInt(vec.len() as u64))),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                any => Err(format!(
+                    "[fatal][VLEN]: expected vector, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(VTOL), new_control) => match self.stack.pop() {
+                Some((VectorCell(vec), new_stack)) => Ok((State {
+                    stack: new_stack.push(ListCell(Box::new(
+                        vec.iter().cloned().collect::<List<SVMCell>>()))),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                any => Err(format!(
+                    "[fatal][VTOL]: expected vector, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(LTOV), new_control) => match self.stack.pop() {
+                Some((ListCell(list), new_stack)) => Ok((State {
+                    stack: new_stack.push(VectorCell(Arc::new(
+                        list.iter().cloned().collect()))),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                any => Err(format!(
+                    "[fatal][LTOV]: expected list, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(BLEN), new_control) => match self.stack.pop() {
+                Some((BytesCell(bytes), new_stack)) => Ok((State {
+                    stack: new_stack.push(AtomCell(UInt(bytes.len() as u64))),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                any => Err(format!(
+                    "[fatal][BLEN]: expected byte buffer, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(BGET), new_control) => match self.stack.pop() {
+                Some((BytesCell(bytes), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(idx), newer_stack)) =>
+                        match checked_index(idx, bytes.len(), "byte buffer") {
+                            Ok(i) => Ok((State {
+                                stack: newer_stack.push(AtomCell(UInt(bytes[i] as u64))),
+                                env: self.env,
+                                control: new_control,
+                                dump: take_dump(dump)
+                            }, None)),
+                            Err(why) => Err(format!(
+                                "[fatal][BGET]: {}\n{}",
+                                why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                        },
+                    any => Err(format!(
+                        "[fatal][BGET]: expected index, found {:?}\n{}",
+                        any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][BGET]: expected byte buffer, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(BSLICE), new_control) => match self.stack.pop() {
+                Some((BytesCell(bytes), new_stack)) => match new_stack.pop() {
+                    Some((AtomCell(start), newer_stack)) => match newer_stack.pop() {
+                        Some((AtomCell(end), newest_stack)) =>
+                            match slice_bounds(start, end, bytes.len()) {
+                                Ok((s, e)) => Ok((State {
+                                    stack: newest_stack.push(BytesCell(bytes[s..e].to_vec())),
+                                    env: self.env,
+                                    control: new_control,
+                                    dump: take_dump(dump)
+                                }, None)),
+                                Err(why) => Err(format!(
+                                    "[fatal][BSLICE]: {}\n{}",
+                                    why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                            },
+                        any => Err(format!(
+                            "[fatal][BSLICE]: expected end index, found {:?}\n{}",
+                            any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
+                    any => Err(format!(
+                        "[fatal][BSLICE]: expected start index, found {:?}\n{}",
+                        any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][BSLICE]: expected byte buffer, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(BCAT), new_control) => match self.stack.pop() {
+                Some((BytesCell(mut first), new_stack)) => match new_stack.pop() {
+                    Some((BytesCell(second), newer_stack)) => {
+                        first.extend(second);
+                        Ok((State {
+                            stack: newer_stack.push(BytesCell(first)),
+                            env: self.env,
+                            control: new_control,
+                            dump: take_dump(dump)
+                        }, None))
+                    },
+                    any => Err(format!(
+                        "[fatal][BCAT]: expected byte buffer, found {:?}\n{}",
+                        any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][BCAT]: expected byte buffer, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(STOB), new_control) => match self.stack.pop() {
+                Some((ListCell(list), new_stack)) => {
+                    let mut string = String::new();
+                    for item in list.iter() {
+                        match *item {
+                            AtomCell(Char(ch)) => string.push(ch),
+                            ref any => return Err(format!(
+                                "[fatal][STOB]: expected char, found {:?}\n{}",
+                                any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                        }
+                    }
+                    Ok((State {
+                        stack: new_stack.push(BytesCell(string.into_bytes())),
+                        env: self.env,
+                        control: new_control,
+                        dump: take_dump(dump)
+                    }, None))
+                },
+                any => Err(format!(
+                    "[fatal][STOB]: expected string, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(BTOS), new_control) => match self.stack.pop() {
+                Some((BytesCell(bytes), new_stack)) => match String::from_utf8(bytes) {
+                    Ok(string) => Ok((State {
+                        stack: new_stack.push(ListCell(Box::new(
+                            string.chars().map(|ch| AtomCell(Char(ch)))
+                                  .collect::<List<SVMCell>>()))),
+                        env: self.env,
+                        control: new_control,
+                        dump: take_dump(dump)
+                    }, None)),
+                    Err(why) => Err(format!(
+                        "[fatal][BTOS]: invalid UTF-8, {}\n{}",
+                        why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][BTOS]: expected byte buffer, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(READB), new_control) => match input {
+                Some(byte) => Ok((State {
+                    stack: self.stack.push(AtomCell(UInt(byte as u64))),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                None => Err(format!(
+                    "[fatal][READB]: no input available\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(WRITEB), new_control) => match self.stack.pop() {
+                Some((BytesCell(bytes), new_stack)) => Ok((State {
+                    stack: new_stack,
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, Some(IOEvent::BufBytes(bytes)))),
+                any => Err(format!(
+                    "[fatal][WRITEB]: expected byte buffer, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(MNEW), new_control) => Ok((State {
+                stack: self.stack.push(MapCell(BTreeMap::new())),
+                env: self.env,
+                control: new_control,
+                dump: take_dump(dump)
+            }, None)),
+            (InstCell(MPUT), new_control) => match self.stack.pop() {
+                Some((MapCell(mut map), new_stack)) => match new_stack.pop() {
+                    Some((key, newer_stack)) => match (MapKey::from_cell(&key),
+                                                       newer_stack.pop()) {
+                        (Ok(key), Some((value, newest_stack))) => {
+                            map.insert(key, value);
+                            Ok((State {
+                                stack: newest_stack.push(MapCell(map)),
+                                env: self.env,
+                                control: new_control,
+                                dump: take_dump(dump)
+                            }, None))
+                        },
+                        (Err(why), _) => Err(format!(
+                            "[fatal][MPUT]: {}\n{}",
+                            why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                        (_, None) => Err(format!(
+                            "[fatal][MPUT]: expected value, found nothing\n{}",
+                            prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
+                    None => Err(format!(
+                        "[fatal][MPUT]: expected key, found nothing\n{}",
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][MPUT]: expected map, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(inst @ MGET), new_control) |
+            (InstCell(inst @ MHAS), new_control) |
+            (InstCell(inst @ MDEL), new_control) => match self.stack.pop() {
+                Some((MapCell(mut map), new_stack)) => match new_stack.pop() {
+                    Some((key, newer_stack)) => match MapKey::from_cell(&key) {
+                        Ok(key) => {
+                            let result = match inst {
+                                MGET => map.remove(&key).ok_or(
+                                    format!("key {:?} not found", key)),
+                                MHAS => Ok(match map.contains_key(&key) {
+                                    true    => list_cell![AtomCell(SInt(1))],
+                                    false   => list_cell![]
+                                }),
+                                _    => {
+                                    map.remove(&key);
+                                    Ok(MapCell(map))
+                                }
+                            };
+                            match result {
+                                Ok(cell) => Ok((State {
+                                    stack: newer_stack.push(cell),
+                                    env: self.env,
+                                    control: new_control,
+                                    dump: take_dump(dump)
+                                }, None)),
+                                Err(why) => Err(format!(
+                                    "[fatal][{:?}]: {}\n{}",
+                                    inst, why,
+                                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                            }
+                        },
+                        Err(why) => Err(format!(
+                            "[fatal][{:?}]: {}\n{}",
+                            inst, why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                    },
+                    None => Err(format!(
+                        "[fatal][{:?}]: expected key, found nothing\n{}",
+                        inst, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][{:?}]: expected map, found {:?}\n{}",
+                    inst, any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(MKEYS), new_control) => match self.stack.pop() {
+                Some((MapCell(map), new_stack)) => Ok((State {
+                    stack: new_stack.push(ListCell(Box::new(
+                        map.keys().map(MapKey::to_cell).collect::<List<SVMCell>>()))),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                any => Err(format!(
+                    "[fatal][MKEYS]: expected map, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(MLEN), new_control) => match self.stack.pop() {
+                Some((MapCell(map), new_stack)) => Ok((State {
+                    stack: new_stack.push(AtomCell(UInt(map.len() as u64))),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                any => Err(format!(
+                    "[fatal][MLEN]: expected map, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(TRY), new_control) => match new_control.pop() {
+                Some((ListCell(handler), newer_control)) => match newer_control.pop() {
+                    Some((ListCell(box body), newest_control)) => Ok((State {
+                        stack: self.stack.clone(),
+                        env: self.env.clone(),
+                        control: body,
+                        dump: take_dump(dump)
+                            .push(list_cell![
+                                ListCell(handler),
+                                ListCell(Box::new(self.stack)),
+                                ListCell(Box::new(self.env)),
+                                ListCell(Box::new(newest_control))
+                            ])
+                            .push(InstCell(TRY))
+                    }, None)),
+                    any => Err(format!(
+                        "[fatal][TRY]: expected body on control, found {:?}\n{}",
+                        any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][TRY]: expected handler on control, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(ENDTRY), _) => match take_dump(dump).pop() {
+                Some((InstCell(TRY), new_dump)) => match new_dump.pop() {
+                    Some((ListCell(box Cons(_, box Cons(_, box Cons(_,
+                          box Cons(ListCell(box continuation), box Nil))))), newer_dump)) =>
+                        Ok((State {
+                            stack: self.stack,
+                            env: self.env,
+                            control: continuation,
+                            dump: newer_dump
+                        }, None)),
+                    any => {
+                        let why = format!(
+                            "[fatal][ENDTRY]: expected handler frame on dump, found {:?}\n{}",
+                            any, prev.map_or(String::new(), |x| x.dump_state("fatal") ));
+                        *dump = any.map_or(Stack::empty(), |(frame, rest)| rest.push(frame))
+                                   .push(InstCell(TRY));
+                        Err(why)
+                    }
+                },
+                any => {
+                    let why = format!(
+                        "[fatal][ENDTRY]: expected handler on dump, found {:?}\n{}",
+                        any, prev.map_or(String::new(), |x| x.dump_state("fatal") ));
+                    *dump = any.map_or(Stack::empty(), |(top, rest)| rest.push(top));
+                    Err(why)
+                }
+            },
+            (InstCell(THROW), _) => match self.stack.pop() {
+                Some((condition, _)) => unwind(take_dump(dump), condition)
+                    .map(|state| (state, None))
+                    .map_err(|condition| format!(
+                        "[fatal][THROW]: uncaught condition {:?}\n{}",
+                        condition, prev.map_or(String::new(), |x| x.dump_state("fatal") ))),
+                None => Err(format!(
+                    "[fatal][THROW]: expected non-empty stack\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(BOX), new_control) => match self.stack.pop() {
+                Some((value, new_stack)) => Ok((State {
+                    stack: new_stack.push(BoxCell(SharedCell::new(value))),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                None => Err(format!(
+                    "[fatal][BOX]: expected non-empty stack\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(UNBOX), new_control) => match self.stack.pop() {
+                Some((BoxCell(shared), new_stack)) => Ok((State {
+                    stack: new_stack.push(shared.get()),
+                    env: self.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None)),
+                any => Err(format!(
+                    "[fatal][UNBOX]: expected box, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(SETBOX), new_control) => match self.stack.pop() {
+                Some((BoxCell(shared), new_stack)) => match new_stack.pop() {
+                    Some((value, newer_stack)) => {
+                        shared.set(value);
+                        Ok((State {
+                            stack: newer_stack,
+                            env: self.env,
+                            control: new_control,
+                            dump: take_dump(dump)
+                        }, None))
+                    },
+                    None => Err(format!(
+                        "[fatal][SETBOX]: expected value, found nothing\n{}",
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][SETBOX]: expected box, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(CHAN), new_control) => Ok((State {
+                stack: self.stack.push(ChannelCell(Channel::new())),
+                env: self.env,
+                control: new_control,
+                dump: take_dump(dump)
+            }, None)),
+            (InstCell(SEND), new_control) => match self.stack.pop() {
+                Some((ChannelCell(channel), new_stack)) => match new_stack.pop() {
+                    Some((value, newer_stack)) => {
+                        channel.send(value);
+                        Ok((State {
+                            stack: newer_stack,
+                            env: self.env,
+                            control: new_control,
+                            dump: take_dump(dump)
+                        }, None))
+                    },
+                    None => Err(format!(
+                        "[fatal][SEND]: expected value, found nothing\n{}",
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][SEND]: expected channel, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(RECV), new_control) => match self.stack.pop() {
+                Some((ChannelCell(channel), new_stack)) => match channel.recv() {
+                    Some(value) => Ok((State {
+                        stack: new_stack.push(value),
+                        env: self.env,
+                        control: new_control,
+                        dump: take_dump(dump)
+                    }, None)),
+                    None => Err(format!(
+                        "[fatal][RECV]: channel is empty\n{}",
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+                any => Err(format!(
+                    "[fatal][RECV]: expected channel, found {:?}\n{}",
+                    any, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(inst @ CALLN), _) |
+            (InstCell(inst @ LDG), _)   |
+            (InstCell(inst @ STG), _)   => Err(format!(
+                "[fatal][{:?}]: this instruction may only be evaluated by a Machine\n{}",
+                inst, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+            (InstCell(inst @ SPAWN), _) |
+            (InstCell(inst @ YIELD), _) |
+            (InstCell(inst @ WAIT), _)  => Err(format!(
+                "[fatal][{:?}]: this instruction may only be evaluated by a Scheduler\n{}",
+                inst, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
             (InstCell(STOP), _) => panic!(
                 "[fatal]: undefined behaviour\n[fatal]: evaluation of STOP word\n{}",
                 prev.map_or(String::new(), |x| x.dump_state("fatal") )
                 ),
-            (thing, _) => panic!(
+            (thing, _) => Err(format!(
                 "[fatal]: Tried to evaluate an unsupported cell type {:?}.\n{}",
                 thing,
-                prev.map_or(String::new(), |x| x.dump_state("fatal") ))
+                prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
         }
     }
 }
 
 
+/// Pops the frame pushed by `AP` or `RAP` from the dump, returning the
+/// saved stack, environment and control. The dump is left as it was if
+/// it does not begin with a frame.
+fn pop_frame(dump: &mut List<SVMCell>, inst: Inst)
+    -> Result<(List<SVMCell>, List<SVMCell>, List<SVMCell>), String> {
+    {
+        let mut frames = dump.iter();
+        match frames.next() {
+            Some(&ListCell(_)) | Some(&AtomCell(_)) => {},
+            _ => return Err(format!(
+                "[fatal][{:?}]: Expected non-empty stack", inst))
+        }
+        match frames.next() {
+            Some(&ListCell(_)) => {},
+            _ => return Err(format!(
+                "[fatal][{:?}]: Expected new environment on dump stack", inst))
+        }
+        match frames.next() {
+            Some(&ListCell(_)) | Some(&InstCell(_)) => {},
+            _ => return Err(format!(
+                "[fatal][{:?}]: Expected new control stack on dump stack", inst))
+        }
+    }
+    let (stack, rest) = take_dump(dump).pop().unwrap();
+    let (env, rest) = rest.pop().unwrap();
+    let (control, rest) = rest.pop().unwrap();
+    *dump = rest;
+    let stack = match stack {
+        ListCell(box s) => s,
+        atom            => list!(atom)
+    };
+    let env = match env {
+        ListCell(box e) => e,
+        _               => unreachable!()
+    };
+    let control = match control {
+        ListCell(box c) => c,
+        inst            => list!(inst)
+    };
+    Ok((stack, env, control))
+}
+
+/// Returns true if a cell is a closure (a pair `[f e]` of a function
+/// body and an environment, as constructed by `LDF`).
+fn is_closure(cell: &SVMCell) -> bool {
+    match *cell {
+        ListCell(box Cons(ListCell(_), box Cons(ListCell(_), box Nil))) => true,
+        ListCell(box Cons(ListCell(_), box Cons(ListCell(_),
+            box Cons(ref spec, box Nil)))) => parse_arity(spec).is_some(),
+        _ => false
+    }
+}
+
+/// Parses an arity given to `LDF`, a list of the number of required
+/// parameters, the number of optional parameters, and whether or not
+/// the closure takes rest arguments (`1` if it does, `0` if not).
+fn parse_arity(spec: &SVMCell) -> Option<(u64, u64, bool)> {
+    match *spec {
+        ListCell(box Cons(AtomCell(UInt(required)),
+                 box Cons(AtomCell(UInt(optional)),
+                 box Cons(AtomCell(UInt(rest)), box Nil))))
+            if rest <= 1 => Some((required, optional, rest == 1)),
+        _ => None
+    }
+}
+
+/// Binds the arguments passed to a closure according to its arity, if it
+/// has one, checking that it was passed an acceptable number of them.
+///
+/// Missing optional arguments are bound to `nil`, and any rest arguments
+/// are packed into a list, which is bound to the last parameter.
+fn bind_args(arity: &List<SVMCell>, args: SVMCell) -> Result<SVMCell, String> {
+    let (required, optional, rest) = match *arity {
+        Nil => return Ok(args),
+        Cons(ref spec, box Nil) => try!(parse_arity(spec).ok_or_else(||
+            format!("malformed arity {:?}", spec))),
+        ref thing => return Err(format!("malformed closure arity {:?}", thing))
+    };
+    let mut list = match args {
+        ListCell(box list) => list,
+        v => list!(v)
+    };
+    let mut items = Vec::new();
+    while let Some((item, rest)) = list.pop() {
+        items.push(item);
+        list = rest;
+    }
+    let count = items.len() as u64;
+    if count < required || (!rest && count > required + optional) {
+        return Err(format!(
+            "arity mismatch, expected {} arguments, found {}",
+            match (optional, rest) {
+                (_, true) => format!("at least {}", required),
+                (0, false) => format!("{}", required),
+                (_, false) => format!("{} to {}", required, required + optional)
+            }, count));
+    }
+    let params = (required + optional) as usize;
+    let rest_args = items.split_off(::std::cmp::min(items.len(), params));
+    while items.len() < params {
+        items.push(list_cell![]);
+    }
+    if rest {
+        items.push(ListCell(Box::new(rest_args.into_iter().collect())));
+    }
+    Ok(ListCell(Box::new(items.into_iter().collect())))
+}
+
+/// Evaluates a step with the dump taken out of `state`, and passes any
+/// error to the innermost exception handler on the dump the step leaves
+/// behind, if there is one.
+///
+/// The step is given the state without its dump, the dump, and `prev`,
+/// a copy of the whole state to include in errors, if the caller made one.
+/// Steps which succeed take the dump with `take_dump()`; steps which fail
+/// leave it, so that the dump need only be searched for a handler when a
+/// step fails.
+fn catch<F>(mut state: State, prev: Option<&State>, step: F) -> EvalResult
+    where F: FnOnce(State, &mut List<SVMCell>, Option<&State>) -> EvalResult
+{
+    let mut dump = take_dump(&mut state.dump);
+    match step(state, &mut dump, prev) {
+        Err(why) => unwind(dump, condition(&why))
+                        .map(|state| (state, None))
+                        .map_err(|_| why),
+        result   => result
+    }
+}
+
+/// Takes the dump from a step, leaving it empty.
+fn take_dump(dump: &mut List<SVMCell>) -> List<SVMCell> {
+    mem::replace(dump, Stack::empty())
+}
+
+/// Unwinds a dump to the innermost exception handler, returning the state
+/// in which that handler is entered with `condition` on top of the stack.
+///
+/// If there is no handler on the dump, `condition` is returned instead.
+fn unwind(dump: List<SVMCell>, condition: SVMCell) -> Result<State, SVMCell> {
+    let mut frames = dump;
+    loop {
+        frames = match frames.pop() {
+            Some((InstCell(TRY), rest)) => return match rest.pop() {
+                Some((ListCell(box Cons(ListCell(box handler),
+                      box Cons(ListCell(box stack),
+                      box Cons(ListCell(box env),
+                      box Cons(continuation @ ListCell(_), box Nil))))), rest)) =>
+                    Ok(State {
+                        stack: stack.push(condition),
+                        env: env,
+                        control: handler,
+                        dump: rest.push(continuation)
+                    }),
+                _ => Err(condition)
+            },
+            Some((_, rest)) => rest,
+            None            => return Err(condition)
+        }
+    }
+}
+
+/// Converts an error raised by the VM into a condition to be passed to
+/// an exception handler: a string containing the first line of the error.
+fn condition(why: &str) -> SVMCell {
+    ListCell(Box::new(
+        why.lines().next().unwrap_or("")
+           .chars().map(|ch| AtomCell(Char(ch)))
+           .collect()))
+}
+
+/// Returns the `n`th item of a list, counting from 1, as `LD` does.
+fn nth_from_one(list: &List<SVMCell>, n: u64) -> Option<&SVMCell> {
+    n.checked_sub(1).and_then(|i| list.iter().nth(i as usize))
+}
+
+/// Makes a vector of `len` copies of `fill`, checking that it holds no
+/// more than `MAX_VECTOR_LEN` cells and that it can be allocated.
+fn make_vector(fill: SVMCell, len: u64) -> Result<Vec<SVMCell>, String> {
+    if len > MAX_VECTOR_LEN as u64 {
+        return Err(format!(
+            "length {} is greater than the maximum vector length, {}",
+            len, MAX_VECTOR_LEN));
+    }
+    let size = copy_size(&fill);
+    if len.saturating_mul(size) > MAX_VECTOR_LEN as u64 {
+        return Err(format!(
+            "{} copies of an item of {} cells would hold more than the maximum of {} cells",
+            len, size, MAX_VECTOR_LEN));
+    }
+    let mut vec = Vec::new();
+    try!(vec.try_reserve_exact(len as usize)
+            .map_err(|_| format!("could not allocate a vector of length {}", len)));
+    vec.resize(len as usize, fill);
+    Ok(vec)
+}
+
+/// Counts the cells copied when a cell is cloned, as for `MAX_VECTOR_LEN`.
+fn copy_size(cell: &SVMCell) -> u64 {
+    1 + match *cell {
+        ListCell(ref list) => list.iter().map(copy_size).sum(),
+        MapCell(ref map) => map.values().map(copy_size).sum::<u64>() + map.len() as u64,
+        BytesCell(ref bytes) => bytes.len() as u64,
+        _ => 0
+    }
+}
+
+/// Checks that an atom is a valid index into a `kind` of length `len`.
+fn checked_index(idx: Atom, len: usize, kind: &str) -> Result<usize, String> {
+    match idx.to_uint() {
+        Ok(UInt(i)) if i < len as u64 => Ok(i as usize),
+        Ok(_)       => Err(format!(
+            "index {:?} out of range for {} of length {}", idx, kind, len)),
+        Err(why)    => Err(format!("invalid index: {}", why))
+    }
+}
+
+/// Checks that two atoms are valid bounds for a slice of a byte buffer
+/// of length `len`. The end bound is exclusive.
+fn slice_bounds(start: Atom, end: Atom, len: usize)
+    -> Result<(usize, usize), String> {
+    match (start.to_uint(), end.to_uint()) {
+        (Ok(UInt(s)), Ok(UInt(e))) if s <= e && e <= len as u64 =>
+            Ok((s as usize, e as usize)),
+        (Ok(_), Ok(_))  => Err(format!(
+            "slice {:?} to {:?} out of range for byte buffer of length {}",
+            start, end, len)),
+        (Err(why), _) | (_, Err(why)) => Err(format!("invalid index: {}", why))
+    }
+}
+
 /// Evaluates a program.
 ///
 /// Evaluates a program (control stack) and returns the final state.
@@ -0,0 +1,613 @@
+use super::{State,IOEvent,EvalResult,VmError};
+use super::convert::IntoSvm;
+use super::{catch,take_dump};
+use super::bytecode::Module;
+use super::slist::{List,Stack};
+use super::slist::List::{Cons,Nil};
+use super::cell::SVMCell;
+use super::cell::SVMCell::*;
+use super::cell::Atom::*;
+use super::cell::Inst::*;
+
+use std::collections::{HashMap,VecDeque};
+use std::sync::Arc;
+use std::{fmt,mem};
+
+#[cfg(test)]
+mod tests;
+
+/// Cooperative tasks, run by a `Scheduler`.
+#[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+pub mod scheduler;
+
+/// Running batches of programs in parallel, on a `Pool` of threads.
+#[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+pub mod pool;
+
+mod replay;
+
+#[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+pub use self::replay::LogEntry;
+use self::replay::Journal;
+
+/// A native function bound by the host.
+///
+/// Native functions are called with a slice of their arguments, and
+/// return either a result cell or an error, which may be caught by an
+/// exception handler in the calling program.
+#[cfg_attr(feature = "nightly", unstable(feature="native"))]
+pub type Native = Arc<Fn(&[SVMCell]) -> Result<SVMCell, VmError> + Send + Sync>;
+
+/// A table of values bound to names.
+///
+/// Each name is bound at a fixed index, so that programs may refer to a
+/// value either by its name or by its index.
+#[derive(Clone)]
+struct Table<T> {
+    entries: Vec<(String, T)>,
+    indices: HashMap<String, usize>
+}
+
+impl<T> Table<T> {
+    fn new() -> Table<T> {
+        Table { entries: Vec::new(), indices: HashMap::new() }
+    }
+
+    /// Binds a value to a name, replacing any value already bound to it,
+    /// and returns the index at which it is bound.
+    fn bind(&mut self, name: &str, value: T) -> usize {
+        match self.indices.get(name) {
+            Some(&index) => {
+                self.entries[index].1 = value;
+                return index
+            },
+            None => {}
+        }
+        self.entries.push((String::from(name), value));
+        self.indices.insert(String::from(name), self.entries.len() - 1);
+        self.entries.len() - 1
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).cloned()
+    }
+
+    fn get(&self, name: &str) -> Option<&T> {
+        self.indices.get(name).map(|&index| &self.entries[index].1)
+    }
+
+    /// Looks up the entry referred to by `reference`, which may be either
+    /// a name (extracted by `name_of`) or an unsigned integer index.
+    fn resolve<F>(&self, reference: &SVMCell, kind: &str, name_of: F)
+                  -> Result<&(String, T), String>
+        where F: Fn(&SVMCell) -> Option<&String>
+    {
+        match (name_of(reference), reference) {
+            (Some(name), _) => self.indices.get(name)
+                .map(|&index| &self.entries[index])
+                .ok_or(format!("no {} bound to {:?}", kind, name)),
+            (None, &AtomCell(UInt(index))) => self.entries.get(index as usize)
+                .ok_or(format!("no {} bound at {}", kind, index)),
+            (None, thing) => Err(format!("expected {}, found {:?}", kind, thing))
+        }
+    }
+
+    fn names(&self) -> Vec<&String> {
+        self.entries.iter().map(|&(ref name, _)| name).collect()
+    }
+}
+
+/// A Seax virtual machine.
+///
+/// A `Machine` owns the `State` it evaluates, along with the native
+/// functions and global definitions available to programs. Programs call
+/// native functions with `CALLN`, or by applying a native cell with `AP`,
+/// and access globals with `LDG` and `STG`; everything else is evaluated
+/// by `State::eval()`.
+#[derive(Clone)]
+#[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+pub struct Machine {
+    state: State,
+    natives: Table<Native>,
+    globals: Table<SVMCell>,
+    steps: u64,
+    journal: Journal,
+    history: VecDeque<(u64, State)>,
+    history_len: usize
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+impl Machine {
+
+    /// Creates a new machine with an empty state and no natives bound.
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    pub fn new() -> Machine {
+        Machine {
+            state: State::new(),
+            natives: Table::new(),
+            globals: Table::new(),
+            steps: 0,
+            journal: Journal::Off,
+            history: VecDeque::new(),
+            history_len: 0
+        }
+    }
+
+    /// Loads a program, replacing the machine's state with a new state
+    /// that will evaluate it, and resetting the step count. Bound natives
+    /// are kept.
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    pub fn load(&mut self, program: List<SVMCell>) {
+        self.state = State {
+            stack:      Stack::empty(),
+            env:        Stack::empty(),
+            control:    program,
+            dump:       Stack::empty()
+        };
+        self.steps = 0;
+        self.history.clear();
+    }
+
+    /// Loads a program decoded from a bytecode file, along with its
+    /// metadata.
+    ///
+    /// Every native the program imports must already be bound, or the
+    /// program is not loaded. The globals in the program's symbol table
+    /// are defined, replacing any existing definitions of the same name.
+    #[cfg_attr(feature = "nightly", unstable(feature="module"))]
+    pub fn load_module(&mut self, module: Module) -> Result<(), VmError> {
+        for name in &module.imports {
+            if self.natives.index(name).is_none() {
+                return Err(format!(
+                    "[fatal][load]: program imports native {}, which is not bound", name));
+            }
+        }
+        for (name, value) in module.symbols {
+            self.globals.bind(&name, value);
+        }
+        self.load(module.code);
+        Ok(())
+    }
+
+    /// Returns the number of steps taken since the program was loaded.
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Keeps the states before each of the last `len` steps, so that the
+    /// machine can step backwards through them with `step_back()`. A
+    /// `len` of zero, the default, keeps no history.
+    ///
+    /// Every state kept is a copy of the whole state, so a long history
+    /// of a large state can use a lot of memory. The contents of boxes and
+    /// channels are not copied, but shared with the machine's state; see
+    /// `step_back()`.
+    #[cfg_attr(feature = "nightly", unstable(feature="history"))]
+    pub fn keep_history(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+    }
+
+    /// Returns the states kept in the machine's history, from the oldest
+    /// to the most recent, along with the step at which each was about to
+    /// be evaluated.
+    #[cfg_attr(feature = "nightly", unstable(feature="history"))]
+    pub fn history(&self) -> Vec<(u64, &State)> {
+        self.history.iter()
+            .map(|&(step, ref state)| (step, state))
+            .collect()
+    }
+
+    /// Steps backwards, restoring the state before the last step taken,
+    /// if it is still in the history. Returns false if there is no
+    /// history left to step back through.
+    ///
+    /// After a step which failed, this steps back from the state in which
+    /// the instruction failed, so that the steps leading up to it can be
+    /// inspected. Anything recorded or replayed after the restored step is
+    /// rewound, so that stepping forwards again records or replays it
+    /// again.
+    ///
+    /// Only the registers are restored. The states in the history share
+    /// their boxes and channels with the machine's state, so a value
+    /// stored in a box by `SETBOX`, or sent on or received from a channel
+    /// by `SEND`, `RECV` or `RECVN`, after the restored step is not undone,
+    /// and the restored state sees the box or channel as it is now. Globals
+    /// are not restored either.
+    #[cfg_attr(feature = "nightly", unstable(feature="history"))]
+    pub fn step_back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some((step, state)) => {
+                self.state = state;
+                self.steps = step;
+                self.journal.rewind(step);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Starts recording a log of the machine's input, the results of
+    /// calls to natives, and its output, discarding any log being
+    /// recorded or replayed.
+    ///
+    /// Replaying the log on a machine with the same program loaded
+    /// should reproduce the run exactly, even if the input or natives
+    /// are not deterministic. Steps are counted from the last `load()`,
+    /// so recording should start before the program is loaded.
+    #[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+    pub fn record(&mut self) {
+        self.journal = Journal::Recording(Vec::new());
+    }
+
+    /// Stops recording, returning the log recorded, or `None` if the
+    /// machine was not recording.
+    #[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+    pub fn stop_recording(&mut self) -> Option<Vec<LogEntry>> {
+        self.journal.stop_recording()
+    }
+
+    /// Starts replaying a log recorded by `record()`.
+    ///
+    /// While replaying, each read takes its input from the log rather
+    /// than from the argument to `step()`, natives are not called but
+    /// return the results recorded, and all output is checked against
+    /// the output recorded. If the run differs from the recording in any
+    /// way, `step()` returns an error saying where it diverged.
+    #[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+    pub fn replay(&mut self, log: Vec<LogEntry>) {
+        self.journal = Journal::Replaying(log, 0);
+    }
+
+    /// Stops replaying, returning an error if the machine was not
+    /// replaying or if part of the log was never replayed.
+    #[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+    pub fn finish_replay(&mut self) -> Result<(), VmError> {
+        self.journal.finish_replay()
+    }
+
+    /// Returns the machine's current state.
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Binds a native function to a name, returning the index at which
+    /// it was bound.
+    ///
+    /// If a function is already bound to the name, it is replaced, and
+    /// the new function is bound at the same index.
+    #[cfg_attr(feature = "nightly", unstable(feature="native"))]
+    pub fn bind_native<F>(&mut self, name: &str, function: F) -> usize
+        where F: Fn(&[SVMCell]) -> Result<SVMCell, VmError> + Send + Sync + 'static
+    {
+        self.natives.bind(name, Arc::new(function))
+    }
+
+    /// Returns the index at which a native function is bound to a name,
+    /// or `None` if no function is bound to that name.
+    #[cfg_attr(feature = "nightly", unstable(feature="native"))]
+    pub fn native_index(&self, name: &str) -> Option<usize> {
+        self.natives.index(name)
+    }
+
+    /// Defines a global, returning the index at which it is bound.
+    ///
+    /// If the global is already defined, its value is replaced, and it
+    /// keeps the same index.
+    #[cfg_attr(feature = "nightly", unstable(feature="globals"))]
+    pub fn define_global<T>(&mut self, name: &str, value: T) -> usize
+        where T: IntoSvm
+    {
+        self.globals.bind(name, value.into_svm())
+    }
+
+    /// Returns the value of a global, or `None` if it is not defined.
+    #[cfg_attr(feature = "nightly", unstable(feature="globals"))]
+    pub fn global(&self, name: &str) -> Option<&SVMCell> {
+        self.globals.get(name)
+    }
+
+    /// Returns the index at which a global is bound, or `None` if it is
+    /// not defined.
+    #[cfg_attr(feature = "nightly", unstable(feature="globals"))]
+    pub fn global_index(&self, name: &str) -> Option<usize> {
+        self.globals.index(name)
+    }
+
+    /// Returns the names and values of all defined globals, in the order
+    /// of their indices.
+    #[cfg_attr(feature = "nightly", unstable(feature="globals"))]
+    pub fn globals(&self) -> Vec<(&str, &SVMCell)> {
+        self.globals.entries.iter()
+            .map(|&(ref name, ref value)| (&name[..], value))
+            .collect()
+    }
+
+    /// Returns true if the machine has finished evaluating its program;
+    /// that is, if the control stack is empty or the next instruction
+    /// is `STOP`.
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    pub fn is_halted(&self) -> bool {
+        is_halted(&self.state)
+    }
+
+    /// Evaluates the next instruction.
+    ///
+    /// This takes the same arguments as `State::eval()`, and returns the
+    /// IO event produced by the instruction, if any. If an error occurs
+    /// which is not caught by an exception handler, the step is not
+    /// counted or kept in the history. If the machine keeps a history, or
+    /// `debug` is set, it is also left in the state before the instruction
+    /// which failed, so that it can be inspected; otherwise, so that the
+    /// state need not be copied on every step, its state is lost.
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    pub fn step(&mut self, input: Option<u8>, debug: bool)
+                -> Result<Option<IOEvent>, VmError> {
+        let step = self.steps;
+        let saved = if self.history_len > 0 || debug {
+            Some(self.state.clone())
+        } else {
+            None
+        };
+        self.steps += 1;
+        match self.eval_step(step, input, saved.as_ref()) {
+            Ok(event) => {
+                match saved {
+                    Some(saved) if self.history_len > 0 => {
+                        if self.history.len() == self.history_len {
+                            self.history.pop_front();
+                        }
+                        self.history.push_back((step, saved));
+                    },
+                    _ => {}
+                }
+                Ok(event)
+            },
+            Err(why) => {
+                if let Some(saved) = saved {
+                    self.state = saved;
+                }
+                self.steps = step;
+                self.journal.rewind(step);
+                Err(why)
+            }
+        }
+    }
+
+    /// Evaluates the next instruction as step number `step`, replacing
+    /// the machine's state with the result. `prev` is a copy of the state
+    /// to include in errors, if there is one. The state is left empty if
+    /// an error occurs.
+    fn eval_step(&mut self, step: u64, input: Option<u8>, prev: Option<&State>)
+                 -> Result<Option<IOEvent>, VmError> {
+        let input = match self.state.control.peek() {
+            Some(&InstCell(READC)) | Some(&InstCell(READB)) =>
+                try!(self.journal.input(step, input)),
+            _ => input
+        };
+        let state = mem::replace(&mut self.state, State::new());
+        let event = try!(self.eval_state(state, input, prev, Some(step))
+            .map(|(state, event)| {
+                self.state = state;
+                event
+            }));
+        try!(self.journal.output(step, &event));
+        Ok(event)
+    }
+
+    /// Evaluates the next instruction of `state`, which need not be the
+    /// machine's own state, with the machine's natives and globals.
+    ///
+    /// Calls to natives are recorded or replayed as part of step number
+    /// `step`, if it is given; otherwise, nothing is recorded or replayed.
+    fn eval_state(&mut self, state: State, input: Option<u8>, prev: Option<&State>,
+                  step: Option<u64>) -> EvalResult {
+        let is_machine_inst = match (state.control.peek(), state.stack.peek()) {
+            (Some(&InstCell(CALLN)), _)                   |
+            (Some(&InstCell(AP)), Some(&NativeCell(_)))   |
+            (Some(&InstCell(LDG)), _)                     |
+            (Some(&InstCell(STG)), _)                     => true,
+            _                                             => false
+        };
+        if is_machine_inst {
+            catch(state, prev, |state, dump, prev| self.eval_machine_inst(state, dump, prev, step))
+        } else {
+            catch(state, prev, |state, dump, prev| state.step(dump, prev, input))
+        }
+    }
+
+    /// Evaluates the loaded program until the machine halts, returning
+    /// the final stack.
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    pub fn run(&mut self, debug: bool) -> Result<List<SVMCell>, VmError> {
+        while !self.is_halted() {
+            try!(self.step(None, debug));
+        }
+        Ok(self.state.stack.clone())
+    }
+
+    /// Applies a function to a list of arguments, returning the result.
+    ///
+    /// The function may be a closure, such as one returned by a program,
+    /// or a native cell. It is applied as if by `AP`, and evaluated until
+    /// the matching `RET`, on a state separate from the machine's own,
+    /// which is left as it was. The arguments may be anything that can be
+    /// converted to a cell; as with `AP`, an argument which is not a list
+    /// is treated as a list of one argument.
+    ///
+    /// The steps taken by the function are not counted in `steps()`, kept
+    /// in the history, or recorded or replayed, so that the host may call
+    /// functions between steps without changing how the program runs.
+    ///
+    /// This makes it possible to use SVM functions as callbacks from the
+    /// host, with the result read back using `FromSvm`.
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    pub fn apply<A>(&mut self, function: SVMCell, args: A) -> Result<SVMCell, VmError>
+        where A: IntoSvm
+    {
+        let mut state = State {
+            stack:      list!(function, args.into_svm()),
+            env:        Stack::empty(),
+            control:    list!(InstCell(AP)),
+            dump:       Stack::empty()
+        };
+        while !is_halted(&state) {
+            state = try!(self.eval_state(state, None, None, None)).0;
+        }
+        state.stack.pop()
+            .map(|(result, _)| result)
+            .ok_or(String::from("[fatal][apply]: expected result on stack, found nothing"))
+    }
+
+    /// Evaluates an instruction which needs access to the machine, rather
+    /// than just its state.
+    fn eval_machine_inst(&mut self, state: State, dump: &mut List<SVMCell>,
+                         prev: Option<&State>, step: Option<u64>) -> EvalResult {
+        let (inst, new_control) = state.control.pop().unwrap();
+        let state = State { control: new_control, ..state };
+        match inst {
+            InstCell(LDG) => self.load_global(state, dump, prev),
+            InstCell(STG) => self.store_global(state, dump, prev),
+            inst          => self.call_native(inst, state, dump, prev, step)
+        }
+    }
+
+    /// Evaluates a `CALLN` instruction, or an `AP` instruction applied to
+    /// a native cell.
+    fn call_native(&mut self, inst: SVMCell, state: State, dump: &mut List<SVMCell>,
+                   prev: Option<&State>, step: Option<u64>) -> EvalResult {
+        let (reference, newer_control, new_stack) = match inst {
+            InstCell(CALLN) => match state.control.pop() {
+                Some((reference, newer_control)) =>
+                    (reference, newer_control, state.stack),
+                None => return Err(format!(
+                    "[fatal][CALLN]: expected native function on control, found nothing\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            _ => {
+                let (reference, new_stack) = state.stack.pop().unwrap();
+                (reference, state.control, new_stack)
+            }
+        };
+        let &(ref name, ref function) = try!(self.natives.resolve(
+            &reference, "native function", |cell| match *cell {
+                NativeCell(ref name) => Some(name),
+                _                    => None
+            }).map_err(|why|
+            format!("[fatal][{:?}]: {}\n{}",
+                inst, why, prev.as_ref().map_or(String::new(), |x| x.dump_state("fatal") ))) );
+        match new_stack.pop() {
+            Some((args, newer_stack)) => {
+                let args = match args {
+                    ListCell(list) => list.iter().cloned().collect::<Vec<SVMCell>>(),
+                    v              => vec![v]
+                };
+                let result = match step {
+                    Some(step) => try!(self.journal.native(step, || function(&args))),
+                    None       => function(&args)
+                };
+                match result {
+                    Ok(result) => Ok((State {
+                        stack: newer_stack.push(result),
+                        env: state.env,
+                        control: newer_control,
+                        dump: take_dump(dump)
+                    }, None)),
+                    Err(why) => Err(format!(
+                        "[fatal][{:?}]: {}: {}\n{}",
+                        inst, name, why, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                }
+            },
+            None => Err(format!(
+                "[fatal][{:?}]: expected arguments, found nothing\n{}",
+                inst, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+        }
+    }
+
+    /// Evaluates a `LDG` instruction.
+    fn load_global(&self, state: State, dump: &mut List<SVMCell>, prev: Option<&State>)
+                   -> EvalResult {
+        match state.control.pop() {
+            Some((reference, new_control)) => {
+                let &(_, ref value) = try!(self.globals.resolve(
+                    &reference, "global", global_name).map_err(|why|
+                    format!("[fatal][LDG]: {}\n{}",
+                        why, prev.map_or(String::new(), |x| x.dump_state("fatal") ))) );
+                Ok((State {
+                    stack: state.stack.push(value.clone()),
+                    env: state.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None))
+            },
+            None => Err(format!(
+                "[fatal][LDG]: expected global on control, found nothing\n{}",
+                prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+        }
+    }
+
+    /// Evaluates a `STG` instruction.
+    ///
+    /// Storing to a global by name defines it if it is not already
+    /// defined; storing to a global by index requires that it is.
+    fn store_global(&mut self, state: State, dump: &mut List<SVMCell>, prev: Option<&State>)
+                    -> EvalResult {
+        match (state.control.pop(), state.stack.pop()) {
+            (Some((reference, new_control)), Some((value, new_stack))) => {
+                let name = match global_name(&reference) {
+                    Some(name) => name.clone(),
+                    None => try!(self.globals.resolve(&reference, "global", global_name)
+                        .map(|&(ref name, _)| name.clone())
+                        .map_err(|why| format!("[fatal][STG]: {}\n{}",
+                            why, prev.map_or(String::new(), |x| x.dump_state("fatal") ))) )
+                };
+                self.globals.bind(&name, value);
+                Ok((State {
+                    stack: new_stack,
+                    env: state.env,
+                    control: new_control,
+                    dump: take_dump(dump)
+                }, None))
+            },
+            (None, _) => Err(format!(
+                "[fatal][STG]: expected global on control, found nothing\n{}",
+                prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+            (_, None) => Err(format!(
+                "[fatal][STG]: expected value on stack, found nothing\n{}",
+                prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+        }
+    }
+}
+
+/// Returns true if a state has finished evaluating its program; that is,
+/// if its control stack is empty or its next instruction is `STOP`.
+fn is_halted(state: &State) -> bool {
+    match state.control.peek() {
+        None | Some(&InstCell(STOP)) => true,
+        _                            => false
+    }
+}
+
+/// Returns the name of the global referred to by a global cell.
+fn global_name(cell: &SVMCell) -> Option<&String> {
+    match *cell {
+        GlobalCell(ref name) => Some(name),
+        _                    => None
+    }
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+impl fmt::Debug for Machine {
+    #[cfg_attr(feature = "nightly", unstable(feature="machine"))]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Machine {{ state: {:?}, natives: {:?}, globals: {:?} }}",
+            self.state,
+            self.natives.names(),
+            self.globals()
+        )
+    }
+}
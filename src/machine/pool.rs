@@ -0,0 +1,445 @@
+use super::Machine;
+use super::super::{State,VmError};
+use super::super::slist::List;
+use super::super::slist::List::Nil;
+use super::super::cell::{SVMCell,SharedCell,Channel};
+use super::super::cell::SVMCell::*;
+
+use std::panic::{self,AssertUnwindSafe};
+use std::sync::{Arc,Mutex,mpsc};
+use std::thread;
+
+/// How often, in steps, a job's memory use is checked.
+const MEMORY_CHECK_INTERVAL: u64 = 64;
+
+/// Limits on the resources a single job may use.
+#[derive(Clone,Copy,Debug,PartialEq,Default)]
+#[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+pub struct Limits {
+    /// The maximum number of steps a job may take, or `None` for no limit.
+    pub fuel: Option<u64>,
+    /// The maximum number of cells a job's state may hold, or `None` for
+    /// no limit.
+    ///
+    /// Cells are counted every few steps rather than after every step,
+    /// so a job may briefly exceed this limit before it is stopped. The
+    /// cells held by a vector, box or channel are counted once, however
+    /// many copies of it the state holds.
+    pub memory: Option<usize>
+}
+
+/// The results of a batch of jobs run by a `Pool`.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+pub struct Report {
+    /// The final stack of each job, or the error that stopped it, in the
+    /// order in which the jobs were given.
+    pub results: Vec<Result<List<SVMCell>, VmError>>
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+impl Report {
+    /// Returns true if every job finished without an error.
+    #[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|result| result.is_ok())
+    }
+
+    /// Returns the number of jobs which finished without an error.
+    #[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|result| result.is_ok()).count()
+    }
+
+    /// Returns the index and error of each job which failed.
+    #[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+    pub fn errors(&self) -> Vec<(usize, &VmError)> {
+        self.results.iter().enumerate()
+            .filter_map(|(i, result)| result.as_ref().err().map(|why| (i, why)))
+            .collect()
+    }
+}
+
+/// Runs batches of independent programs in parallel.
+///
+/// Each program is run to completion on its own copy of the pool's
+/// machine, so every job sees the natives and globals defined on it, but
+/// changes one job makes to its globals, or to the boxes and channels they
+/// hold, are not seen by any other job.
+///
+/// Everything else is shared between jobs: the natives, along with
+/// anything they capture, and any boxes or channels in the programs
+/// themselves, which a host may use to communicate with its jobs.
+#[derive(Clone,Debug)]
+#[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+pub struct Pool {
+    machine: Machine,
+    threads: usize,
+    limits: Limits
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+impl Pool {
+
+    /// Creates a pool which runs jobs on copies of `machine`, using up to
+    /// `threads` threads, within the given limits.
+    #[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+    pub fn new(machine: Machine, threads: usize, limits: Limits) -> Pool {
+        Pool { machine: machine, threads: threads, limits: limits }
+    }
+
+    /// Runs each program, returning a report of their results.
+    ///
+    /// An error in one job, including a panic or running out of fuel or
+    /// memory, stops only that job. A job which panics may leave something
+    /// it shares with other jobs, such as a box in its program, part of the
+    /// way through being changed.
+    #[cfg_attr(feature = "nightly", unstable(feature="pool"))]
+    pub fn run(&self, programs: Vec<List<SVMCell>>) -> Report {
+        let len = programs.len();
+        let jobs = Arc::new(Mutex::new(programs.into_iter().enumerate()));
+        let (sender, receiver) = mpsc::channel();
+        let workers = (0..::std::cmp::max(1, ::std::cmp::min(self.threads, len)))
+            .map(|_| {
+                let jobs = jobs.clone();
+                let sender = sender.clone();
+                let machine = self.machine.clone();
+                let limits = self.limits;
+                thread::spawn(move || loop {
+                    // release the lock before running the job
+                    let job = jobs.lock().unwrap().next();
+                    match job {
+                        Some((i, program)) => {
+                            let result = run_job(job_machine(&machine), program, limits);
+                            sender.send((i, result)).unwrap();
+                        },
+                        None => break
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(sender);
+        let mut results = (0..len).map(|_| None).collect::<Vec<_>>();
+        for (i, result) in receiver {
+            results[i] = Some(result);
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        Report {
+            results: results.into_iter()
+                .map(|result| result.unwrap())
+                .collect()
+        }
+    }
+}
+
+/// Copies a machine for a job, giving it its own copies of the boxes and
+/// channels held by the machine's globals.
+fn job_machine(machine: &Machine) -> Machine {
+    let mut machine = machine.clone();
+    let mut copier = Copier::default();
+    for entry in &mut machine.globals.entries {
+        entry.1 = copier.copy(&entry.1);
+    }
+    machine
+}
+
+/// Copies cells, making new boxes and channels to hold copies of what
+/// the originals hold. A box or channel which is held more than once, or
+/// which holds itself, is copied once, and the copies are shared in the
+/// same way.
+#[derive(Default)]
+struct Copier {
+    boxes: Vec<(SharedCell, SharedCell)>,
+    channels: Vec<(Channel, Channel)>
+}
+
+impl Copier {
+    fn copy(&mut self, cell: &SVMCell) -> SVMCell {
+        match *cell {
+            ListCell(ref list) => ListCell(Box::new(list.iter().map(|it| self.copy(it)).collect())),
+            VectorCell(ref cells) => VectorCell(Arc::new(cells.iter().map(|it| self.copy(it)).collect())),
+            MapCell(ref map) => MapCell(map.iter()
+                .map(|(key, value)| (key.clone(), self.copy(value)))
+                .collect()),
+            BoxCell(ref shared) => {
+                if let Some(&(_, ref copy)) = self.boxes.iter().find(|it| it.0.ptr_eq(shared)) {
+                    return BoxCell(copy.clone());
+                }
+                // the copy is made before its contents, which may hold it
+                let copy = SharedCell::new(list_cell![]);
+                self.boxes.push((shared.clone(), copy.clone()));
+                copy.set(self.copy(&shared.get()));
+                BoxCell(copy)
+            },
+            ChannelCell(ref channel) => {
+                if let Some(&(_, ref copy)) = self.channels.iter().find(|it| it.0.ptr_eq(channel)) {
+                    return ChannelCell(copy.clone());
+                }
+                let copy = Channel::new();
+                self.channels.push((channel.clone(), copy.clone()));
+                for value in channel.pending() {
+                    copy.send(self.copy(&value));
+                }
+                ChannelCell(copy)
+            },
+            ref cell => cell.clone()
+        }
+    }
+}
+
+/// Runs a single program within the given limits.
+fn run_job(mut machine: Machine, program: List<SVMCell>, limits: Limits)
+           -> Result<List<SVMCell>, VmError> {
+    machine.load(program);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut steps = 0;
+        while !machine.is_halted() {
+            if limits.fuel.map_or(false, |fuel| steps >= fuel) {
+                return Err(format!(
+                    "[fatal][Pool]: out of fuel after {} steps", steps));
+            }
+            try!(machine.step(None, false));
+            steps += 1;
+            match limits.memory {
+                Some(memory) if steps % MEMORY_CHECK_INTERVAL == 0 => {
+                    let used = state_size(machine.state());
+                    if used > memory {
+                        return Err(format!(
+                            "[fatal][Pool]: out of memory, {} cells used of {}",
+                            used, memory));
+                    }
+                },
+                _ => {}
+            }
+        }
+        Ok(machine.state().stack.clone())
+    }));
+    match result {
+        Ok(result) => result,
+        Err(why) => Err(format!(
+            "[fatal][Pool]: job panicked: {}",
+            why.downcast_ref::<String>().map(|why| &why[..])
+                .or_else(|| why.downcast_ref::<&str>().map(|why| *why))
+                .unwrap_or("unknown error")
+                .lines().next().unwrap_or("")))
+    }
+}
+
+/// Counts the cells held by a state.
+fn state_size(state: &State) -> usize {
+    let mut counted = Counted::default();
+    [&state.stack, &state.env, &state.control, &state.dump].iter()
+        .map(|list| list_size(list, &mut counted))
+        .sum()
+}
+
+/// The vectors, boxes and channels whose contents have already been
+/// counted, so that each is counted once, even if it holds itself.
+#[derive(Default)]
+struct Counted {
+    vectors: Vec<Arc<Vec<SVMCell>>>,
+    boxes: Vec<SharedCell>,
+    channels: Vec<Channel>
+}
+
+fn list_size(list: &List<SVMCell>, counted: &mut Counted) -> usize {
+    list.iter().map(|cell| cell_size(cell, counted)).sum()
+}
+
+fn cell_size(cell: &SVMCell, counted: &mut Counted) -> usize {
+    1 + match *cell {
+        ListCell(ref list) => list_size(list, counted),
+        VectorCell(ref cells) if !counted.vectors.iter().any(|it| Arc::ptr_eq(it, cells)) => {
+            counted.vectors.push(cells.clone());
+            cells.iter().map(|cell| cell_size(cell, counted)).sum()
+        },
+        BytesCell(ref bytes) => bytes.len(),
+        MapCell(ref map) => map.values().map(|cell| cell_size(cell, counted)).sum::<usize>()
+            + map.len(),
+        BoxCell(ref shared) if !counted.boxes.iter().any(|it| it.ptr_eq(shared)) => {
+            counted.boxes.push(shared.clone());
+            cell_size(&shared.get(), counted)
+        },
+        ChannelCell(ref channel) if !counted.channels.iter().any(|it| it.ptr_eq(channel)) => {
+            counted.channels.push(channel.clone());
+            channel.pending().iter().map(|cell| cell_size(cell, counted)).sum()
+        },
+        _ => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pool,Limits,Copier,state_size};
+    use super::super::Machine;
+    use ::{State,List,VmError};
+    use ::slist::Stack;
+    use ::slist::List::{Cons,Nil};
+    use ::cell::{SVMCell,SharedCell};
+    use ::cell::Atom::*;
+    use ::cell::SVMCell::*;
+    use ::Inst::*;
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    #[test]
+    fn test_send() {
+        assert_send::<SVMCell>();
+        assert_send::<List<SVMCell>>();
+        assert_send::<State>();
+        assert_send::<Machine>();
+        assert_sync::<SVMCell>();
+        assert_sync::<Machine>();
+    }
+
+    /// Builds a program which adds `a` and `b`.
+    fn add(a: i64, b: i64) -> List<SVMCell> {
+        list!(
+            InstCell(LDC), AtomCell(SInt(a)),
+            InstCell(LDC), AtomCell(SInt(b)),
+            InstCell(ADD)
+        )
+    }
+
+    /// Builds a program which loops forever, consing onto a list.
+    fn forever() -> List<SVMCell> {
+        list!(
+            InstCell(NIL),
+            InstCell(NIL), InstCell(CONS),
+            InstCell(LDF),
+            list_cell![
+                InstCell(NIL),
+                InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+                InstCell(LDC), AtomCell(SInt(1)),
+                InstCell(CONS),
+                InstCell(CONS),
+                InstCell(LDG), GlobalCell(String::from("loop")),
+                InstCell(AP),
+                InstCell(RET)
+            ],
+            InstCell(STG), GlobalCell(String::from("loop")),
+            InstCell(LDG), GlobalCell(String::from("loop")),
+            InstCell(AP)
+        )
+    }
+
+    #[test]
+    fn test_run_in_order() {
+        let pool = Pool::new(Machine::new(), 4, Limits::default());
+        let report = pool.run((0..20).map(|i| add(i, i)).collect());
+        assert!(report.is_ok());
+        assert_eq!(report.passed(), 20);
+        for (i, result) in report.results.into_iter().enumerate() {
+            assert_eq!(result.unwrap().peek(), Some(&AtomCell(SInt(2 * i as i64))));
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let pool = Pool::new(Machine::new(), 2, Limits::default());
+        let report = pool.run(vec![
+            add(1, 2),
+            list!(InstCell(CAR)),
+            add(3, 4),
+            list!(InstCell(STOP), InstCell(NIL))
+        ]);
+        assert!(!report.is_ok());
+        assert_eq!(report.passed(), 3);
+        let errors = report.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+    }
+
+    #[test]
+    fn test_panic() {
+        let mut machine = Machine::new();
+        machine.bind_native("boom", |_: &[SVMCell]| -> Result<SVMCell, VmError> {
+            panic!("boom")
+        });
+        let pool = Pool::new(machine, 1, Limits::default());
+        let report = pool.run(vec![
+            list!(InstCell(NIL), InstCell(CALLN), NativeCell(String::from("boom"))),
+            add(1, 1)
+        ]);
+        assert!(report.results[0].as_ref().unwrap_err().starts_with("[fatal][Pool]: job panicked"));
+        assert_eq!(report.results[1].as_ref().map(|s| s.peek().cloned()), Ok(Some(AtomCell(SInt(2)))));
+    }
+
+    #[test]
+    fn test_fuel() {
+        let pool = Pool::new(Machine::new(), 2, Limits { fuel: Some(1000), memory: None });
+        let report = pool.run(vec![forever(), add(1, 1)]);
+        assert_eq!(report.results[0], Err(String::from("[fatal][Pool]: out of fuel after 1000 steps")));
+        assert!(report.results[1].is_ok());
+    }
+
+    #[test]
+    fn test_globals_copied() {
+        let mut machine = Machine::new();
+        let count = SharedCell::new(AtomCell(SInt(0)));
+        machine.define_global("count", BoxCell(count.clone()));
+        let pool = Pool::new(machine, 2, Limits::default());
+        // each job sets the box to one more than it held
+        let increment = list!(
+            InstCell(LDC), AtomCell(SInt(1)),
+            InstCell(LDG), GlobalCell(String::from("count")), InstCell(UNBOX),
+            InstCell(ADD),
+            InstCell(LDG), GlobalCell(String::from("count")), InstCell(SETBOX),
+            InstCell(LDG), GlobalCell(String::from("count")), InstCell(UNBOX)
+        );
+        let report = pool.run((0..4).map(|_| increment.clone()).collect());
+        for result in report.results {
+            assert_eq!(result.unwrap().peek(), Some(&AtomCell(SInt(1))));
+        }
+        assert_eq!(count.get(), AtomCell(SInt(0)));
+    }
+
+    #[test]
+    fn test_copy_cycle() {
+        let shared = SharedCell::new(list_cell![]);
+        shared.set(list_cell![ BoxCell(shared.clone()), BoxCell(shared.clone()) ]);
+        let copy = match Copier::default().copy(&BoxCell(shared.clone())) {
+            BoxCell(copy) => copy,
+            other => panic!("expected a box, found {:?}", other)
+        };
+        assert!(!copy.ptr_eq(&shared));
+        match copy.get() {
+            ListCell(box Cons(BoxCell(ref a), box Cons(BoxCell(ref b), _))) => {
+                assert!(a.ptr_eq(&copy));
+                assert!(b.ptr_eq(&copy));
+            },
+            other => panic!("expected a list of boxes, found {:?}", other)
+        }
+        // break the cycles so the boxes can be dropped
+        shared.set(list_cell![]);
+        copy.set(list_cell![]);
+    }
+
+    #[test]
+    fn test_box_size() {
+        let shared = SharedCell::new(list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ]);
+        let state = State {
+            stack: list!(BoxCell(shared.clone()), BoxCell(shared.clone())),
+            env: Stack::empty(),
+            control: Stack::empty(),
+            dump: Stack::empty()
+        };
+        // two copies of the box, and its list of two atoms, counted once
+        assert_eq!(state_size(&state), 5);
+        shared.set(BoxCell(shared.clone()));
+        assert_eq!(state_size(&state), 3);
+        shared.set(list_cell![]);
+    }
+
+    #[test]
+    fn test_memory() {
+        let pool = Pool::new(Machine::new(), 2, Limits { fuel: None, memory: Some(500) });
+        let report = pool.run(vec![forever(), add(1, 1)]);
+        assert!(report.results[0].as_ref().unwrap_err()
+            .starts_with("[fatal][Pool]: out of memory"));
+        assert!(report.results[1].is_ok());
+    }
+}
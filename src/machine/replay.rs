@@ -0,0 +1,161 @@
+use super::super::{IOEvent,VmError};
+use super::super::cell::SVMCell;
+
+use std::mem;
+
+/// An entry in a log recorded by a `Machine`, for replaying a run of a
+/// program.
+///
+/// Each entry records something the program received from, or sent to,
+/// the host, along with the step at which it happened.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+pub enum LogEntry {
+    /// A byte of input read by `READC` or `READB`.
+    #[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+    Input(u64, u8),
+    /// The result of a call to a native function.
+    #[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+    Native(u64, Result<SVMCell, VmError>),
+    /// An IO event produced by the program.
+    #[cfg_attr(feature = "nightly", unstable(feature="replay"))]
+    Output(u64, IOEvent)
+}
+
+/// Records or replays the input, native results and output of a machine.
+#[derive(Clone,Debug)]
+pub enum Journal {
+    Off,
+    Recording(Vec<LogEntry>),
+    Replaying(Vec<LogEntry>, usize)
+}
+
+impl Journal {
+
+    /// Passes on the input read at `step`, recording it, or replaces it
+    /// with the input that was recorded.
+    pub fn input(&mut self, step: u64, input: Option<u8>) -> Result<Option<u8>, VmError> {
+        match *self {
+            Journal::Off => Ok(input),
+            Journal::Recording(ref mut log) => {
+                if let Some(byte) = input {
+                    log.push(LogEntry::Input(step, byte));
+                }
+                Ok(input)
+            },
+            Journal::Replaying(ref log, ref mut next) => match log.get(*next) {
+                Some(&LogEntry::Input(at, byte)) if at == step => {
+                    *next += 1;
+                    Ok(Some(byte))
+                },
+                entry => Err(diverged(step, entry, "a read"))
+            }
+        }
+    }
+
+    /// Calls a native function at `step`, recording its result, or
+    /// returns the result that was recorded without calling it.
+    pub fn native<F>(&mut self, step: u64, call: F)
+                     -> Result<Result<SVMCell, VmError>, VmError>
+        where F: FnOnce() -> Result<SVMCell, VmError>
+    {
+        match *self {
+            Journal::Off => Ok(call()),
+            Journal::Recording(ref mut log) => {
+                let result = call();
+                log.push(LogEntry::Native(step, result.clone()));
+                Ok(result)
+            },
+            Journal::Replaying(ref log, ref mut next) => match log.get(*next) {
+                Some(&LogEntry::Native(at, ref result)) if at == step => {
+                    *next += 1;
+                    Ok(result.clone())
+                },
+                entry => Err(diverged(step, entry, "a native call"))
+            }
+        }
+    }
+
+    /// Records the output produced at `step`, or checks that it is the
+    /// output that was recorded.
+    pub fn output(&mut self, step: u64, event: &Option<IOEvent>) -> Result<(), VmError> {
+        match *self {
+            Journal::Off => Ok(()),
+            Journal::Recording(ref mut log) => {
+                if let Some(ref event) = *event {
+                    log.push(LogEntry::Output(step, event.clone()));
+                }
+                Ok(())
+            },
+            Journal::Replaying(ref log, ref mut next) => match (log.get(*next), event) {
+                (Some(&LogEntry::Output(at, ref expected)), &Some(ref event))
+                    if at == step && expected == event => {
+                    *next += 1;
+                    Ok(())
+                },
+                (Some(&LogEntry::Output(at, _)), &None) if at != step => Ok(()),
+                (Some(&LogEntry::Output(_, _)), &None) =>
+                    Err(diverged(step, log.get(*next), "no output")),
+                (entry, &Some(ref event)) =>
+                    Err(diverged(step, entry, &format!("output {:?}", event))),
+                (_, &None) => Ok(())
+            }
+        }
+    }
+
+    /// Forgets everything recorded or replayed from `step` onwards, so
+    /// that it may be recorded or replayed again.
+    pub fn rewind(&mut self, step: u64) {
+        match *self {
+            Journal::Off => {},
+            Journal::Recording(ref mut log) =>
+                log.retain(|entry| entry_step(entry) < step),
+            Journal::Replaying(ref log, ref mut next) =>
+                *next = log.iter().take_while(|entry| entry_step(entry) < step).count()
+        }
+    }
+
+    /// Stops recording, returning the log, if the journal was recording.
+    pub fn stop_recording(&mut self) -> Option<Vec<LogEntry>> {
+        match mem::replace(self, Journal::Off) {
+            Journal::Recording(log) => Some(log),
+            other => {
+                *self = other;
+                None
+            }
+        }
+    }
+
+    /// Stops replaying, checking that the whole log was replayed.
+    pub fn finish_replay(&mut self) -> Result<(), VmError> {
+        match mem::replace(self, Journal::Off) {
+            Journal::Replaying(ref log, next) if next < log.len() => Err(format!(
+                "[fatal][replay]: replay ended with {} entries left, starting with {:?}",
+                log.len() - next, log[next])),
+            Journal::Replaying(_, _) => Ok(()),
+            other => {
+                *self = other;
+                Err(String::from("[fatal][replay]: not replaying"))
+            }
+        }
+    }
+}
+
+fn entry_step(entry: &LogEntry) -> u64 {
+    match *entry {
+        LogEntry::Input(step, _)  |
+        LogEntry::Native(step, _) |
+        LogEntry::Output(step, _) => step
+    }
+}
+
+fn diverged(step: u64, expected: Option<&LogEntry>, found: &str) -> VmError {
+    match expected {
+        Some(entry) => format!(
+            "[fatal][replay]: diverged at step {}: expected {:?}, found {}",
+            step, entry, found),
+        None => format!(
+            "[fatal][replay]: diverged at step {}: expected end of log, found {}",
+            step, found)
+    }
+}
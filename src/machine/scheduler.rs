@@ -0,0 +1,556 @@
+use super::Machine;
+use super::super::{State,EvalResult,VmError};
+use super::super::convert::IntoSvm;
+use super::super::{catch,take_dump};
+use super::super::slist::{List,Stack};
+use super::super::slist::List::{Cons,Nil};
+use super::super::cell::SVMCell;
+use super::super::cell::SVMCell::*;
+use super::super::cell::Atom::*;
+use super::super::cell::Inst::*;
+
+use std::mem;
+
+/// Identifies a task run by a `Scheduler`.
+#[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+pub type TaskId = usize;
+
+/// The result of a task which has finished.
+#[derive(Clone,Debug,PartialEq)]
+enum Outcome {
+    Finished(SVMCell),
+    Failed(VmError)
+}
+
+#[derive(Clone,Debug)]
+struct Task {
+    state: State,
+    outcome: Option<Outcome>
+}
+
+/// What happened when a task was stepped.
+enum Step {
+    Continue,
+    Yield,
+    Done
+}
+
+/// Runs several tasks on one `Machine`, cooperatively and deterministically.
+///
+/// Each task has its own `State`, but they share the natives and globals
+/// of the scheduler's machine. Tasks are started by the host with
+/// `spawn()` or by programs with `SPAWN`. A task runs until it executes
+/// `YIELD`, waits with `WAIT` for a task which has not yet finished,
+/// waits with `RECV` on an empty channel, or finishes; the scheduler then
+/// switches to the next task which is ready to run, in order of task ID,
+/// wrapping around after the last task.
+///
+/// The host drives the scheduler by calling `run()` with a budget of
+/// steps, so it decides how long programs may run before it regains
+/// control.
+///
+/// Tasks may not use the IO instructions `READC`, `READB`, `WRITEC` and
+/// `WRITEB`, since they have no IO streams of their own; a task which
+/// tries to fails with an error, which it may catch. Tasks should talk to
+/// the host through channels or native functions instead.
+#[derive(Clone,Debug)]
+#[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+pub struct Scheduler {
+    machine: Machine,
+    tasks: Vec<Task>,
+    current: TaskId
+}
+
+#[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+impl Scheduler {
+
+    /// Creates a new scheduler with no tasks, running on `machine`.
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    pub fn new(machine: Machine) -> Scheduler {
+        Scheduler { machine: machine, tasks: Vec::new(), current: 0 }
+    }
+
+    /// Returns the machine the scheduler's tasks run on.
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Returns the machine the scheduler's tasks run on, so that natives
+    /// may be bound and globals defined.
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Starts a new task evaluating `program`, returning its ID.
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    pub fn spawn(&mut self, program: List<SVMCell>) -> TaskId {
+        self.push_task(State {
+            stack:      Stack::empty(),
+            env:        Stack::empty(),
+            control:    program,
+            dump:       Stack::empty()
+        })
+    }
+
+    /// Starts a new task applying `function` to `args`, as `SPAWN` does,
+    /// returning its ID.
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    pub fn spawn_apply<A>(&mut self, function: SVMCell, args: A) -> TaskId
+        where A: IntoSvm
+    {
+        self.push_task(apply_state(function, args.into_svm()))
+    }
+
+    /// Returns the result of a task, or `None` if it has not finished
+    /// (or does not exist).
+    ///
+    /// The result of a task which finished normally is the value on top
+    /// of its stack, or `nil` if its stack was empty; the result of a task
+    /// which failed is its error.
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    pub fn result(&self, task: TaskId) -> Option<Result<SVMCell, VmError>> {
+        self.tasks.get(task)
+            .and_then(|task| task.outcome.as_ref())
+            .map(|outcome| match *outcome {
+                Outcome::Finished(ref result) => Ok(result.clone()),
+                Outcome::Failed(ref why)      => Err(why.clone())
+            })
+    }
+
+    /// Returns true if every task has finished.
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    pub fn is_finished(&self) -> bool {
+        self.tasks.iter().all(|task| task.outcome.is_some())
+    }
+
+    /// Runs tasks for at most `budget` steps, returning true if every task
+    /// has finished, or false if the budget ran out first or every
+    /// unfinished task is waiting.
+    ///
+    /// An error in a task does not stop the scheduler; it is kept as the
+    /// result of that task. Since the host may send values on channels
+    /// between calls to `run()`, tasks waiting to receive from a channel
+    /// may be resumed later; but it is an error if every unfinished task
+    /// is waiting for another unfinished task.
+    #[cfg_attr(feature = "nightly", unstable(feature="tasks"))]
+    pub fn run(&mut self, budget: usize, debug: bool) -> Result<bool, VmError> {
+        let mut steps = 0;
+        while steps < budget {
+            let task = match self.next_ready() {
+                Some(task) => task,
+                None if self.is_finished() => return Ok(true),
+                None if self.waiting().iter().any(|&task| self.is_receiving(task)) =>
+                    return Ok(false),
+                None => return Err(format!(
+                    "[fatal][Scheduler]: deadlock, tasks {:?} are all waiting",
+                    self.waiting()))
+            };
+            self.current = task;
+            while steps < budget {
+                steps += 1;
+                match self.step_task(task, debug) {
+                    Step::Continue if !self.is_blocked(task) => {},
+                    Step::Continue => break,
+                    Step::Yield => {
+                        self.current = task + 1;
+                        break
+                    },
+                    Step::Done => break
+                }
+            }
+        }
+        Ok(self.is_finished())
+    }
+
+    fn push_task(&mut self, state: State) -> TaskId {
+        self.tasks.push(Task { state: state, outcome: None });
+        self.tasks.len() - 1
+    }
+
+    /// Returns the first task which is ready to run, starting from the
+    /// current task.
+    fn next_ready(&self) -> Option<TaskId> {
+        let len = self.tasks.len();
+        (0..len)
+            .map(|i| (self.current + i) % len)
+            .find(|&task| self.tasks[task].outcome.is_none() && !self.is_blocked(task))
+    }
+
+    /// Returns the unfinished tasks which are waiting for other tasks.
+    fn waiting(&self) -> Vec<TaskId> {
+        (0..self.tasks.len())
+            .filter(|&task| self.tasks[task].outcome.is_none() && self.is_blocked(task))
+            .collect()
+    }
+
+    /// Returns true if a task is waiting for a task which has not yet
+    /// finished, or to receive from an empty channel.
+    fn is_blocked(&self, task: TaskId) -> bool {
+        let state = &self.tasks[task].state;
+        match (state.control.peek(), state.stack.peek()) {
+            (Some(&InstCell(WAIT)), Some(&AtomCell(UInt(other)))) =>
+                self.tasks.get(other as usize)
+                    .map_or(false, |other| other.outcome.is_none()),
+            _ => self.is_receiving(task)
+        }
+    }
+
+    /// Returns true if a task is waiting to receive from an empty channel.
+    fn is_receiving(&self, task: TaskId) -> bool {
+        let state = &self.tasks[task].state;
+        match (state.control.peek(), state.stack.peek()) {
+            (Some(&InstCell(RECV)), Some(&ChannelCell(ref channel))) => channel.is_empty(),
+            _ => false
+        }
+    }
+
+    /// Evaluates the next instruction of a task.
+    fn step_task(&mut self, task: TaskId, debug: bool) -> Step {
+        let state = mem::replace(&mut self.tasks[task].state, State::new());
+        let inst = match state.control.peek() {
+            None | Some(&InstCell(STOP)) => {
+                let result = state.stack.peek().cloned().unwrap_or(list_cell![]);
+                self.tasks[task].outcome = Some(Outcome::Finished(result));
+                return Step::Done
+            },
+            Some(inst) => inst.clone()
+        };
+        let prev = if debug { Some(state.clone()) } else { None };
+        let result = match inst {
+            InstCell(SPAWN) | InstCell(WAIT) |
+            InstCell(READC) | InstCell(READB) | InstCell(WRITEC) | InstCell(WRITEB) => {
+                catch(state, prev.as_ref(),
+                      |state, dump, prev| self.eval_task_inst(state, dump, prev))
+                    .map(|(state, _)| state)
+            },
+            InstCell(YIELD) => Ok(State {
+                control: state.control.pop().unwrap().1,
+                ..state
+            }),
+            // tasks are evaluated on the machine without touching its own
+            // state, step count, history or journal
+            _ => self.machine.eval_state(state, None, prev.as_ref(), None)
+                    .map(|(state, _)| state)
+        };
+        match result {
+            Ok(state) => {
+                self.tasks[task].state = state;
+                match inst {
+                    InstCell(YIELD) => Step::Yield,
+                    _               => Step::Continue
+                }
+            },
+            Err(why) => {
+                self.tasks[task].outcome = Some(Outcome::Failed(why));
+                Step::Done
+            }
+        }
+    }
+
+    /// Evaluates an instruction which needs access to the scheduler.
+    fn eval_task_inst(&mut self, state: State, dump: &mut List<SVMCell>,
+                      prev: Option<&State>) -> EvalResult {
+        let (inst, new_control) = state.control.pop().unwrap();
+        match (inst, state.stack.pop()) {
+            (InstCell(SPAWN), Some((function, new_stack))) => match new_stack.pop() {
+                Some((args, newer_stack)) => {
+                    let task = self.push_task(apply_state(function, args));
+                    Ok((State {
+                        stack: newer_stack.push(AtomCell(UInt(task as u64))),
+                        env: state.env,
+                        control: new_control,
+                        dump: take_dump(dump)
+                    }, None))
+                },
+                None => Err(format!(
+                    "[fatal][SPAWN]: expected arguments, found nothing\n{}",
+                    prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+            },
+            (InstCell(WAIT), Some((AtomCell(UInt(task)), new_stack))) =>
+                match self.tasks.get(task as usize).map(|task| task.outcome.clone()) {
+                    Some(Some(Outcome::Finished(result))) => Ok((State {
+                        stack: new_stack.push(result),
+                        env: state.env,
+                        control: new_control,
+                        dump: take_dump(dump)
+                    }, None)),
+                    Some(Some(Outcome::Failed(why))) => Err(format!(
+                        "[fatal][WAIT]: task {} failed: {}\n{}",
+                        task, why.lines().next().unwrap_or(""),
+                        prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+                    Some(None) => unreachable!(
+                        "[fatal][WAIT]: scheduled a task which is waiting"),
+                    None => Err(format!(
+                        "[fatal][WAIT]: no task {}\n{}",
+                        task, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+                },
+            (InstCell(io @ READC), _)  | (InstCell(io @ READB), _) |
+            (InstCell(io @ WRITEC), _) | (InstCell(io @ WRITEB), _) => Err(format!(
+                "[fatal][{:?}]: tasks cannot do IO; use a channel or a native function instead\n{}",
+                io, prev.map_or(String::new(), |x| x.dump_state("fatal") )) ),
+            (inst, thing) => Err(format!(
+                "[fatal][{:?}]: expected {}, found {:?}\n{}",
+                inst,
+                match inst { InstCell(SPAWN) => "closure", _ => "task ID" },
+                thing, prev.map_or(String::new(), |x| x.dump_state("fatal") )) )
+        }
+    }
+}
+
+/// Returns a state which applies `function` to `args`.
+fn apply_state(function: SVMCell, args: SVMCell) -> State {
+    State {
+        stack:      list!(function, args),
+        env:        Stack::empty(),
+        control:    list!(InstCell(AP)),
+        dump:       Stack::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduler;
+    use super::super::Machine;
+    use ::slist::Stack;
+    use ::slist::List::{Cons,Nil};
+    use ::cell::{SVMCell,Channel};
+    use ::cell::Atom::*;
+    use ::cell::SVMCell::*;
+    use ::Inst::*;
+
+    use std::sync::{Arc,Mutex};
+
+    /// Builds a program which logs `a`, yields, and then logs `b`.
+    fn log_twice(a: i64, b: i64) -> ::slist::List<SVMCell> {
+        list!(
+            InstCell(LDC), AtomCell(SInt(a)),
+            InstCell(CALLN), NativeCell(String::from("log")),
+            InstCell(YIELD),
+            InstCell(LDC), AtomCell(SInt(b)),
+            InstCell(CALLN), NativeCell(String::from("log"))
+        )
+    }
+
+    #[test]
+    fn test_yield_round_robin() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut machine = Machine::new();
+        let sink = log.clone();
+        machine.bind_native("log", move |args: &[SVMCell]| {
+            sink.lock().unwrap().push(args[0].clone());
+            Ok(list_cell![])
+        });
+        let mut scheduler = Scheduler::new(machine);
+        scheduler.spawn(log_twice(1, 2));
+        scheduler.spawn(log_twice(10, 20));
+        assert_eq!(scheduler.run(100, true), Ok(true));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![AtomCell(SInt(1)), AtomCell(SInt(10)), AtomCell(SInt(2)), AtomCell(SInt(20))]
+        );
+    }
+
+    #[test]
+    fn test_spawn_wait() {
+        let mut scheduler = Scheduler::new(Machine::new());
+        // (wait (spawn (lambda (a b) (+ a b)) '(20 22)))
+        let task = scheduler.spawn(list!(
+            InstCell(NIL),
+            InstCell(LDC), AtomCell(SInt(20)), InstCell(CONS),
+            InstCell(LDC), AtomCell(SInt(22)), InstCell(CONS),
+            InstCell(LDF),
+            list_cell![
+                InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+                InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(2)) ],
+                InstCell(ADD),
+                InstCell(RET)
+            ],
+            InstCell(SPAWN),
+            InstCell(WAIT)
+        ));
+        assert_eq!(scheduler.run(100, true), Ok(true));
+        assert_eq!(scheduler.result(task), Some(Ok(AtomCell(SInt(42)))));
+        assert_eq!(scheduler.result(task + 1), Some(Ok(AtomCell(SInt(42)))));
+    }
+
+    #[test]
+    fn test_wait_failed_task() {
+        let mut scheduler = Scheduler::new(Machine::new());
+        let failing = scheduler.spawn(list!(InstCell(CAR)));
+        // a waiting task can catch the error
+        let waiting = scheduler.spawn(list!(
+            InstCell(TRY),
+                list_cell![ InstCell(JOIN) ],
+                list_cell![
+                    InstCell(LDC), AtomCell(UInt(failing as u64)),
+                    InstCell(WAIT),
+                    InstCell(ENDTRY)
+                ]
+        ));
+        assert_eq!(scheduler.run(100, false), Ok(true));
+        assert!(scheduler.result(failing).unwrap().is_err());
+        match scheduler.result(waiting) {
+            Some(Ok(ListCell(_))) => {},
+            thing => panic!("expected condition, found {:?}", thing)
+        }
+    }
+
+    #[test]
+    fn test_io_in_task() {
+        let mut scheduler = Scheduler::new(Machine::new());
+        let reading = scheduler.spawn(list!(InstCell(READC)));
+        let writing = scheduler.spawn(list!(
+            InstCell(TRY),
+                list_cell![ InstCell(JOIN) ],
+                list_cell![
+                    InstCell(LDC), AtomCell(Char('a')),
+                    InstCell(WRITEC),
+                    InstCell(ENDTRY)
+                ]
+        ));
+        assert_eq!(scheduler.run(100, false), Ok(true));
+        assert_eq!(
+            scheduler.result(reading),
+            Some(Err(String::from(
+                "[fatal][READC]: tasks cannot do IO; use a channel or a native function instead\n")))
+        );
+        match scheduler.result(writing) {
+            Some(Ok(ListCell(_))) => {},
+            thing => panic!("expected condition, found {:?}", thing)
+        }
+    }
+
+    #[test]
+    fn test_tasks_leave_machine() {
+        let mut machine = Machine::new();
+        machine.keep_history(100);
+        machine.record();
+        let mut scheduler = Scheduler::new(machine);
+        let task = scheduler.spawn(list!(
+            InstCell(LDC), AtomCell(SInt(1)),
+            InstCell(LDC), AtomCell(SInt(2)),
+            InstCell(ADD)
+        ));
+        assert_eq!(scheduler.run(100, false), Ok(true));
+        assert_eq!(scheduler.result(task), Some(Ok(AtomCell(SInt(3)))));
+        let machine = scheduler.machine_mut();
+        assert_eq!(machine.steps(), 0);
+        assert!(machine.history().is_empty());
+        assert!(!machine.step_back());
+        assert_eq!(machine.stop_recording(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut scheduler = Scheduler::new(Machine::new());
+        let task = scheduler.spawn(list!(
+            InstCell(LDC), AtomCell(SInt(1)),
+            InstCell(LDC), AtomCell(SInt(2)),
+            InstCell(ADD)
+        ));
+        assert_eq!(scheduler.run(2, true), Ok(false));
+        assert_eq!(scheduler.result(task), None);
+        assert_eq!(scheduler.run(2, true), Ok(true));
+        assert_eq!(scheduler.result(task), Some(Ok(AtomCell(SInt(3)))));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let mut scheduler = Scheduler::new(Machine::new());
+        scheduler.spawn(list!(InstCell(LDC), AtomCell(UInt(0)), InstCell(WAIT)));
+        assert_eq!(
+            scheduler.run(100, true),
+            Err(String::from("[fatal][Scheduler]: deadlock, tasks [0] are all waiting"))
+        );
+    }
+
+    #[test]
+    fn test_spawn_without_scheduler() {
+        let result = ::State {
+            stack: Stack::empty(),
+            env: Stack::empty(),
+            control: list!(InstCell(YIELD)),
+            dump: Stack::empty()
+        }.eval(None, false);
+        assert_eq!(
+            result,
+            Err(String::from("[fatal][YIELD]: this instruction may only be evaluated by a Scheduler\n"))
+        );
+    }
+
+    #[test]
+    fn test_channel_between_tasks() {
+        let mut scheduler = Scheduler::new(Machine::new());
+        let channel = Channel::new();
+        // (lambda (ch) (+ (recv ch) (recv ch)))
+        let consumer = scheduler.spawn_apply(list_cell![
+            list_cell![
+                InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+                InstCell(RECV),
+                InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+                InstCell(RECV),
+                InstCell(ADD),
+                InstCell(RET)
+            ],
+            list_cell![]
+        ], (ChannelCell(channel.clone()),));
+        // (lambda (ch) (send ch 1) (yield) (send ch 2))
+        scheduler.spawn_apply(list_cell![
+            list_cell![
+                InstCell(LDC), AtomCell(SInt(1)),
+                InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+                InstCell(SEND),
+                InstCell(YIELD),
+                InstCell(LDC), AtomCell(SInt(2)),
+                InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+                InstCell(SEND),
+                InstCell(NIL),
+                InstCell(RET)
+            ],
+            list_cell![]
+        ], (ChannelCell(channel.clone()),));
+        assert_eq!(scheduler.run(100, true), Ok(true));
+        assert_eq!(scheduler.result(consumer), Some(Ok(AtomCell(SInt(3)))));
+    }
+
+    #[test]
+    fn test_channel_with_host() {
+        let mut scheduler = Scheduler::new(Machine::new());
+        let requests = Channel::new();
+        let replies = Channel::new();
+        scheduler.machine_mut().define_global("requests", ChannelCell(requests.clone()));
+        scheduler.machine_mut().define_global("replies", ChannelCell(replies.clone()));
+        // echo each request back, doubled, forever
+        scheduler.spawn(list!(
+            InstCell(LDF),
+            list_cell![
+                InstCell(LDG), GlobalCell(String::from("requests")),
+                InstCell(RECV),
+                InstCell(LDC), AtomCell(SInt(2)),
+                InstCell(MUL),
+                InstCell(LDG), GlobalCell(String::from("replies")),
+                InstCell(SEND),
+                InstCell(NIL),
+                InstCell(LDG), GlobalCell(String::from("loop")),
+                InstCell(AP),
+                InstCell(RET)
+            ],
+            InstCell(STG), GlobalCell(String::from("loop")),
+            InstCell(NIL),
+            InstCell(LDG), GlobalCell(String::from("loop")),
+            InstCell(AP)
+        ));
+        // the task waits for the host, rather than deadlocking
+        assert_eq!(scheduler.run(100, true), Ok(false));
+        assert!(replies.is_empty());
+        requests.send(AtomCell(SInt(21)));
+        assert_eq!(scheduler.run(100, true), Ok(false));
+        assert_eq!(replies.recv(), Some(AtomCell(SInt(42))));
+        requests.send(AtomCell(SInt(1)));
+        requests.send(AtomCell(SInt(2)));
+        assert_eq!(scheduler.run(100, true), Ok(false));
+        assert_eq!(replies.pending(), vec![AtomCell(SInt(2)), AtomCell(SInt(4))]);
+    }
+}
@@ -0,0 +1,577 @@
+use super::{Machine,LogEntry};
+use ::bytecode::Module;
+use ::IOEvent;
+use ::slist::List;
+use ::convert::FromSvm;
+use ::slist::Stack;
+use ::slist::List::{Cons,Nil};
+use ::cell::{SVMCell,SharedCell};
+use ::cell::Atom::*;
+use ::cell::SVMCell::*;
+use ::Inst::*;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+fn sum(args: &[SVMCell]) -> Result<SVMCell, String> {
+    args.iter().fold(Ok(AtomCell(SInt(0))), |acc, arg| match (acc, arg) {
+        (Ok(AtomCell(a)), &AtomCell(b)) => a.checked_add(b).map(AtomCell),
+        (Ok(_), thing) => Err(format!("expected number, found {:?}", thing)),
+        (err, _) => err
+    })
+}
+
+#[test]
+fn test_calln_by_name() {
+    let mut machine = Machine::new();
+    machine.bind_native("sum", sum);
+    machine.load(list!(
+        InstCell(NIL),
+        InstCell(LDC), AtomCell(SInt(2)), InstCell(CONS),
+        InstCell(LDC), AtomCell(SInt(1)), InstCell(CONS),
+        InstCell(CALLN), NativeCell(String::from("sum"))
+    ));
+    assert_eq!(machine.run(true).unwrap().peek(), Some(&AtomCell(SInt(3))));
+}
+
+#[test]
+fn test_calln_by_index() {
+    let mut machine = Machine::new();
+    machine.bind_native("one", |_: &[SVMCell]| Ok(AtomCell(SInt(1))));
+    let index = machine.bind_native("sum", sum);
+    assert_eq!(index, 1);
+    assert_eq!(machine.native_index("sum"), Some(1));
+    machine.load(list!(
+        InstCell(LDC), AtomCell(SInt(5)),
+        InstCell(CALLN), AtomCell(UInt(index as u64))
+    ));
+    assert_eq!(machine.run(true).unwrap().peek(), Some(&AtomCell(SInt(5))));
+}
+
+#[test]
+fn test_rebind_native() {
+    let mut machine = Machine::new();
+    assert_eq!(machine.bind_native("f", |_: &[SVMCell]| Ok(AtomCell(SInt(1)))), 0);
+    assert_eq!(machine.bind_native("f", |_: &[SVMCell]| Ok(AtomCell(SInt(2)))), 0);
+    machine.load(list!(
+        InstCell(NIL),
+        InstCell(CALLN), NativeCell(String::from("f"))
+    ));
+    assert_eq!(machine.run(true).unwrap().peek(), Some(&AtomCell(SInt(2))));
+}
+
+#[test]
+fn test_ap_native() {
+    let mut machine = Machine::new();
+    machine.bind_native("sum", sum);
+    machine.load(list!(
+        InstCell(NIL),
+        InstCell(LDC), AtomCell(SInt(20)), InstCell(CONS),
+        InstCell(LDC), AtomCell(SInt(10)), InstCell(CONS),
+        InstCell(LDC), NativeCell(String::from("sum")),
+        InstCell(AP)
+    ));
+    assert_eq!(machine.run(true).unwrap().peek(), Some(&AtomCell(SInt(30))));
+}
+
+#[test]
+fn test_calln_unbound() {
+    let mut machine = Machine::new();
+    machine.load(list!(
+        InstCell(NIL),
+        InstCell(CALLN), NativeCell(String::from("missing"))
+    ));
+    assert_eq!(
+        machine.run(false),
+        Err(String::from("[fatal][CALLN]: no native function bound to \"missing\"\n"))
+    );
+}
+
+#[test]
+fn test_native_error_caught() {
+    let mut machine = Machine::new();
+    machine.bind_native("sum", sum);
+    machine.load(list!(
+        InstCell(TRY),
+            list_cell![ InstCell(JOIN) ],
+            list_cell![
+                InstCell(NIL),
+                InstCell(NIL), InstCell(CONS),
+                InstCell(CALLN), NativeCell(String::from("sum")),
+                InstCell(ENDTRY)
+            ]
+    ));
+    let condition = "[fatal][CALLN]: sum: expected number, found nil".chars()
+        .map(|ch| AtomCell(Char(ch)))
+        .collect::<::slist::List<_>>();
+    assert_eq!(
+        machine.run(true).unwrap().peek(),
+        Some(&ListCell(Box::new(condition)))
+    );
+}
+
+#[test]
+fn test_procp_native() {
+    let mut machine = Machine::new();
+    machine.load(list!(
+        InstCell(LDC), NativeCell(String::from("sum")),
+        InstCell(PROCP)
+    ));
+    assert_eq!(
+        machine.run(true).unwrap().peek(),
+        Some(&list_cell![AtomCell(SInt(1))])
+    );
+}
+
+/// Builds a closure which adds `n` to its argument.
+fn add_n(n: i64) -> Vec<SVMCell> {
+    vec![
+        InstCell(LDF),
+        list_cell![
+            InstCell(LDC), AtomCell(SInt(n)),
+            InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+            InstCell(ADD),
+            InstCell(RET)
+        ]
+    ]
+}
+
+#[test]
+fn test_apply_closure() {
+    let mut machine = Machine::new();
+    machine.load(add_n(1).into_iter().collect());
+    let closure = machine.run(true).unwrap().peek().unwrap().clone();
+    assert_eq!(
+        machine.apply(closure.clone(), vec![41i64]).and_then(i64::from_svm),
+        Ok(42)
+    );
+    // the machine's own state is left as it was
+    assert_eq!(machine.state().stack.peek(), Some(&closure));
+}
+
+#[test]
+fn test_apply_leaves_history() {
+    let mut machine = Machine::new();
+    machine.bind_native("sum", sum);
+    machine.keep_history(100);
+    machine.record();
+    machine.load(list!(
+        InstCell(LDF),
+        list_cell![
+            InstCell(NIL),
+            InstCell(LDC), AtomCell(SInt(4)), InstCell(CONS),
+            InstCell(LDC), AtomCell(SInt(3)), InstCell(CONS),
+            InstCell(CALLN), NativeCell(String::from("sum")),
+            InstCell(RET)
+        ]
+    ));
+    assert!(machine.step(None, false).is_ok());
+    let closure = machine.state().stack.peek().unwrap().clone();
+    assert_eq!(machine.apply(closure, Vec::<i64>::new()), Ok(AtomCell(SInt(7))));
+    // none of the steps taken by the closure are counted or recorded
+    assert_eq!(machine.steps(), 1);
+    assert_eq!(machine.history().len(), 1);
+    assert_eq!(machine.stop_recording(), Some(Vec::new()));
+    assert!(machine.step_back());
+    assert_eq!(machine.state().control.peek(), Some(&InstCell(LDF)));
+}
+
+#[test]
+fn test_apply_nested_call() {
+    let mut machine = Machine::new();
+    // (lambda (f) (f 2)), applied to a closure
+    machine.load(list!(
+        InstCell(LDF),
+        list_cell![
+            InstCell(NIL),
+            InstCell(LDC), AtomCell(SInt(2)), InstCell(CONS),
+            InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+            InstCell(AP),
+            InstCell(RET)
+        ]
+    ));
+    let call_with_two = machine.run(true).unwrap().peek().unwrap().clone();
+    machine.load(add_n(10).into_iter().collect());
+    let add_ten = machine.run(true).unwrap().peek().unwrap().clone();
+    assert_eq!(
+        machine.apply(call_with_two, (add_ten,)),
+        Ok(AtomCell(SInt(12)))
+    );
+}
+
+#[test]
+fn test_apply_native() {
+    let mut machine = Machine::new();
+    machine.bind_native("sum", sum);
+    assert_eq!(
+        machine.apply(NativeCell(String::from("sum")), vec![1i64, 2, 3]),
+        Ok(AtomCell(SInt(6)))
+    );
+}
+
+#[test]
+fn test_apply_not_a_function() {
+    let mut machine = Machine::new();
+    assert!(machine.apply(AtomCell(SInt(1)), vec![1i64]).is_err());
+    assert_eq!(machine.state().stack.peek(), None);
+}
+
+#[test]
+fn test_ldg() {
+    let mut machine = Machine::new();
+    machine.define_global("x", 1i64);
+    let index = machine.define_global("y", 2i64);
+    machine.load(list!(
+        InstCell(LDG), GlobalCell(String::from("x")),
+        InstCell(LDG), AtomCell(UInt(index as u64)),
+        InstCell(ADD)
+    ));
+    assert_eq!(machine.run(true).unwrap().peek(), Some(&AtomCell(SInt(3))));
+}
+
+#[test]
+fn test_ldg_undefined() {
+    let mut machine = Machine::new();
+    machine.load(list!(InstCell(LDG), GlobalCell(String::from("x"))));
+    assert_eq!(
+        machine.run(false),
+        Err(String::from("[fatal][LDG]: no global bound to \"x\"\n"))
+    );
+}
+
+#[test]
+fn test_stg() {
+    let mut machine = Machine::new();
+    machine.load(list!(
+        InstCell(LDC), AtomCell(SInt(5)),
+        InstCell(STG), GlobalCell(String::from("x"))
+    ));
+    assert_eq!(machine.run(true).unwrap().peek(), None);
+    assert_eq!(machine.global("x"), Some(&AtomCell(SInt(5))));
+    assert_eq!(machine.global_index("x"), Some(0));
+    // storing by index replaces the value in the same slot
+    machine.load(list!(
+        InstCell(LDC), AtomCell(SInt(6)),
+        InstCell(STG), AtomCell(UInt(0))
+    ));
+    machine.run(true).unwrap();
+    assert_eq!(machine.globals(), vec![("x", &AtomCell(SInt(6)))]);
+}
+
+#[test]
+fn test_stg_undefined_index() {
+    let mut machine = Machine::new();
+    machine.load(list!(
+        InstCell(LDC), AtomCell(SInt(5)),
+        InstCell(STG), AtomCell(UInt(0))
+    ));
+    assert_eq!(
+        machine.run(false),
+        Err(String::from("[fatal][STG]: no global bound at 0\n"))
+    );
+}
+
+#[test]
+fn test_globals_across_programs() {
+    let mut machine = Machine::new();
+    // (define (inc x) (+ x 1))
+    machine.load(add_n(1).into_iter()
+        .chain(vec![InstCell(STG), GlobalCell(String::from("inc"))])
+        .collect());
+    machine.run(true).unwrap();
+    // (inc 41)
+    machine.load(list!(
+        InstCell(NIL),
+        InstCell(LDC), AtomCell(SInt(41)), InstCell(CONS),
+        InstCell(LDG), GlobalCell(String::from("inc")),
+        InstCell(AP)
+    ));
+    assert_eq!(machine.run(true).unwrap().peek(), Some(&AtomCell(SInt(42))));
+}
+
+#[test]
+fn test_ldg_without_machine() {
+    use ::State;
+    let result = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: list!(InstCell(LDG), GlobalCell(String::from("x"))),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][LDG]: this instruction may only be evaluated by a Machine\n"))
+    );
+}
+
+#[test]
+fn test_counter_closures() {
+    let mut machine = Machine::new();
+    // (lambda (n) (lambda () (set-box! n (+ (unbox n) 1)) (unbox n)))
+    machine.load(list!(
+        InstCell(LDF),
+        list_cell![
+            InstCell(LDF),
+            list_cell![
+                InstCell(LDC), AtomCell(SInt(1)),
+                InstCell(LD), list_cell![ AtomCell(UInt(2)), AtomCell(UInt(1)) ],
+                InstCell(UNBOX),
+                InstCell(ADD),
+                InstCell(LD), list_cell![ AtomCell(UInt(2)), AtomCell(UInt(1)) ],
+                InstCell(SETBOX),
+                InstCell(LD), list_cell![ AtomCell(UInt(2)), AtomCell(UInt(1)) ],
+                InstCell(UNBOX),
+                InstCell(RET)
+            ],
+            InstCell(RET)
+        ]
+    ));
+    let make_counter = machine.run(true).unwrap().peek().unwrap().clone();
+    let count = SharedCell::new(AtomCell(SInt(0)));
+    // closures capture the first level of $e, so the box is passed in a list
+    // to be found in a level of the counter's environment
+    let first = machine.apply(make_counter.clone(), (list_cell![ BoxCell(count.clone()) ],))
+        .unwrap();
+    let second = machine.apply(make_counter,
+                               (list_cell![ BoxCell(SharedCell::new(AtomCell(SInt(10)))) ],))
+        .unwrap();
+    assert_eq!(machine.apply(first.clone(), Vec::<i64>::new()), Ok(AtomCell(SInt(1))));
+    assert_eq!(machine.apply(first.clone(), Vec::<i64>::new()), Ok(AtomCell(SInt(2))));
+    assert_eq!(machine.apply(second, Vec::<i64>::new()), Ok(AtomCell(SInt(11))));
+    assert_eq!(machine.apply(first, Vec::<i64>::new()), Ok(AtomCell(SInt(3))));
+    // the host sees the count through its own copy of the box
+    assert_eq!(count.get(), AtomCell(SInt(3)));
+}
+
+/// Runs the loaded program, feeding it `input` a byte at a time as it
+/// reads, and returns its output.
+fn run_with_input(machine: &mut Machine, input: &[u8]) -> Result<Vec<IOEvent>, String> {
+    let mut input = input.iter().cloned();
+    let mut output = Vec::new();
+    while !machine.is_halted() {
+        let byte = match machine.state().control.peek() {
+            Some(&InstCell(READC)) => input.next(),
+            _                      => None
+        };
+        if let Some(event) = try!(machine.step(byte, true)) {
+            output.push(event);
+        }
+    }
+    Ok(output)
+}
+
+/// Builds a program which echoes a char, then writes a char returned by
+/// the native `next`.
+fn echo_and_next() -> List<SVMCell> {
+    list!(
+        InstCell(READC), InstCell(WRITEC),
+        InstCell(NIL),
+        InstCell(CALLN), NativeCell(String::from("next")),
+        InstCell(WRITEC)
+    )
+}
+
+#[test]
+fn test_record_replay() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut machine = Machine::new();
+    {
+        let counter = counter.clone();
+        machine.bind_native("next", move |_: &[SVMCell]| {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            Ok(AtomCell(Char((b'a' + n as u8) as char)))
+        });
+    }
+    machine.record();
+    machine.load(echo_and_next());
+    let output = run_with_input(&mut machine, b"x").unwrap();
+    assert_eq!(output, vec![IOEvent::Buf('x'), IOEvent::Buf('a')]);
+    let log = machine.stop_recording().unwrap();
+    assert_eq!(log, vec![
+        LogEntry::Input(0, b'x'),
+        LogEntry::Output(1, IOEvent::Buf('x')),
+        LogEntry::Native(3, Ok(AtomCell(Char('a')))),
+        LogEntry::Output(4, IOEvent::Buf('a'))
+    ]);
+    assert_eq!(machine.stop_recording(), None);
+
+    // the native would now return 'b', and the input is different
+    machine.replay(log);
+    machine.load(echo_and_next());
+    let replayed = run_with_input(&mut machine, b"y").unwrap();
+    assert_eq!(replayed, output);
+    assert_eq!(machine.finish_replay(), Ok(()));
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_replay_divergence() {
+    let mut machine = Machine::new();
+    machine.bind_native("next", |_: &[SVMCell]| Ok(AtomCell(Char('a'))));
+    machine.record();
+    machine.load(echo_and_next());
+    run_with_input(&mut machine, b"x").unwrap();
+    let log = machine.stop_recording().unwrap();
+
+    machine.replay(log);
+    machine.load(list!(
+        InstCell(READC), InstCell(WRITEC),
+        InstCell(LDC), AtomCell(Char('b')),
+        InstCell(WRITEC)
+    ));
+    let why = run_with_input(&mut machine, b"x").unwrap_err();
+    assert!(why.starts_with("[fatal][replay]: diverged at step 3"), "{}", why);
+}
+
+#[test]
+fn test_replay_unfinished() {
+    let mut machine = Machine::new();
+    machine.bind_native("next", |_: &[SVMCell]| Ok(AtomCell(Char('a'))));
+    machine.record();
+    machine.load(echo_and_next());
+    run_with_input(&mut machine, b"x").unwrap();
+    let log = machine.stop_recording().unwrap();
+
+    machine.replay(log);
+    machine.load(list!(InstCell(READC), InstCell(WRITEC)));
+    run_with_input(&mut machine, b"x").unwrap();
+    assert!(machine.finish_replay().unwrap_err()
+        .starts_with("[fatal][replay]: replay ended with 2 entries left"));
+    assert!(machine.finish_replay().is_err());
+}
+
+#[test]
+fn test_step_back_from_error() {
+    let mut machine = Machine::new();
+    machine.keep_history(16);
+    machine.load(list!(
+        InstCell(LDC), AtomCell(SInt(1)),
+        InstCell(LDC), AtomCell(SInt(2)),
+        InstCell(ADD),
+        InstCell(CAR)
+    ));
+    assert!(machine.run(false).is_err());
+    assert_eq!(machine.history().len(), 3);
+
+    // the machine is left in the state in which CAR failed
+    assert_eq!(machine.steps(), 3);
+    assert_eq!(machine.state().control.peek(), Some(&InstCell(CAR)));
+    assert_eq!(machine.state().stack.peek(), Some(&AtomCell(SInt(3))));
+    assert!(machine.step(None, false).is_err());
+    assert_eq!(machine.steps(), 3);
+
+    // the state in which the bad value was produced
+    assert!(machine.step_back());
+    assert_eq!(machine.state().control.peek(), Some(&InstCell(ADD)));
+    assert!(machine.step_back());
+    assert!(machine.step_back());
+    assert_eq!(machine.steps(), 0);
+    assert!(!machine.step_back());
+}
+
+#[test]
+fn test_step_error_debug() {
+    // without a history, the state is only kept after an error in debug mode
+    let mut machine = Machine::new();
+    machine.load(list!(InstCell(LDC), AtomCell(SInt(1)), InstCell(CAR)));
+    assert!(machine.step(None, false).is_ok());
+    assert!(machine.step(None, true).is_err());
+    assert_eq!(machine.steps(), 1);
+    assert_eq!(machine.state().control.peek(), Some(&InstCell(CAR)));
+    assert!(machine.history().is_empty());
+}
+
+#[test]
+fn test_step_back_shares_boxes() {
+    let shared = SharedCell::new(AtomCell(SInt(1)));
+    let mut machine = Machine::new();
+    machine.keep_history(16);
+    machine.load(list!(
+        InstCell(LDC), AtomCell(SInt(2)),
+        InstCell(LDC), BoxCell(shared.clone()),
+        InstCell(SETBOX)
+    ));
+    assert!(machine.run(false).is_ok());
+    assert!(machine.step_back());
+    assert_eq!(machine.state().control.peek(), Some(&InstCell(SETBOX)));
+    // the box is shared with the history, so the store is not undone
+    assert_eq!(shared.get(), AtomCell(SInt(2)));
+}
+
+#[test]
+fn test_history_len() {
+    let mut machine = Machine::new();
+    machine.keep_history(2);
+    machine.load(list!(
+        InstCell(LDC), AtomCell(SInt(1)),
+        InstCell(LDC), AtomCell(SInt(2)),
+        InstCell(LDC), AtomCell(SInt(3))
+    ));
+    machine.run(false).unwrap();
+    let steps = machine.history().iter().map(|&(step, _)| step).collect::<Vec<_>>();
+    assert_eq!(steps, vec![1, 2]);
+    assert!(machine.step_back());
+    assert!(machine.step_back());
+    assert!(!machine.step_back());
+    assert_eq!(machine.state().stack.peek(), Some(&AtomCell(SInt(1))));
+
+    machine.keep_history(0);
+    machine.run(false).unwrap();
+    assert!(machine.history().is_empty());
+}
+
+#[test]
+fn test_step_back_while_recording() {
+    let mut machine = Machine::new();
+    machine.bind_native("next", |_: &[SVMCell]| Ok(AtomCell(Char('a'))));
+    machine.keep_history(8);
+    machine.record();
+    machine.load(echo_and_next());
+    run_with_input(&mut machine, b"x").unwrap();
+    assert!(machine.step_back());
+    assert!(machine.step_back());
+    run_with_input(&mut machine, b"").unwrap();
+    let log = machine.stop_recording().unwrap();
+    assert_eq!(log.len(), 4);
+
+    machine.replay(log);
+    machine.load(echo_and_next());
+    run_with_input(&mut machine, b"x").unwrap();
+    for _ in 0..4 {
+        assert!(machine.step_back());
+    }
+    run_with_input(&mut machine, b"").unwrap();
+    assert_eq!(machine.finish_replay(), Ok(()));
+}
+
+#[test]
+fn test_load_module() {
+    let mut machine = Machine::new();
+    machine.bind_native("sum", sum);
+    let module = Module {
+        symbols: vec![(String::from("x"), AtomCell(SInt(2)))],
+        imports: vec![String::from("sum")],
+        ..Module::new(list!(
+            InstCell(NIL),
+            InstCell(LDG), GlobalCell(String::from("x")), InstCell(CONS),
+            InstCell(LDC), AtomCell(SInt(1)), InstCell(CONS),
+            InstCell(CALLN), NativeCell(String::from("sum"))
+        ))
+    };
+    machine.load_module(module).unwrap();
+    assert_eq!(machine.run(false).unwrap().peek(), Some(&AtomCell(SInt(3))));
+}
+
+#[test]
+fn test_load_module_missing_import() {
+    let mut machine = Machine::new();
+    let module = Module {
+        imports: vec![String::from("sum")],
+        ..Module::new(list!(InstCell(NIL)))
+    };
+    assert_eq!(
+        machine.load_module(module),
+        Err(String::from("[fatal][load]: program imports native sum, which is not bound"))
+    );
+    assert!(machine.is_halted());
+}
@@ -1,6 +1,8 @@
 use ::slist::Stack;
 use ::slist::List::{Cons,Nil};
-use super::State;
+use super::{State,IOEvent,MapKey,SharedCell,Channel,SVMCell};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use super::cell::Atom::*;
 use super::cell::SVMCell::*;
 use super::Inst::*;
@@ -1238,6 +1240,15 @@ fn test_eval_ap() {
         )
     );
     //assert_eq!(state.dump, list!(ListCell(box list!(InstCell(DUM))),ListCell(box list!(ListCell(box list!(AtomCell(Char('D'))))))));
+    // the saved stack is on top of the dump, where RET expects it
+    assert_eq!(
+        state.dump,
+        list!(
+            list_cell![],
+            list_cell![ list_cell![ AtomCell(Char('D')) ] ],
+            list_cell![ InstCell(DUM) ]
+        )
+    );
 }
 
 #[test]
@@ -1419,6 +1430,996 @@ fn test_eval_null() {
         );
 }
 
+#[test]
+fn test_eval_type_predicates() {
+    fn check(inst: super::Inst, target: super::SVMCell) -> bool {
+        State {
+            stack: list!(target),
+            env: Stack::empty(),
+            control: list!(InstCell(inst)),
+            dump: Stack::empty(),
+        }.eval(None,true).unwrap().0.stack.peek() != Some(&list_cell![])
+    }
+    assert!(check(INTP, AtomCell(SInt(-1))));
+    assert!(check(INTP, AtomCell(UInt(1))));
+    assert!(!check(INTP, AtomCell(Float(1.0))));
+    assert!(check(FLOATP, AtomCell(Float(1.0))));
+    assert!(!check(FLOATP, AtomCell(SInt(1))));
+    assert!(check(CHARP, AtomCell(Char('a'))));
+    assert!(!check(CHARP, list_cell![ AtomCell(Char('a')) ]));
+    assert!(check(PAIRP, list_cell![ AtomCell(Char('a')) ]));
+    assert!(!check(PAIRP, list_cell![]));
+    assert!(!check(PAIRP, AtomCell(SInt(1))));
+    assert!(check(PROCP, list_cell![
+        list_cell![ InstCell(LDC), AtomCell(SInt(1)), InstCell(RET) ],
+        list_cell![]
+    ]));
+    assert!(!check(PROCP, list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ]));
+    assert!(!check(PROCP, AtomCell(SInt(1))));
+}
+
+#[test]
+fn test_eval_conversions() {
+    fn convert(inst: super::Inst, atom: super::Atom) -> Result<super::SVMCell, String> {
+        State {
+            stack: list!(AtomCell(atom)),
+            env: Stack::empty(),
+            control: list!(InstCell(inst)),
+            dump: Stack::empty(),
+        }.eval(None,true).map(|(state, _)| state.stack.peek().unwrap().clone())
+    }
+    assert_eq!(convert(TOINT, Float(4.0)), Ok(AtomCell(SInt(4))));
+    assert_eq!(convert(TOINT, UInt(4)), Ok(AtomCell(SInt(4))));
+    assert_eq!(convert(TOUINT, SInt(4)), Ok(AtomCell(UInt(4))));
+    assert_eq!(convert(TOFLOAT, SInt(-4)), Ok(AtomCell(Float(-4.0))));
+    assert_eq!(convert(TOCHAR, UInt(0x61)), Ok(AtomCell(Char('a'))));
+    assert_eq!(convert(TOINT, Char('a')), Ok(AtomCell(SInt(0x61))));
+    // lossy conversions are errors
+    assert!(convert(TOINT, Float(4.5)).unwrap_err()
+        .starts_with("[fatal][TOINT]: cannot convert 4.5f to sint without loss"));
+    assert!(convert(TOUINT, SInt(-4)).is_err());
+    assert!(convert(TOCHAR, UInt(0x110000)).is_err());
+}
+
+#[test]
+fn test_eval_conversion_type_error() {
+    let result = State {
+        stack: list!(list_cell![]),
+        env: Stack::empty(),
+        control: list!(InstCell(TOFLOAT)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][TOFLOAT]: expected atom, found Some((nil, nil))\n"))
+    );
+}
+
+#[test]
+fn test_eval_char_arithmetic() {
+    let state = State {
+        stack: list!(AtomCell(Char('λ')), AtomCell(UInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(ADD)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(Char('μ'))));
+
+    let state = State {
+        stack: list!(AtomCell(Char('😀')), AtomCell(Char('\u{1}'))),
+        env: Stack::empty(),
+        control: list!(InstCell(SUB)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(Char('\u{1F5FF}'))));
+
+    // results which aren't valid chars are errors
+    let result = State {
+        stack: list!(AtomCell(Char('\u{D7FF}')), AtomCell(UInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(ADD)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][ADD]: 0xd800 is not a valid char\n"))
+    );
+}
+
+#[test]
+fn test_eval_writec_utf8() {
+    let (_, event) = State {
+        stack: list!(AtomCell(Char('λ'))),
+        env: Stack::empty(),
+        control: list!(InstCell(WRITEC)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap();
+    let mut out = Vec::new();
+    event.unwrap().write_to(&mut out).unwrap();
+    assert_eq!(out, vec![0xCE, 0xBB]);
+}
+
+#[test]
+fn test_eval_float_math() {
+    fn unary(inst: super::Inst, atom: super::Atom) -> super::SVMCell {
+        State {
+            stack: list!(AtomCell(atom)),
+            env: Stack::empty(),
+            control: list!(InstCell(inst)),
+            dump: Stack::empty(),
+        }.eval(None,true).unwrap().0.stack.peek().unwrap().clone()
+    }
+    assert_eq!(unary(SQRT, Float(2.25)), AtomCell(Float(1.5)));
+    // integers are coerced to floats, as with FDIV
+    assert_eq!(unary(SQRT, SInt(16)), AtomCell(Float(4.0)));
+    assert_eq!(unary(EXP, UInt(0)), AtomCell(Float(1.0)));
+    assert_eq!(unary(LOG, Float(1.0)), AtomCell(Float(0.0)));
+    assert_eq!(unary(SIN, SInt(0)), AtomCell(Float(0.0)));
+    assert_eq!(unary(COS, SInt(0)), AtomCell(Float(1.0)));
+    assert_eq!(unary(TAN, Float(0.0)), AtomCell(Float(0.0)));
+    assert_eq!(unary(ASIN, SInt(1)), AtomCell(Float(::std::f64::consts::FRAC_PI_2)));
+    assert_eq!(unary(ACOS, SInt(1)), AtomCell(Float(0.0)));
+    assert_eq!(unary(ATAN, SInt(0)), AtomCell(Float(0.0)));
+    assert_eq!(unary(FLOOR, Float(-1.5)), AtomCell(Float(-2.0)));
+    assert_eq!(unary(CEIL, Float(-1.5)), AtomCell(Float(-1.0)));
+    assert_eq!(unary(ROUND, Float(2.5)), AtomCell(Float(3.0)));
+    assert_eq!(unary(NANP, Float((-1.0f64).sqrt())), list_cell![AtomCell(SInt(1))]);
+    assert_eq!(unary(NANP, SInt(1)), list_cell![]);
+    assert_eq!(unary(INFP, Float(1.0f64 / 0.0)), list_cell![AtomCell(SInt(1))]);
+    assert_eq!(unary(INFP, Float(1.0)), list_cell![]);
+}
+
+#[test]
+fn test_eval_pow_atan2() {
+    let state = State {
+        stack: list!(AtomCell(SInt(2)), AtomCell(Float(10.0))),
+        env: Stack::empty(),
+        control: list!(InstCell(POW)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(Float(1024.0))));
+
+    let state = State {
+        stack: list!(AtomCell(SInt(1)), AtomCell(SInt(-1))),
+        env: Stack::empty(),
+        control: list!(InstCell(ATAN2)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(
+        state.stack.peek(),
+        Some(&AtomCell(Float(3.0 * ::std::f64::consts::FRAC_PI_4)))
+    );
+}
+
+#[test]
+fn test_eval_float_math_type_error() {
+    let result = State {
+        stack: list!(list_cell![]),
+        env: Stack::empty(),
+        control: list!(InstCell(SQRT)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][SQRT]: expected number, found Some((nil, nil))\n"))
+    );
+}
+
+#[test]
+fn test_eval_vmake() {
+    let state = State {
+        stack: list!(AtomCell(UInt(3)), AtomCell(Char('a'))),
+        env: Stack::empty(),
+        control: list!(InstCell(VMAKE)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(
+        state.stack.peek(),
+        Some(&VectorCell(Arc::new(vec![AtomCell(Char('a')); 3])))
+    );
+}
+
+#[test]
+fn test_eval_vmake_too_long() {
+    let result = State {
+        stack: list!(AtomCell(UInt(1 << 40)), AtomCell(Char('a'))),
+        env: Stack::empty(),
+        control: list!(InstCell(VMAKE)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][VMAKE]: length 1099511627776 is greater than the \
+                          maximum vector length, 16777216\n"))
+    );
+}
+
+#[test]
+fn test_eval_vmake_too_large() {
+    let result = State {
+        stack: list!(AtomCell(UInt(1 << 23)), list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ]),
+        env: Stack::empty(),
+        control: list!(InstCell(VMAKE)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][VMAKE]: 8388608 copies of an item of 3 cells would hold \
+                          more than the maximum of 16777216 cells\n"))
+    );
+}
+
+#[test]
+fn test_eval_vget() {
+    let state = State {
+        stack: list!(
+            VectorCell(Arc::new(vec![AtomCell(SInt(1)), AtomCell(SInt(2)), AtomCell(SInt(3))])),
+            AtomCell(UInt(1))
+        ),
+        env: Stack::empty(),
+        control: list!(InstCell(VGET)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(SInt(2))));
+
+    let result = State {
+        stack: list!(VectorCell(Arc::new(vec![AtomCell(SInt(1))])), AtomCell(UInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(VGET)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(
+        result,
+        Err(String::from(
+            "[fatal][VGET]: index 1u out of range for vector of length 1\n"))
+    );
+}
+
+#[test]
+fn test_eval_vset() {
+    let state = State {
+        stack: list!(
+            VectorCell(Arc::new(vec![AtomCell(SInt(1)), AtomCell(SInt(2))])),
+            AtomCell(SInt(0)),
+            list_cell![]
+        ),
+        env: Stack::empty(),
+        control: list!(InstCell(VSET)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(
+        state.stack,
+        list!(VectorCell(Arc::new(vec![list_cell![], AtomCell(SInt(2))])))
+    );
+}
+
+#[test]
+fn test_eval_vset_shared() {
+    let vec = Arc::new(vec![AtomCell(SInt(1)), AtomCell(SInt(2))]);
+    let state = State {
+        stack: Stack::empty(),
+        env: list!(list_cell![ VectorCell(vec.clone()) ]),
+        control: list!(
+            InstCell(LDC), AtomCell(SInt(3)),
+            InstCell(LDC), AtomCell(UInt(1)),
+            InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(1)) ],
+            InstCell(VSET)
+        ),
+        dump: Stack::empty(),
+    };
+    let state = state.eval(None,true).unwrap().0
+                     .eval(None,true).unwrap().0
+                     .eval(None,true).unwrap().0;
+    // the vector is loaded without being copied
+    match state.stack.peek() {
+        Some(&VectorCell(ref loaded)) => assert!(Arc::ptr_eq(loaded, &vec)),
+        other => panic!("expected a vector, found {:?}", other)
+    }
+    let state = state.eval(None,true).unwrap().0;
+    assert_eq!(
+        state.stack.peek(),
+        Some(&VectorCell(Arc::new(vec![AtomCell(SInt(1)), AtomCell(SInt(3))])))
+    );
+    // the copy in the environment is unchanged
+    assert_eq!(state.env, list!(list_cell![ VectorCell(vec.clone()) ]));
+    assert_eq!(*vec, vec![AtomCell(SInt(1)), AtomCell(SInt(2))]);
+}
+
+#[test]
+fn test_eval_vlen() {
+    let state = State {
+        stack: list!(VectorCell(Arc::new(vec![AtomCell(SInt(1)), AtomCell(SInt(2))]))),
+        env: Stack::empty(),
+        control: list!(InstCell(VLEN)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(UInt(2))));
+}
+
+#[test]
+fn test_eval_vtol_ltov() {
+    let state = State {
+        stack: list!(list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ]),
+        env: Stack::empty(),
+        control: list!(InstCell(LTOV)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(
+        state.stack.peek(),
+        Some(&VectorCell(Arc::new(vec![AtomCell(SInt(1)), AtomCell(SInt(2))])))
+    );
+    let state = State {
+        stack: state.stack,
+        env: Stack::empty(),
+        control: list!(InstCell(VTOL)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(
+        state.stack.peek(),
+        Some(&list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ])
+    );
+}
+
+#[test]
+fn test_eval_blen() {
+    let state = State {
+        stack: list!(BytesCell(vec![1, 2, 3])),
+        env: Stack::empty(),
+        control: list!(InstCell(BLEN)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(UInt(3))));
+}
+
+#[test]
+fn test_eval_bget() {
+    let state = State {
+        stack: list!(BytesCell(vec![7, 8, 9]), AtomCell(UInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(BGET)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(UInt(8))));
+    let result = State {
+        stack: list!(BytesCell(vec![7]), AtomCell(UInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(BGET)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(result, Err(String::from(
+            "[fatal][BGET]: index 1u out of range for byte buffer of length 1\n")));
+}
+
+#[test]
+fn test_eval_bslice() {
+    let state = State {
+        stack: list!(BytesCell(vec![1, 2, 3, 4]), AtomCell(UInt(1)), AtomCell(UInt(3))),
+        env: Stack::empty(),
+        control: list!(InstCell(BSLICE)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&BytesCell(vec![2, 3])));
+    let result = State {
+        stack: list!(BytesCell(vec![1, 2]), AtomCell(UInt(1)), AtomCell(UInt(3))),
+        env: Stack::empty(),
+        control: list!(InstCell(BSLICE)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_eval_bcat() {
+    let state = State {
+        stack: list!(BytesCell(vec![1, 2]), BytesCell(vec![3])),
+        env: Stack::empty(),
+        control: list!(InstCell(BCAT)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&BytesCell(vec![1, 2, 3])));
+}
+
+#[test]
+fn test_eval_stob_btos() {
+    let state = State {
+        stack: list!(list_cell![ AtomCell(Char('h')), AtomCell(Char('é')) ]),
+        env: Stack::empty(),
+        control: list!(InstCell(STOB)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&BytesCell(vec![0x68, 0xC3, 0xA9])));
+    let state = State {
+        stack: state.stack,
+        env: Stack::empty(),
+        control: list!(InstCell(BTOS)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(
+        state.stack.peek(),
+        Some(&list_cell![ AtomCell(Char('h')), AtomCell(Char('é')) ])
+    );
+}
+
+#[test]
+fn test_eval_btos_invalid_utf8() {
+    let result = State {
+        stack: list!(BytesCell(vec![0xFF, 0xFE])),
+        env: Stack::empty(),
+        control: list!(InstCell(BTOS)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_eval_readb() {
+    let state = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: list!(InstCell(READB)),
+        dump: Stack::empty(),
+    }.eval(Some(0xFF),true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(UInt(0xFF))));
+}
+
+#[test]
+fn test_eval_writeb() {
+    let (state, event) = State {
+        stack: list!(BytesCell(vec![0, 1, 0xFF])),
+        env: Stack::empty(),
+        control: list!(InstCell(WRITEB)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap();
+    assert_eq!(state.stack.peek(), None);
+    assert_eq!(event, Some(IOEvent::BufBytes(vec![0, 1, 0xFF])));
+    let mut out = Vec::new();
+    event.unwrap().write_to(&mut out).unwrap();
+    assert_eq!(out, vec![0, 1, 0xFF]);
+}
+
+#[test]
+fn test_eval_mput_mget() {
+    let state = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: list!(
+            InstCell(LDC), AtomCell(Char('b')),
+            InstCell(LDC), AtomCell(SInt(2)), InstCell(LDC), AtomCell(Char('b')),
+            InstCell(LDC), AtomCell(SInt(1)), InstCell(LDC), AtomCell(Char('a')),
+            InstCell(MNEW), InstCell(MPUT), InstCell(MPUT), InstCell(MGET)
+        ),
+        dump: Stack::empty(),
+    };
+    let state = (0..9).fold(state, |s, _| s.eval(None,true).unwrap().0);
+    assert_eq!(state.stack.peek(), Some(&AtomCell(SInt(2))));
+}
+
+#[test]
+fn test_eval_mget_missing() {
+    let result = State {
+        stack: list!(MapCell(BTreeMap::new()), AtomCell(UInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(MGET)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(result, Err(String::from("[fatal][MGET]: key 1u not found\n")));
+}
+
+#[test]
+fn test_eval_mput_string_key() {
+    let key = list_cell![ AtomCell(Char('x')), AtomCell(Char('y')) ];
+    let state = State {
+        stack: list!(MapCell(BTreeMap::new()), key.clone(), AtomCell(Float(1.5))),
+        env: Stack::empty(),
+        control: list!(InstCell(MPUT)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    let mut expected = BTreeMap::new();
+    expected.insert(MapKey::Str(String::from("xy")), AtomCell(Float(1.5)));
+    assert_eq!(state.stack.peek(), Some(&MapCell(expected)));
+    let result = State {
+        stack: list!(MapCell(BTreeMap::new()), VectorCell(Arc::new(vec![])), AtomCell(SInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(MPUT)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_eval_mhas_mdel() {
+    let mut map = BTreeMap::new();
+    map.insert(MapKey::Char('a'), AtomCell(SInt(1)));
+    let state = State {
+        stack: list!(MapCell(map.clone()), AtomCell(Char('a'))),
+        env: Stack::empty(),
+        control: list!(InstCell(MHAS)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&list_cell![AtomCell(SInt(1))]));
+    let state = State {
+        stack: list!(MapCell(map.clone()), AtomCell(Char('a'))),
+        env: Stack::empty(),
+        control: list!(InstCell(MDEL)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&MapCell(BTreeMap::new())));
+    let state = State {
+        stack: list!(MapCell(BTreeMap::new()), AtomCell(Char('a'))),
+        env: Stack::empty(),
+        control: list!(InstCell(MHAS)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&list_cell![]));
+}
+
+#[test]
+fn test_eval_mkeys_mlen() {
+    let mut map = BTreeMap::new();
+    map.insert(MapKey::UInt(3), list_cell![]);
+    map.insert(MapKey::UInt(1), list_cell![]);
+    let state = State {
+        stack: list!(MapCell(map.clone())),
+        env: Stack::empty(),
+        control: list!(InstCell(MKEYS)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(
+        state.stack.peek(),
+        Some(&list_cell![ AtomCell(UInt(1)), AtomCell(UInt(3)) ])
+    );
+    let state = State {
+        stack: list!(MapCell(map)),
+        env: Stack::empty(),
+        control: list!(InstCell(MLEN)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(UInt(2))));
+}
+
+#[test]
+fn test_eval_try() {
+    let state = State {
+        stack: list!(AtomCell(SInt(1))),
+        env: list!(list_cell![]),
+        control: list!(
+            InstCell(TRY),
+                list_cell![ InstCell(JOIN) ],
+                list_cell![ InstCell(LDC), AtomCell(SInt(2)), InstCell(ENDTRY) ],
+            InstCell(ADD)
+        ),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.control, list!(InstCell(LDC), AtomCell(SInt(2)), InstCell(ENDTRY)));
+    assert_eq!(state.dump.peek(), Some(&InstCell(TRY)));
+    assert_eq!(
+        state.dump.get(1),
+        Some(&list_cell![
+            list_cell![ InstCell(JOIN) ],
+            list_cell![ AtomCell(SInt(1)) ],
+            list_cell![ list_cell![] ],
+            list_cell![ InstCell(ADD) ]
+        ])
+    );
+}
+
+#[test]
+fn test_eval_endtry() {
+    let state = State {
+        stack: list!(AtomCell(SInt(2)), AtomCell(SInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(ENDTRY)),
+        dump: list!(
+            InstCell(TRY),
+            list_cell![
+                list_cell![ InstCell(JOIN) ],
+                list_cell![ AtomCell(SInt(1)) ],
+                list_cell![],
+                list_cell![ InstCell(ADD) ]
+            ]
+        ),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack, list!(AtomCell(SInt(2)), AtomCell(SInt(1))));
+    assert_eq!(state.control, list!(InstCell(ADD)));
+    assert_eq!(state.dump, Stack::empty());
+}
+
+#[test]
+fn test_eval_throw() {
+    let state = State {
+        stack: list!(AtomCell(Char('x')), AtomCell(SInt(3))),
+        env: list!(list_cell![ AtomCell(SInt(4)) ]),
+        control: list!(InstCell(THROW), InstCell(NIL)),
+        dump: list!(
+            // a frame from AP, which THROW should unwind past
+            list_cell![], list_cell![], list_cell![],
+            InstCell(TRY),
+            list_cell![
+                list_cell![ InstCell(JOIN) ],
+                list_cell![ AtomCell(SInt(1)) ],
+                list_cell![],
+                list_cell![ InstCell(ADD) ]
+            ]
+        ),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack, list!(AtomCell(Char('x')), AtomCell(SInt(1))));
+    assert_eq!(state.env, Stack::empty());
+    assert_eq!(state.control, list!(InstCell(JOIN)));
+    assert_eq!(state.dump, list!(list_cell![ InstCell(ADD) ]));
+}
+
+#[test]
+fn test_eval_throw_uncaught() {
+    let result = State {
+        stack: list!(AtomCell(Char('x'))),
+        env: Stack::empty(),
+        control: list!(InstCell(THROW)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(result, Err(String::from("[fatal][THROW]: uncaught condition 'x'\n")));
+}
+
+#[test]
+fn test_eval_error_caught() {
+    let state = State {
+        stack: list!(AtomCell(SInt(1)), AtomCell(SInt(0))),
+        env: Stack::empty(),
+        control: list!(InstCell(DIV)),
+        dump: list!(
+            InstCell(TRY),
+            list_cell![
+                list_cell![ InstCell(JOIN) ],
+                list_cell![],
+                list_cell![],
+                list_cell![]
+            ]
+        ),
+    }.eval(None,true).unwrap().0;
+    let condition = "[fatal][DIV]: attempted to divide 1 by zero".chars()
+        .map(|ch| AtomCell(Char(ch)))
+        .collect::<::slist::List<_>>();
+    assert_eq!(state.stack, list!(ListCell(Box::new(condition))));
+    assert_eq!(state.control, list!(InstCell(JOIN)));
+}
+
+#[test]
+fn test_eval_eq_type_error_caught() {
+    let state = State {
+        stack: list!(AtomCell(SInt(1)), list_cell![]),
+        env: Stack::empty(),
+        control: list!(InstCell(EQ)),
+        dump: list!(
+            InstCell(TRY),
+            list_cell![
+                list_cell![ InstCell(JOIN) ],
+                list_cell![],
+                list_cell![],
+                list_cell![]
+            ]
+        ),
+    }.eval(None,true).unwrap().0;
+    let condition = "[fatal][EQ]: expected second operand, found Some((nil, nil))"
+        .chars()
+        .map(|ch| AtomCell(Char(ch)))
+        .collect::<::slist::List<_>>();
+    assert_eq!(state.stack, list!(ListCell(Box::new(condition))));
+    assert_eq!(state.control, list!(InstCell(JOIN)));
+}
+
+#[test]
+fn test_eval_ap_empty_stack_caught() {
+    let state = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: list!(InstCell(AP)),
+        dump: list!(
+            InstCell(TRY),
+            list_cell![
+                list_cell![ InstCell(JOIN) ],
+                list_cell![],
+                list_cell![],
+                list_cell![]
+            ]
+        ),
+    }.eval(None,true).unwrap().0;
+    let condition = "[fatal][AP]: expected closure on stack, found nothing".chars()
+        .map(|ch| AtomCell(Char(ch)))
+        .collect::<::slist::List<_>>();
+    assert_eq!(state.stack, list!(ListCell(Box::new(condition))));
+    assert_eq!(state.control, list!(InstCell(JOIN)));
+}
+
+#[test]
+fn test_eval_ret_empty_stack() {
+    let result = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: list!(InstCell(RET)),
+        dump: list!(list_cell![], list_cell![], list_cell![]),
+    }.eval(None,false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][RET]: expected return value on stack, found nothing
+"))
+    );
+}
+
+#[test]
+fn test_eval_ld_bad_index() {
+    let result = State {
+        stack: Stack::empty(),
+        env: list!(list_cell![ AtomCell(SInt(1)) ]),
+        control: list!(InstCell(LD), list_cell![ AtomCell(UInt(1)), AtomCell(UInt(2)) ]),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(result, Err(String::from("[fatal][LD]: no variable at (1, 2) in $e\n")));
+    let result = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: list!(InstCell(LD), list_cell![ AtomCell(UInt(0)), AtomCell(UInt(1)) ]),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(result, Err(String::from("[fatal][LD]: no level 0 in $e\n")));
+}
+
+#[test]
+fn test_eval_box() {
+    let state = State {
+        stack: list!(AtomCell(SInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(BOX), InstCell(UNBOX)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&BoxCell(SharedCell::new(AtomCell(SInt(1))))));
+    let state = state.eval(None,true).unwrap().0;
+    assert_eq!(state.stack.peek(), Some(&AtomCell(SInt(1))));
+}
+
+#[test]
+fn test_eval_setbox() {
+    let shared = SharedCell::new(AtomCell(SInt(1)));
+    let state = State {
+        stack: list!(BoxCell(shared.clone()), AtomCell(SInt(2)), AtomCell(SInt(3))),
+        env: Stack::empty(),
+        control: list!(InstCell(SETBOX)),
+        dump: Stack::empty(),
+    }.eval(None,true).unwrap().0;
+    assert_eq!(state.stack, list!(AtomCell(SInt(3))));
+    // the new contents are visible through every copy of the box
+    assert_eq!(shared.get(), AtomCell(SInt(2)));
+}
+
+#[test]
+fn test_eval_unbox_not_a_box() {
+    let result = State {
+        stack: list!(AtomCell(SInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(UNBOX)),
+        dump: Stack::empty(),
+    }.eval(None,false);
+    assert_eq!(result, Err(String::from("[fatal][UNBOX]: expected box, found Some((1, nil))\n")));
+}
+
+#[test]
+fn test_eval_retn() {
+    let state = State {
+        stack: list!(AtomCell(SInt(1)), AtomCell(SInt(2)), AtomCell(SInt(3))),
+        env: list!(list_cell![ AtomCell(SInt(4)) ]),
+        control: list!(InstCell(RETN), AtomCell(UInt(2))),
+        dump: list!(
+            list_cell![ AtomCell(Char('S')) ],
+            list_cell![ list_cell![ AtomCell(Char('E')) ] ],
+            list_cell![ InstCell(RECVN), AtomCell(UInt(2)) ]
+        )
+    }.eval(None, true).unwrap().0;
+    assert_eq!(
+        state.stack,
+        list!(AtomCell(UInt(2)), AtomCell(SInt(1)), AtomCell(SInt(2)), AtomCell(Char('S')))
+    );
+    assert_eq!(state.env, list!(list_cell![ AtomCell(Char('E')) ]));
+    assert_eq!(state.dump, Stack::empty());
+    let state = state.eval(None, true).unwrap().0;
+    assert_eq!(
+        state.stack,
+        list!(AtomCell(SInt(1)), AtomCell(SInt(2)), AtomCell(Char('S')))
+    );
+}
+
+#[test]
+fn test_eval_retn_too_few_values() {
+    let result = State {
+        stack: list!(AtomCell(SInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(RETN), AtomCell(UInt(2))),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(result, Err(String::from("[fatal][RETN]: expected 2 values, found 1\n")));
+}
+
+#[test]
+fn test_eval_recvn_wrong_count() {
+    let result = State {
+        stack: list!(AtomCell(UInt(1)), AtomCell(SInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(RECVN), AtomCell(UInt(2))),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(result, Err(String::from("[fatal][RECVN]: expected 2 values, received 1\n")));
+}
+
+#[test]
+fn test_eval_ldf_arity() {
+    let state = State {
+        stack: Stack::empty(),
+        env: list!(list_cell![ AtomCell(SInt(155)) ]),
+        control: list!(
+            InstCell(LDF),
+            list_cell![ AtomCell(UInt(1)), AtomCell(UInt(0)), AtomCell(UInt(1)) ],
+            list_cell![ InstCell(RET) ]
+        ),
+        dump: Stack::empty()
+    }.eval(None, true).unwrap().0;
+    assert_eq!(
+        state.stack.peek(),
+        Some(&list_cell![
+            list_cell![ InstCell(RET) ],
+            list_cell![ AtomCell(SInt(155)) ],
+            list_cell![ AtomCell(UInt(1)), AtomCell(UInt(0)), AtomCell(UInt(1)) ]
+        ])
+    );
+    assert_eq!(state.control, Stack::empty());
+    let result = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: list!(
+            InstCell(LDF),
+            list_cell![ AtomCell(UInt(1)), AtomCell(UInt(0)) ],
+            list_cell![ InstCell(RET) ]
+        ),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][LDF]: malformed arity (1u . (0u . nil))\n"))
+    );
+}
+
+/// Builds a closure with the given arity and an empty body and environment
+fn closure_with_arity(required: u64, optional: u64, rest: u64) -> SVMCell {
+    list_cell![
+        list_cell![ InstCell(RET) ],
+        list_cell![],
+        list_cell![ AtomCell(UInt(required)), AtomCell(UInt(optional)), AtomCell(UInt(rest)) ]
+    ]
+}
+
+#[test]
+fn test_eval_ap_arity_mismatch() {
+    let result = State {
+        stack: list!(closure_with_arity(2, 0, 0), list_cell![ AtomCell(SInt(1)) ]),
+        env: Stack::empty(),
+        control: list!(InstCell(AP)),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][AP]: arity mismatch, expected 2 arguments, found 1\n"))
+    );
+    let result = State {
+        stack: list!(
+            closure_with_arity(0, 1, 0),
+            list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)) ]
+        ),
+        env: Stack::empty(),
+        control: list!(InstCell(AP)),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][AP]: arity mismatch, expected 0 to 1 arguments, found 2\n"))
+    );
+}
+
+#[test]
+fn test_eval_ap_optional_args() {
+    let state = State {
+        stack: list!(closure_with_arity(1, 2, 0), list_cell![ AtomCell(SInt(1)) ]),
+        env: Stack::empty(),
+        control: list!(InstCell(AP)),
+        dump: Stack::empty()
+    }.eval(None, true).unwrap().0;
+    assert_eq!(
+        state.env,
+        list!(list_cell![ AtomCell(SInt(1)), list_cell![], list_cell![] ])
+    );
+}
+
+#[test]
+fn test_eval_ap_rest_args() {
+    let state = State {
+        stack: list!(
+            closure_with_arity(1, 0, 1),
+            list_cell![ AtomCell(SInt(1)), AtomCell(SInt(2)), AtomCell(SInt(3)) ]
+        ),
+        env: Stack::empty(),
+        control: list!(InstCell(AP)),
+        dump: Stack::empty()
+    }.eval(None, true).unwrap().0;
+    assert_eq!(
+        state.env,
+        list!(list_cell![
+            AtomCell(SInt(1)),
+            list_cell![ AtomCell(SInt(2)), AtomCell(SInt(3)) ]
+        ])
+    );
+    let result = State {
+        stack: list!(closure_with_arity(1, 0, 1), list_cell![]),
+        env: Stack::empty(),
+        control: list!(InstCell(AP)),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][AP]: arity mismatch, expected at least 1 arguments, found 0\n"))
+    );
+}
+
+#[test]
+fn test_eval_rap_arity_mismatch() {
+    let result = State {
+        stack: list!(closure_with_arity(1, 0, 0), list_cell![]),
+        env: list!(list_cell![]),
+        control: list!(InstCell(RAP)),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(
+        result,
+        Err(String::from("[fatal][RAP]: arity mismatch, expected 1 arguments, found 0\n"))
+    );
+}
+
+#[test]
+fn test_eval_send_recv() {
+    let state = State {
+        stack: Stack::empty(),
+        env: Stack::empty(),
+        control: list!(InstCell(CHAN)),
+        dump: Stack::empty()
+    }.eval(None, true).unwrap().0;
+    let channel = match state.stack.peek() {
+        Some(&ChannelCell(ref channel)) => channel.clone(),
+        thing => panic!("expected channel, found {:?}", thing)
+    };
+    assert!(channel.is_empty());
+    let state = State {
+        stack: list!(ChannelCell(channel.clone()), AtomCell(SInt(1))),
+        env: Stack::empty(),
+        control: list!(InstCell(SEND)),
+        dump: Stack::empty()
+    }.eval(None, true).unwrap().0;
+    assert_eq!(state.stack, Stack::empty());
+    assert_eq!(channel.pending(), vec![AtomCell(SInt(1))]);
+    let state = State {
+        stack: list!(ChannelCell(channel.clone())),
+        env: Stack::empty(),
+        control: list!(InstCell(RECV)),
+        dump: Stack::empty()
+    }.eval(None, true).unwrap().0;
+    assert_eq!(state.stack, list!(AtomCell(SInt(1))));
+    assert!(channel.is_empty());
+}
+
+#[test]
+fn test_eval_recv_empty() {
+    let result = State {
+        stack: list!(ChannelCell(Channel::new())),
+        env: Stack::empty(),
+        control: list!(InstCell(RECV)),
+        dump: Stack::empty()
+    }.eval(None, false);
+    assert_eq!(result, Err(String::from("[fatal][RECV]: channel is empty\n")));
+}
+
 #[bench]
 fn bench_list_creation(b: &mut Bencher) {
     b.iter(|| {
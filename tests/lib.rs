@@ -141,3 +141,277 @@ fn test_basic_branching() {
     );
 }
 
+
+/// Test for vector construction and indexing
+///
+/// ```lisp
+/// (vector-ref (list->vector (cons 10 (cons 20 nil))) 1)
+/// ```
+#[test]
+fn test_vector_ref() {
+    assert_eq!(
+        svm::eval_program(list!(
+            InstCell(LDC), AtomCell(UInt(1)),
+            InstCell(NIL),
+            InstCell(LDC), AtomCell(SInt(20)), InstCell(CONS),
+            InstCell(LDC), AtomCell(SInt(10)), InstCell(CONS),
+            InstCell(LTOV),
+            InstCell(VGET)
+        ), true).unwrap().peek(),
+        Some(&AtomCell(SInt(20)))
+    );
+}
+
+/// Test for catching an error raised by the VM
+///
+/// ```lisp
+/// (try (/ 1 0) (lambda (e) e))
+/// ```
+#[test]
+fn test_try_catch_error() {
+    let condition = "[fatal][DIV]: attempted to divide 1 by zero".chars()
+        .map(|ch| AtomCell(Char(ch)))
+        .collect::<svm::slist::List<_>>();
+    assert_eq!(
+        svm::eval_program(list!(
+            InstCell(TRY),
+                ListCell(box list!(InstCell(JOIN))),
+                ListCell(box list!(
+                    InstCell(LDC), AtomCell(SInt(0)),
+                    InstCell(LDC), AtomCell(SInt(1)),
+                    InstCell(DIV),
+                    InstCell(ENDTRY)
+                ))
+        ), true).unwrap().peek(),
+        Some(&ListCell(Box::new(condition)))
+    );
+}
+
+/// Test for throwing a value out of a function call
+///
+/// ```lisp
+/// (+ 1 (try ((lambda () (throw 5))) (lambda (e) e)))
+/// ```
+#[test]
+fn test_try_throw_from_function() {
+    assert_eq!(
+        svm::eval_program(list!(
+            InstCell(LDC), AtomCell(SInt(1)),
+            InstCell(TRY),
+                ListCell(box list!(InstCell(JOIN))),
+                ListCell(box list!(
+                    InstCell(NIL),
+                    InstCell(LDF), ListCell(box list!(
+                        InstCell(LDC), AtomCell(SInt(5)),
+                        InstCell(THROW),
+                        InstCell(RET)
+                    )),
+                    InstCell(AP),
+                    InstCell(ENDTRY)
+                )),
+            InstCell(ADD)
+        ), true).unwrap().peek(),
+        Some(&AtomCell(SInt(6)))
+    );
+}
+
+/// Test for catching an error raised inside a function call
+///
+/// ```lisp
+/// (try ((lambda () (/ 1 0))) (lambda (e) e))
+/// ```
+#[test]
+fn test_try_catch_error_in_function() {
+    let condition = "[fatal][DIV]: attempted to divide 1 by zero".chars()
+        .map(|ch| AtomCell(Char(ch)))
+        .collect::<svm::slist::List<_>>();
+    assert_eq!(
+        svm::eval_program(list!(
+            InstCell(TRY),
+                ListCell(box list!(InstCell(JOIN))),
+                ListCell(box list!(
+                    InstCell(NIL),
+                    InstCell(LDF), ListCell(box list!(
+                        InstCell(LDC), AtomCell(SInt(0)),
+                        InstCell(LDC), AtomCell(SInt(1)),
+                        InstCell(DIV),
+                        InstCell(RET)
+                    )),
+                    InstCell(AP),
+                    InstCell(ENDTRY)
+                ))
+        ), true).unwrap().peek(),
+        Some(&ListCell(Box::new(condition)))
+    );
+}
+
+/// Test for a `try` body which completes normally
+///
+/// ```lisp
+/// (+ 1 (try 2 (lambda (e) 3)))
+/// ```
+#[test]
+fn test_try_no_throw() {
+    assert_eq!(
+        svm::eval_program(list!(
+            InstCell(LDC), AtomCell(SInt(1)),
+            InstCell(TRY),
+                ListCell(box list!(
+                    InstCell(LDC), AtomCell(SInt(3)),
+                    InstCell(JOIN)
+                )),
+                ListCell(box list!(
+                    InstCell(LDC), AtomCell(SInt(2)),
+                    InstCell(ENDTRY)
+                )),
+            InstCell(ADD)
+        ), true).unwrap().peek(),
+        Some(&AtomCell(SInt(3)))
+    );
+}
+
+/// Test for an uncaught `throw`
+#[test]
+fn test_throw_uncaught() {
+    assert!(
+        svm::eval_program(list!(
+            InstCell(LDC), AtomCell(SInt(5)),
+            InstCell(THROW)
+        ), false).is_err()
+    );
+}
+
+/// Test for a non-tail call, whose result is used after it returns
+///
+/// ```lisp
+/// (+ ((lambda (x) (+ x 1)) 1) 2)
+/// ```
+#[test]
+fn test_non_tail_call() {
+    assert_eq!(
+        svm::eval_program(list!(
+            InstCell(NIL),
+            InstCell(LDC), AtomCell(SInt(1)), InstCell(CONS),
+            InstCell(LDF),
+                ListCell(box list!(
+                    InstCell(LD), ListCell(box list!(AtomCell(UInt(1)), AtomCell(UInt(1)))),
+                    InstCell(LDC), AtomCell(SInt(1)),
+                    InstCell(ADD),
+                    InstCell(RET)
+                )),
+            InstCell(AP),
+            InstCell(LDC), AtomCell(SInt(2)),
+            InstCell(ADD)
+        ), true).unwrap().peek(),
+        Some(&AtomCell(SInt(4)))
+    );
+}
+
+/// Test for a counter closure, which keeps its count in a box
+#[test]
+fn test_counter_closure() {
+    assert_eq!(
+        svm::eval_program(list!(
+            InstCell(NIL),
+            InstCell(NIL),
+            InstCell(NIL),
+            InstCell(LDC), AtomCell(SInt(0)), InstCell(BOX), InstCell(CONS),
+            InstCell(CONS),
+            // (lambda (n) (lambda () (set-box! n (+ (unbox n) 1)) (unbox n)))
+            InstCell(LDF),
+                ListCell(box list!(
+                    InstCell(LDF),
+                    ListCell(box list!(
+                        InstCell(LDC), AtomCell(SInt(1)),
+                        InstCell(LD), ListCell(box list!(AtomCell(UInt(2)), AtomCell(UInt(1)))),
+                        InstCell(UNBOX),
+                        InstCell(ADD),
+                        InstCell(LD), ListCell(box list!(AtomCell(UInt(2)), AtomCell(UInt(1)))),
+                        InstCell(SETBOX),
+                        InstCell(LD), ListCell(box list!(AtomCell(UInt(2)), AtomCell(UInt(1)))),
+                        InstCell(UNBOX),
+                        InstCell(RET)
+                    )),
+                    InstCell(RET)
+                )),
+            InstCell(AP),
+            InstCell(CONS),
+            // (lambda (counter) (list (counter) (counter)))
+            InstCell(LDF),
+                ListCell(box list!(
+                    InstCell(NIL),
+                    InstCell(NIL),
+                    InstCell(LD), ListCell(box list!(AtomCell(UInt(1)), AtomCell(UInt(1)))),
+                    InstCell(AP),
+                    InstCell(CONS),
+                    InstCell(NIL),
+                    InstCell(LD), ListCell(box list!(AtomCell(UInt(1)), AtomCell(UInt(1)))),
+                    InstCell(AP),
+                    InstCell(CONS),
+                    InstCell(RET)
+                )),
+            InstCell(AP)
+        ), true).unwrap().peek(),
+        Some(&ListCell(box list!(AtomCell(SInt(2)), AtomCell(SInt(1)))))
+    );
+}
+
+/// Test for returning multiple values with `RETN`
+#[test]
+fn test_multiple_values() {
+    assert_eq!(
+        svm::eval_program(list!(
+            // (call-with-values (lambda () (values 10 3)) -)
+            InstCell(NIL),
+            InstCell(LDF),
+                ListCell(box list!(
+                    InstCell(LDC), AtomCell(SInt(3)),
+                    InstCell(LDC), AtomCell(SInt(10)),
+                    InstCell(RETN), AtomCell(UInt(2))
+                )),
+            InstCell(AP),
+            InstCell(RECVN), AtomCell(UInt(2)),
+            InstCell(SUB)
+        ), true).unwrap().peek(),
+        Some(&AtomCell(SInt(7)))
+    );
+}
+
+/// Test for a variadic function, `(lambda (x . rest) (cons x rest))`
+#[test]
+fn test_rest_args() {
+    assert_eq!(
+        svm::eval_program(list!(
+            InstCell(NIL),
+            InstCell(LDC), AtomCell(SInt(3)), InstCell(CONS),
+            InstCell(LDC), AtomCell(SInt(2)), InstCell(CONS),
+            InstCell(LDC), AtomCell(SInt(1)), InstCell(CONS),
+            InstCell(LDF),
+                ListCell(box list!(AtomCell(UInt(1)), AtomCell(UInt(0)), AtomCell(UInt(1)))),
+                ListCell(box list!(
+                    InstCell(LD), ListCell(box list!(AtomCell(UInt(1)), AtomCell(UInt(2)))),
+                    InstCell(LD), ListCell(box list!(AtomCell(UInt(1)), AtomCell(UInt(1)))),
+                    InstCell(CONS),
+                    InstCell(RET)
+                )),
+            InstCell(AP)
+        ), true).unwrap().peek(),
+        Some(&ListCell(box list!(AtomCell(SInt(1)), AtomCell(SInt(2)), AtomCell(SInt(3)))))
+    );
+}
+
+/// Test that a corrupted file is rejected with an integrity error, which
+/// callers can tell apart from other decoding errors.
+#[test]
+fn test_decode_corrupted_program() {
+    use std::io::Cursor;
+    use svm::bytecode::{encode_program,decode_program,INTEGRITY_ERROR};
+
+    let mut encoded = Vec::new();
+    encode_program(&list!(InstCell(NIL), InstCell(LDC), AtomCell(SInt(1))), &mut encoded)
+        .unwrap();
+    let last = encoded.len() - 1;
+    encoded[last] ^= 0x40;
+    assert!(decode_program(&mut Cursor::new(encoded)).unwrap_err()
+        .starts_with(INTEGRITY_ERROR));
+}